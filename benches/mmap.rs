@@ -0,0 +1,52 @@
+//! Compares analyzing a large file read into memory against analyzing it in
+//! place through a memory mapping, as `--mmap` does, and against running the
+//! byte-oriented detector over the mapping, which `--mmap` does not use.
+//!
+//! ```text
+//! cargo bench --bench mmap
+//! ```
+
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use method_chains::ChainConfig;
+
+const ITERATIONS: u32 = 20;
+
+fn time<F: FnMut() -> Vec<usize>>(name: &str, mut f: F) -> Vec<usize> {
+    let mut result = f();
+    let mut total = Duration::ZERO;
+    for _ in 0..ITERATIONS {
+        let start = Instant::now();
+        result = f();
+        total += start.elapsed();
+    }
+    println!("{:>6}: {:?} per iteration", name, total / ITERATIONS);
+    result
+}
+
+fn write_large_file(path: &Path) {
+    let method = "    void f() {\n        list.stream().map(x -> x.foo()).filter(y -> y.bar()).collect(toList());\n        // a().b().c();\n        builder.a(1).b(\"c\").build();\n    }\n";
+    let mut contents = String::from("class Large {\n");
+    while contents.len() < 16 * 1024 * 1024 {
+        contents.push_str(method);
+    }
+    contents.push_str("}\n");
+    std::fs::write(path, contents).unwrap();
+}
+
+fn main() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("Large.java");
+    write_large_file(&path);
+
+    let config = ChainConfig::default();
+    let read = time("read", || method_chains::analyze_path_with(&path, 1000, &config).unwrap());
+    let mapped = time("mmap", || method_chains::analyze_path_mmap_with(&path, 1000, &config).unwrap());
+    assert_eq!(read, mapped);
+    let bytes = time("bytes", || {
+        let contents = method_chains::SourceBytes::map(&path).unwrap();
+        method_chains::method_chain_counts_bytes_with(&contents, 1000, &config).unwrap()
+    });
+    assert_eq!(read, bytes);
+}
@@ -0,0 +1,22 @@
+//! Feeds arbitrary bytes, lossily decoded, through comment removal, the
+//! tokenizer, and chain detection.
+//!
+//! ```text
+//! cargo +nightly fuzz run tokenize
+//! ```
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use method_chains::MethodChaining;
+
+fuzz_target!(|data: &[u8]| {
+    let contents = String::from_utf8_lossy(data);
+
+    let clean = method_chains::remove_comments(&contents);
+    assert!(clean.len() <= contents.len(), "comment removal made the input longer");
+
+    method_chains::tokenize(&clean);
+    // Deep nesting is reported as an error, not a panic.
+    let _ = contents.method_chain_counts(1000);
+});
@@ -0,0 +1,116 @@
+//! How far the detector is from hand-labeled chains, to measure the effect
+//! of changes to its heuristics.
+
+use alloc::collections::BTreeMap;
+
+use crate::MethodChaining;
+
+/// The difference between the chain lengths the detector finds in a source
+/// and the expected ones, compared as multisets: a chain of length 3 detected
+/// as two chains of lengths 1 and 2 is one missing chain and two extra ones.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Discrepancy {
+    /// Chains detected beyond the expected ones, by length: the number of
+    /// false positives.
+    pub overcounted: BTreeMap<usize, usize>,
+    /// Expected chains the detector did not find, by length: the number of
+    /// false negatives.
+    pub undercounted: BTreeMap<usize, usize>,
+}
+
+impl Discrepancy {
+    pub fn is_exact(&self) -> bool {
+        self.overcounted.is_empty() && self.undercounted.is_empty()
+    }
+
+    pub fn overcount(&self) -> usize {
+        self.overcounted.values().sum()
+    }
+
+    pub fn undercount(&self) -> usize {
+        self.undercounted.values().sum()
+    }
+
+    /// Adds the discrepancy of another source, to report on a whole corpus.
+    pub fn merge(&mut self, other: &Discrepancy) {
+        for (length, count) in &other.overcounted {
+            *self.overcounted.entry(*length).or_insert(0) += count;
+        }
+        for (length, count) in &other.undercounted {
+            *self.undercounted.entry(*length).or_insert(0) += count;
+        }
+    }
+}
+
+/// Detects the chains of `source` and compares their lengths to `expected`,
+/// in any order.
+pub fn compare_against(expected: &[usize], source: &str, max_depth: usize) -> anyhow::Result<Discrepancy> {
+    let mut balance: BTreeMap<usize, i64> = BTreeMap::new();
+    for length in source.method_chain_counts(max_depth)? {
+        *balance.entry(length).or_insert(0) += 1;
+    }
+    for length in expected {
+        *balance.entry(*length).or_insert(0) -= 1;
+    }
+    let mut discrepancy = Discrepancy::default();
+    for (length, difference) in balance {
+        if difference > 0 {
+            discrepancy.overcounted.insert(length, difference as usize);
+        } else if difference < 0 {
+            discrepancy.undercounted.insert(length, -difference as usize);
+        }
+    }
+    Ok(discrepancy)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The lengths listed on the first line of a labeled file, like
+    // `// expected: 3, 1`.
+    fn expected_lengths(source: &str) -> Vec<usize> {
+        let header = source.lines().next().and_then(|line| line.strip_prefix("// expected:"))
+            .expect("labeled file starts with the expected lengths");
+        header.split(',').map(|length| length.trim().parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn test_compare_against() {
+        assert!(compare_against(&[2, 1], "a().b(); c.d();", 10).unwrap().is_exact());
+        let discrepancy = compare_against(&[3, 3, 1], "a().b(); c.d(); e.f();", 10).unwrap();
+        assert_eq!(discrepancy, Discrepancy {
+            overcounted: BTreeMap::from([(1, 1), (2, 1)]),
+            undercounted: BTreeMap::from([(3, 2)]),
+        });
+        assert_eq!((discrepancy.overcount(), discrepancy.undercount()), (2, 2));
+    }
+
+    #[test]
+    fn test_labeled_corpus() {
+        let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data/labeled");
+        let mut paths = std::fs::read_dir(&dir).unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect::<Vec<_>>();
+        paths.sort();
+
+        let mut total = Discrepancy::default();
+        let mut expected_chains = 0;
+        let mut summary = String::new();
+        for path in &paths {
+            let source = std::fs::read_to_string(path).unwrap();
+            let expected = expected_lengths(&source);
+            let discrepancy = compare_against(&expected, &source, 100).unwrap();
+            summary += &format!("{}: {} expected, {} over, {} under\n", path.file_name().unwrap().to_string_lossy(),
+                                expected.len(), discrepancy.overcount(), discrepancy.undercount());
+            expected_chains += expected.len();
+            total.merge(&discrepancy);
+        }
+
+        // The only known error: after a generic return type, the declaration
+        // of `names` in Streams.java looks like a call.
+        assert_eq!(paths.len(), 3);
+        assert_eq!(expected_chains, 11);
+        assert_eq!(total, Discrepancy { overcounted: BTreeMap::from([(1, 1)]), undercounted: BTreeMap::new() }, "\n{}", summary);
+    }
+}
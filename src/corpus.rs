@@ -0,0 +1,1301 @@
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+
+use anyhow::Context;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use rand::{Rng, SeedableRng};
+use rand::seq::SliceRandom;
+
+use crate::{AssertionRoots, ChainConfig, ChainSpan, MethodChaining, RootKind, SourceBytes};
+use crate::histogram;
+
+/// A directory of projects to analyze. Every subdirectory of the root is a
+/// project; every file under a project with a matching extension is analyzed.
+///
+/// ```no_run
+/// use method_chains::corpus::Corpus;
+///
+/// let report = Corpus::new("projects")
+///     .extensions(["java"])
+///     .exclude(["**/test/**"])
+///     .jobs(8)
+///     .run()
+///     .unwrap();
+/// ```
+#[derive(Clone, Debug)]
+pub struct Corpus {
+    root: PathBuf,
+    extensions: Vec<String>,
+    exclude: Vec<String>,
+    jobs: usize,
+    max_depth: usize,
+    chain_config: ChainConfig,
+    mmap: bool,
+    by_package: bool,
+    report_over: Option<usize>,
+    /// The probability of analyzing each file, and the seed choosing them.
+    sample: Option<(f64, u64)>,
+    count_anonymous_classes: bool,
+    assertion_roots: Option<AssertionRoots>,
+    /// Projects to analyze instead of the subdirectories of the root.
+    project_dirs: Option<Vec<PathBuf>>,
+    require_balanced: bool,
+    by_extension: bool,
+    chain_length_cap: Option<usize>,
+    by_root_kind: bool,
+    nesting_depth: bool,
+    limit_files: Option<usize>,
+    sanity_max: Option<usize>,
+    min_files: Option<usize>,
+    /// A zip archive to read the projects from instead of the root.
+    archive: Option<PathBuf>,
+    max_in_flight: Option<usize>,
+    stop: Option<Arc<AtomicBool>>,
+    /// The seed shuffling the projects.
+    shuffle: Option<u64>,
+    shard: Option<Shard>,
+}
+
+/// One of `total` disjoint parts of a corpus, numbered from 0, for splitting
+/// it among workers: see [`Corpus::shard`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Shard {
+    pub index: usize,
+    pub total: usize,
+}
+
+impl Shard {
+    /// Whether the project named `name` belongs to the shard, going by a hash
+    /// of the name only, so that the other projects do not matter and every
+    /// worker agrees.
+    pub fn contains(&self, name: &str) -> bool {
+        stable_hash(name) % self.total as u64 == self.index as u64
+    }
+}
+
+// FNV-1a, which unlike the standard hasher is the same everywhere.
+pub(crate) fn stable_hash(text: &str) -> u64 {
+    text.bytes().fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3))
+}
+
+/// Chain statistics for a single project.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProjectReport {
+    pub name: String,
+    pub path: PathBuf,
+    /// Number of files analyzed.
+    pub files: usize,
+    /// Total number of lines in the analyzed files.
+    pub lines: usize,
+    /// Total size of the analyzed files.
+    pub bytes: u64,
+    pub histogram: BTreeMap<usize, usize>,
+    /// The chain lengths of [`ProjectReport::histogram`] in the order they
+    /// were first found, going through the files in path order.
+    pub first_appearances: Vec<usize>,
+    /// The histogram split by the package declared in each file, with files
+    /// without a declaration under [`DEFAULT_PACKAGE`]. Only filled in with
+    /// [`Corpus::by_package`].
+    pub packages: BTreeMap<String, BTreeMap<usize, usize>>,
+    /// The histogram split by the lower case extension of each file. Only
+    /// filled in with [`Corpus::by_extension`].
+    pub extensions: BTreeMap<String, BTreeMap<usize, usize>>,
+    /// The histogram split by what the chains are called on. Only filled in
+    /// with [`Corpus::by_root_kind`].
+    pub root_kinds: BTreeMap<RootKind, BTreeMap<usize, usize>>,
+    /// The chains longer than the [`Corpus::report_over`] threshold, by file
+    /// and line.
+    pub long_chains: Vec<LongChain>,
+    /// The chains in the bodies of anonymous classes, which are then left
+    /// out of [`ProjectReport::histogram`]. Only filled in with
+    /// [`Corpus::count_anonymous_classes`].
+    pub anonymous_histogram: BTreeMap<usize, usize>,
+    /// The fluent assertion chains (see [`crate::Chain::is_assertion`]), which are
+    /// then left out of [`ProjectReport::histogram`]. Only filled in with
+    /// [`Corpus::assertion_chains`].
+    pub assertion_histogram: BTreeMap<usize, usize>,
+    /// The deepest nesting of parentheses, brackets, and braces in any of the
+    /// files. Only found with [`Corpus::nesting_depth`].
+    pub max_nesting_depth: usize,
+    /// The file, relative to the project, with the longest chain of
+    /// [`ProjectReport::histogram`]: the first in path order if several have
+    /// one that long, and none if there are no chains.
+    pub longest_chain_file: Option<PathBuf>,
+}
+
+impl ProjectReport {
+    /// The number of chains in [`ProjectReport::histogram`] per thousand
+    /// lines, for comparing projects of different sizes. A project without
+    /// lines has a density of 0.
+    pub fn chains_per_kloc(&self) -> f64 {
+        if self.lines == 0 {
+            return 0.0;
+        }
+        let chains: usize = self.histogram.values().sum();
+        chains as f64 * 1000.0 / self.lines as f64
+    }
+}
+
+/// A chain longer than the [`Corpus::report_over`] threshold.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LongChain {
+    /// The file, relative to the project directory.
+    pub file: PathBuf,
+    pub start_line: usize,
+    pub length: usize,
+}
+
+/// Chain lengths found in a single file.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FileReport {
+    pub path: PathBuf,
+    pub chain_counts: Vec<usize>,
+    /// Number of lines: newlines, plus one for a last line without a newline.
+    pub lines: usize,
+    pub bytes: u64,
+    /// The declared package, only looked for with [`Corpus::by_package`].
+    pub package: Option<String>,
+    /// Chains longer than the [`Corpus::report_over`] threshold.
+    pub long_chains: Vec<ChainSpan>,
+    /// Lengths of the chains by what they are called on, only looked for with
+    /// [`Corpus::by_root_kind`].
+    pub root_kind_counts: BTreeMap<RootKind, Vec<usize>>,
+    /// Lengths of the chains in anonymous classes, only split off from
+    /// [`FileReport::chain_counts`] with [`Corpus::count_anonymous_classes`].
+    pub anonymous_chain_counts: Vec<usize>,
+    /// Lengths of the assertion chains, only split off from
+    /// [`FileReport::chain_counts`] with [`Corpus::assertion_chains`].
+    pub assertion_chain_counts: Vec<usize>,
+    /// Only found with [`Corpus::nesting_depth`], see
+    /// [`crate::MethodChaining::max_nesting_depth`].
+    pub max_nesting_depth: usize,
+}
+
+/// Package name reported for files that do not declare a package.
+pub const DEFAULT_PACKAGE: &str = "(default)";
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CorpusReport {
+    pub projects: Vec<ProjectReport>,
+}
+
+impl Corpus {
+    pub fn new<P: Into<PathBuf>>(root: P) -> Self {
+        Corpus {
+            root: root.into(),
+            extensions: vec!["java".to_owned()],
+            exclude: Vec::new(),
+            jobs: 1,
+            max_depth: 1000,
+            chain_config: ChainConfig::default(),
+            mmap: false,
+            by_package: false,
+            report_over: None,
+            sample: None,
+            count_anonymous_classes: false,
+            assertion_roots: None,
+            project_dirs: None,
+            require_balanced: false,
+            by_extension: false,
+            chain_length_cap: None,
+            by_root_kind: false,
+            nesting_depth: false,
+            limit_files: None,
+            sanity_max: None,
+            min_files: None,
+            archive: None,
+            max_in_flight: None,
+            stop: None,
+            shuffle: None,
+            shard: None,
+        }
+    }
+
+    /// File extensions (without the dot) to analyze. Defaults to `java`.
+    pub fn extensions<I, S>(mut self, extensions: I) -> Self where I: IntoIterator<Item = S>, S: Into<String> {
+        self.extensions = extensions.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Glob patterns of files to skip, matched against paths relative to the
+    /// corpus root (the parent of each project directory), e.g. `**/test/**`.
+    pub fn exclude<I, S>(mut self, patterns: I) -> Self where I: IntoIterator<Item = S>, S: Into<String> {
+        self.exclude = patterns.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Number of threads analyzing the files of a project. Defaults to 1.
+    ///
+    /// The reports are the same for any number of threads: the file reports
+    /// are merged in path order whichever thread finished first, and all the
+    /// merged counts are integers.
+    pub fn jobs(mut self, jobs: usize) -> Self {
+        self.jobs = jobs.max(1);
+        self
+    }
+
+    /// Read and hold at most this many files at once (at least one), however
+    /// many [`Corpus::jobs`] there are, so that a few threads on large files
+    /// do not run out of memory. A file is held from the time it is read until
+    /// its analysis is done. Without a limit, every thread holds a file.
+    pub fn max_in_flight(mut self, max_in_flight: Option<usize>) -> Self {
+        self.max_in_flight = max_in_flight.map(|max_in_flight| max_in_flight.max(1));
+        self
+    }
+
+    /// Maximum nesting depth passed to the detector. Defaults to 1000.
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// What the detector counts as a chain.
+    pub fn chain_config(mut self, chain_config: ChainConfig) -> Self {
+        self.chain_config = chain_config;
+        self
+    }
+
+    /// Memory-map files instead of reading them into memory. Files that cannot
+    /// be mapped are read as usual.
+    pub fn mmap(mut self, mmap: bool) -> Self {
+        self.mmap = mmap;
+        self
+    }
+
+    /// Also split each project's histogram by the package declared in each
+    /// file, see [`ProjectReport::packages`].
+    pub fn by_package(mut self, by_package: bool) -> Self {
+        self.by_package = by_package;
+        self
+    }
+
+    /// Also locate every chain longer than `threshold`, see
+    /// [`ProjectReport::long_chains`].
+    pub fn report_over(mut self, threshold: Option<usize>) -> Self {
+        self.report_over = threshold;
+        self
+    }
+
+    /// Analyze only a random sample of the files, each with probability
+    /// `rate` (clamped to `0.0..=1.0`). The same `seed` picks the same files.
+    /// Counts are those of the sample and are not scaled up.
+    pub fn sample(mut self, rate: f64, seed: u64) -> Self {
+        self.sample = Some((rate.clamp(0.0, 1.0), seed));
+        self
+    }
+
+    /// Count the chains in the bodies of anonymous classes separately, see
+    /// [`ProjectReport::anonymous_histogram`].
+    pub fn count_anonymous_classes(mut self, count_anonymous_classes: bool) -> Self {
+        self.count_anonymous_classes = count_anonymous_classes;
+        self
+    }
+
+    /// Count the chains starting with one of `roots` separately, see
+    /// [`ProjectReport::assertion_histogram`]. An assertion in an anonymous
+    /// class counts as an assertion.
+    pub fn assertion_chains(mut self, roots: Option<AssertionRoots>) -> Self {
+        self.assertion_roots = roots;
+        self
+    }
+
+    /// Analyze exactly these project directories, in this order, instead of
+    /// the subdirectories of the root. They may be anywhere.
+    pub fn project_dirs<I, P>(mut self, project_dirs: I) -> Self where I: IntoIterator<Item = P>, P: Into<PathBuf> {
+        self.project_dirs = Some(project_dirs.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Also split each project's histogram by file extension, see
+    /// [`ProjectReport::extensions`].
+    pub fn by_extension(mut self, by_extension: bool) -> Self {
+        self.by_extension = by_extension;
+        self
+    }
+
+    /// Count every chain of at least this length as exactly this long, so the
+    /// histograms end in a single bucket for all the longest chains instead of
+    /// a sparse tail. See [`histogram::cap_chain_lengths`].
+    pub fn chain_length_cap(mut self, chain_length_cap: Option<usize>) -> Self {
+        self.chain_length_cap = chain_length_cap;
+        self
+    }
+
+    /// Analyze at most this many files of each project, the first ones in
+    /// path order (after [`Corpus::sample`]). The reports are then partial.
+    pub fn limit_files(mut self, limit_files: Option<usize>) -> Self {
+        self.limit_files = limit_files;
+        self
+    }
+
+    /// Log a warning for every file with a chain longer than this. Such chains
+    /// are rare in real code, and more often a sign of something the
+    /// tokenizer gets wrong.
+    pub fn sanity_max(mut self, sanity_max: Option<usize>) -> Self {
+        self.sanity_max = sanity_max;
+        self
+    }
+
+    /// Leave out the projects with fewer files than this to analyze (counted
+    /// after [`Corpus::sample`] and [`Corpus::limit_files`]), without
+    /// analyzing their files. Tiny projects mostly add noise.
+    pub fn min_files(mut self, min_files: Option<usize>) -> Self {
+        self.min_files = min_files;
+        self
+    }
+
+    /// Go through the projects in an order shuffled by `seed` rather than by
+    /// name, the same for the same seed and projects. Workers sharing a
+    /// corpus then do not all run into its large projects at once.
+    pub fn shuffle(mut self, seed: Option<u64>) -> Self {
+        self.shuffle = seed;
+        self
+    }
+
+    /// Only analyze the projects of `shard`. Running every shard of a total,
+    /// one per worker, analyzes each project exactly once.
+    pub fn shard(mut self, shard: Option<Shard>) -> Self {
+        self.shard = shard;
+        self
+    }
+
+    fn in_shard(&self, project: &str) -> bool {
+        self.shard.is_none_or(|shard| shard.contains(project))
+    }
+
+    /// Stop once `stop` is set, before starting the next project, as when the
+    /// user interrupts a long run: the projects already handed out are
+    /// complete, and [`Corpus::for_each_project`] returns early without an
+    /// error.
+    pub fn stop_when(mut self, stop: Arc<AtomicBool>) -> Self {
+        self.stop = Some(stop);
+        self
+    }
+
+    // Whether to start no more projects, logging it if so.
+    fn stopped(&self, remaining: usize) -> bool {
+        let stopped = self.stop.as_ref().is_some_and(|stop| stop.load(Ordering::SeqCst));
+        if stopped {
+            tracing::warn!(remaining, "stopping before the remaining projects");
+        }
+        stopped
+    }
+
+    /// Read the projects from the top-level directories of this zip archive
+    /// instead of the subdirectories of the root, without extracting it. The
+    /// paths of the projects and their files start with the path of the
+    /// archive, as in `corpus.zip/alpha/src/A.java`, and entries outside any
+    /// directory are left out.
+    pub fn archive(mut self, archive: Option<PathBuf>) -> Self {
+        self.archive = archive;
+        self
+    }
+
+    /// Also find how deep parentheses, brackets, and braces nest, see
+    /// [`ProjectReport::max_nesting_depth`].
+    pub fn nesting_depth(mut self, nesting_depth: bool) -> Self {
+        self.nesting_depth = nesting_depth;
+        self
+    }
+
+    /// Also split each project's histogram by what the chains are called on,
+    /// see [`ProjectReport::root_kinds`].
+    pub fn by_root_kind(mut self, by_root_kind: bool) -> Self {
+        self.by_root_kind = by_root_kind;
+        self
+    }
+
+    /// Skip the files whose parentheses, brackets, and braces are not
+    /// balanced (see [`crate::is_balanced`]) instead of counting their chains.
+    pub fn require_balanced(mut self, require_balanced: bool) -> Self {
+        self.require_balanced = require_balanced;
+        self
+    }
+
+    /// The project directories in the corpus: those given to
+    /// [`Corpus::project_dirs`], or else the subdirectories of the root,
+    /// sorted by name. Either way only those of the [`Corpus::shard`], and
+    /// shuffled with [`Corpus::shuffle`].
+    pub fn projects(&self) -> anyhow::Result<Vec<PathBuf>> {
+        let mut projects = match &self.project_dirs {
+            Some(project_dirs) => project_dirs.clone(),
+            None => {
+                let mut projects = std::fs::read_dir(&self.root)
+                    .with_context(|| format!("Cannot read directory {:?}", self.root))?
+                    .filter_map(|entry| entry.ok())
+                    .filter(|entry| entry.file_type().is_ok_and(|file_type| file_type.is_dir()))
+                    .map(|entry| entry.path())
+                    .collect::<Vec<PathBuf>>();
+                projects.sort();
+                projects
+            }
+        };
+        projects.retain(|project| self.in_shard(&project.file_name().unwrap_or_default().to_string_lossy()));
+        self.shuffle_projects(&mut projects);
+        Ok(projects)
+    }
+
+    fn shuffle_projects<T>(&self, projects: &mut [T]) {
+        if let Some(seed) = self.shuffle {
+            projects.shuffle(&mut rand::rngs::StdRng::seed_from_u64(seed));
+        }
+    }
+
+    /// The files of `project_dir` that will be analyzed, sorted by path.
+    pub fn project_files(&self, project_dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+        self.select_files(project_dir, read_dir_all(project_dir)?)
+    }
+
+    // The files of `project_dir` among `files` that will be analyzed, sorted
+    // by path.
+    fn select_files(&self, project_dir: &Path, files: Vec<PathBuf>) -> anyhow::Result<Vec<PathBuf>> {
+        let exclude = self.exclude_set()?;
+        let mut files = files
+            .into_iter()
+            .filter(|path| has_extension(path, &self.extensions))
+            .filter(|path| {
+                // Relative to the parent of the project, the root unless the
+                // project directories were listed.
+                let parent = project_dir.parent().unwrap_or(project_dir);
+                let relative = path.strip_prefix(parent).unwrap_or(path);
+                !exclude.is_match(relative)
+            })
+            .collect::<Vec<PathBuf>>();
+        files.sort();
+        if let Some((rate, seed)) = self.sample {
+            // Drawn in path order, so a project's sample does not depend on
+            // the other projects, from a seed of its own, so that projects
+            // with the same files do not pick the same ones.
+            let name = project_dir.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+            let mut rng = rand::rngs::StdRng::seed_from_u64(seed ^ stable_hash(&name));
+            files.retain(|_| rng.gen_bool(rate));
+        }
+        if let Some(limit) = self.limit_files {
+            files.truncate(limit);
+        }
+        Ok(files)
+    }
+
+    fn exclude_set(&self) -> anyhow::Result<GlobSet> {
+        let mut builder = GlobSetBuilder::new();
+        for pattern in &self.exclude {
+            builder.add(Glob::new(pattern).with_context(|| format!("Invalid exclude pattern {:?}", pattern))?);
+        }
+        Ok(builder.build()?)
+    }
+
+    /// Analyzes a single project directory.
+    pub fn analyze_project(&self, project_dir: &Path) -> anyhow::Result<ProjectReport> {
+        let files = self.project_files(project_dir)?;
+        self.analyze_project_files(project_dir, files)
+    }
+
+    fn analyze_project_files(&self, project_dir: &Path, files: Vec<PathBuf>) -> anyhow::Result<ProjectReport> {
+        self.analyze_project_with(project_dir, &files, |path| self.analyze_file(path))
+    }
+
+    fn analyze_project_with<F>(&self, project_dir: &Path, files: &[PathBuf], analyze: F) -> anyhow::Result<ProjectReport>
+        where F: Fn(&Path) -> anyhow::Result<FileReport> + Sync {
+        let name = project_dir.file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let span = tracing::info_span!("project", name = %name);
+        let _entered = span.enter();
+
+        tracing::info!(files = files.len(), "processing files");
+
+        let reports = self.analyze_files(files, analyze);
+        let lines = reports.iter().map(|report| report.lines).sum();
+        let bytes = reports.iter().map(|report| report.bytes).sum();
+        let mut packages: BTreeMap<String, BTreeMap<usize, usize>> = BTreeMap::new();
+        if self.by_package {
+            for report in &reports {
+                let package = report.package.as_deref().unwrap_or(DEFAULT_PACKAGE);
+                let histogram = packages.entry(package.to_owned()).or_default();
+                for chain_length in &report.chain_counts {
+                    histogram::add_to_histogram(histogram, *chain_length, 1);
+                }
+            }
+        }
+        let mut extensions: BTreeMap<String, BTreeMap<usize, usize>> = BTreeMap::new();
+        if self.by_extension {
+            for report in &reports {
+                let extension = report.path.extension()
+                    .map(|extension| extension.to_string_lossy().to_ascii_lowercase())
+                    .unwrap_or_default();
+                let histogram = extensions.entry(extension).or_default();
+                for chain_length in &report.chain_counts {
+                    histogram::add_to_histogram(histogram, *chain_length, 1);
+                }
+            }
+        }
+        let mut root_kinds: BTreeMap<RootKind, BTreeMap<usize, usize>> = BTreeMap::new();
+        for report in &reports {
+            for (root_kind, chain_counts) in &report.root_kind_counts {
+                let histogram = root_kinds.entry(*root_kind).or_default();
+                for chain_length in chain_counts {
+                    histogram::add_to_histogram(histogram, *chain_length, 1);
+                }
+            }
+        }
+        let long_chains = reports.iter()
+            .flat_map(|report| {
+                let file = report.path.strip_prefix(project_dir).unwrap_or(&report.path);
+                report.long_chains.iter().map(move |span| {
+                    LongChain { file: file.to_path_buf(), start_line: span.start_line, length: span.length }
+                })
+            })
+            .collect();
+        let anonymous_histogram = histogram::histogram(reports.iter().flat_map(|report| report.anonymous_chain_counts.iter().copied()));
+        let assertion_histogram = histogram::histogram(reports.iter().flat_map(|report| report.assertion_chain_counts.iter().copied()));
+        let max_nesting_depth = reports.iter().map(|report| report.max_nesting_depth).max().unwrap_or(0);
+        let longest_chain_file = reports.iter()
+            .filter_map(|report| report.chain_counts.iter().max().map(|length| (*length, &report.path)))
+            .fold(None, |longest: Option<(usize, &PathBuf)>, (length, path)| match longest {
+                Some((longest_length, _)) if longest_length >= length => longest,
+                _ => Some((length, path)),
+            })
+            .map(|(_, path)| path.strip_prefix(project_dir).unwrap_or(path).to_path_buf());
+        let first_appearances = histogram::first_appearances(reports.iter().flat_map(|report| report.chain_counts.iter().copied()));
+        let histogram = histogram::histogram(reports.into_iter().flat_map(|report| report.chain_counts));
+
+        Ok(ProjectReport {
+            name, path: project_dir.to_path_buf(), files: files.len(), lines, bytes, histogram, first_appearances, packages, extensions, root_kinds,
+            long_chains, anonymous_histogram, assertion_histogram, max_nesting_depth, longest_chain_file,
+        })
+    }
+
+    /// Reads and analyzes a single file.
+    pub fn analyze_file(&self, path: &Path) -> anyhow::Result<FileReport> {
+        let contents = if self.mmap { SourceBytes::map(path)? } else { SourceBytes::read(path)? };
+        self.analyze_contents(path, &contents)
+    }
+
+    fn analyze_contents(&self, path: &Path, contents: &[u8]) -> anyhow::Result<FileReport> {
+        let lines = count_lines(contents);
+        let bytes = contents.len() as u64;
+        let source = String::from_utf8_lossy(contents);
+        let chain_config = self.chain_config.for_path(path);
+        if self.require_balanced && !crate::is_balanced(&crate::tokenize_source(&source, chain_config.language)) {
+            anyhow::bail!("Unbalanced parentheses, brackets, or braces in {:?}", path);
+        }
+        let mut chain_counts = Vec::new();
+        let mut anonymous_chain_counts = Vec::new();
+        let mut assertion_chain_counts = Vec::new();
+        if self.count_anonymous_classes || self.assertion_roots.is_some() {
+            for chain in source.method_chains_with(self.max_depth, &chain_config)? {
+                let counts = if self.assertion_roots.as_ref().is_some_and(|roots| chain.is_assertion(roots)) {
+                    &mut assertion_chain_counts
+                } else if self.count_anonymous_classes && chain.in_anonymous_class {
+                    &mut anonymous_chain_counts
+                } else {
+                    &mut chain_counts
+                };
+                counts.push(chain.length());
+            }
+        } else {
+            chain_counts = source.method_chain_counts_with(self.max_depth, &chain_config)?;
+        }
+        if let Some(limit) = self.sanity_max {
+            let longest = chain_counts.iter().chain(&anonymous_chain_counts).chain(&assertion_chain_counts).max();
+            if let Some(&length) = longest.filter(|&&length| length > limit) {
+                tracing::warn!(path = ?path, length, limit, "suspiciously long chain");
+            }
+        }
+        let mut root_kind_counts = BTreeMap::new();
+        if self.by_root_kind {
+            let source = if chain_config.decode_unicode_escapes { crate::decode_unicode_escapes(&source) } else { Cow::Borrowed(source.as_ref()) };
+            let tokens = crate::chain_tokens(&source, &chain_config);
+            root_kind_counts = crate::chain_counts_by_root_kind(&tokens, self.max_depth, &chain_config)?;
+        }
+        if let Some(cap) = self.chain_length_cap {
+            histogram::cap_chain_lengths(&mut chain_counts, cap);
+            histogram::cap_chain_lengths(&mut anonymous_chain_counts, cap);
+            histogram::cap_chain_lengths(&mut assertion_chain_counts, cap);
+            for chain_counts in root_kind_counts.values_mut() {
+                histogram::cap_chain_lengths(chain_counts, cap);
+            }
+        }
+        let package = if self.by_package { crate::package_name(&source) } else { None };
+        let long_chains = match self.report_over {
+            Some(threshold) => source.method_chain_spans_with(self.max_depth, &chain_config)?
+                .into_iter()
+                .filter(|span| span.length > threshold)
+                .collect(),
+            None => Vec::new(),
+        };
+        let max_nesting_depth = if self.nesting_depth { source.max_nesting_depth_with(&chain_config) } else { 0 };
+        Ok(FileReport {
+            path: path.to_path_buf(), chain_counts, lines, bytes, package, long_chains, root_kind_counts, anonymous_chain_counts, assertion_chain_counts,
+            max_nesting_depth,
+        })
+    }
+
+    // Per-file reports, in the same order as `files`. Files that fail to
+    // analyze are reported and contribute no chains.
+    fn analyze_files<F>(&self, files: &[PathBuf], analyze_file: F) -> Vec<FileReport>
+        where F: Fn(&Path) -> anyhow::Result<FileReport> + Sync {
+        let in_flight = self.max_in_flight.map(Semaphore::new);
+        let analyze = |path: &PathBuf| {
+            let _permit = in_flight.as_ref().map(Semaphore::acquire);
+            match analyze_file(path) {
+                Ok(report) => {
+                    tracing::info!(path = ?path, chains = report.chain_counts.len(), "analyzed file");
+                    report
+                }
+                Err(error) => {
+                    tracing::warn!(path = ?path, error = %error, "skipping file");
+                    FileReport { path: path.clone(), ..FileReport::default() }
+                }
+            }
+        };
+
+        if self.jobs <= 1 || files.len() <= 1 {
+            return files.iter().map(analyze).collect();
+        }
+
+        let next = AtomicUsize::new(0);
+        let results = Mutex::new(vec![FileReport::default(); files.len()]);
+        let span = tracing::Span::current();
+        std::thread::scope(|scope| {
+            for _ in 0..self.jobs.min(files.len()) {
+                scope.spawn(|| {
+                    // Workers log within the span of the project.
+                    let _entered = span.enter();
+                    loop {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        if index >= files.len() {
+                            break;
+                        }
+                        let report = analyze(&files[index]);
+                        results.lock().unwrap()[index] = report;
+                    }
+                });
+            }
+        });
+        results.into_inner().unwrap()
+    }
+
+    /// Analyzes the projects one by one, handing each report to `f` as soon as
+    /// it is complete.
+    pub fn for_each_project<F: FnMut(ProjectReport)>(&self, mut f: F) -> anyhow::Result<()> {
+        if let Some(archive) = &self.archive {
+            return self.for_each_archive_project(archive, f);
+        }
+        let projects = self.projects()?;
+        let total_projects = projects.len();
+        tracing::info!(projects = total_projects, root = ?self.root, "found project directories");
+        for (i, project_dir) in projects.iter().enumerate() {
+            if self.stopped(total_projects - i) {
+                break;
+            }
+            tracing::info!(index = i + 1, total = total_projects, path = ?project_dir, "processing project");
+            let files = self.project_files(project_dir)?;
+            if let Some(min_files) = self.min_files.filter(|min_files| files.len() < *min_files) {
+                tracing::info!(files = files.len(), min_files, path = ?project_dir, "skipping project with too few files");
+                continue;
+            }
+            f(self.analyze_project_files(project_dir, files)?);
+        }
+        Ok(())
+    }
+
+    fn for_each_archive_project<F: FnMut(ProjectReport)>(&self, archive: &Path, mut f: F) -> anyhow::Result<()> {
+        let file = std::fs::File::open(archive).with_context(|| format!("Cannot open archive {:?}", archive))?;
+        let mut zip = zip::ZipArchive::new(file).with_context(|| format!("Cannot read archive {:?}", archive))?;
+
+        // The index of each file entry, by project name and path.
+        let mut projects: BTreeMap<String, HashMap<PathBuf, usize>> = BTreeMap::new();
+        for index in 0..zip.len() {
+            let entry = zip.by_index_raw(index).with_context(|| format!("Cannot read archive {:?}", archive))?;
+            let Some(name) = entry.enclosed_name().filter(|_| !entry.is_dir()) else {
+                continue;
+            };
+            let mut components = name.components();
+            if let (Some(project), Some(_)) = (components.next(), components.next()) {
+                let project = project.as_os_str().to_string_lossy().into_owned();
+                projects.entry(project).or_default().insert(archive.join(&name), index);
+            }
+        }
+
+        let zip = Mutex::new(zip);
+        let mut projects = projects.into_iter().filter(|(project, _)| self.in_shard(project)).collect::<Vec<_>>();
+        self.shuffle_projects(&mut projects);
+        let total_projects = projects.len();
+        tracing::info!(projects = total_projects, archive = ?archive, "found project directories");
+        for (i, (project, entries)) in projects.into_iter().enumerate() {
+            if self.stopped(total_projects - i) {
+                break;
+            }
+            let project_dir = archive.join(project);
+            tracing::info!(index = i + 1, total = total_projects, path = ?project_dir, "processing project");
+            let files = self.select_files(&project_dir, entries.keys().cloned().collect())?;
+            if let Some(min_files) = self.min_files.filter(|min_files| files.len() < *min_files) {
+                tracing::info!(files = files.len(), min_files, path = ?project_dir, "skipping project with too few files");
+                continue;
+            }
+            // Entries are read one at a time, each when its analysis starts,
+            // so that no more than `max_in_flight` are held at once.
+            f(self.analyze_project_with(&project_dir, &files, |path| {
+                let mut contents = Vec::new();
+                zip.lock().unwrap().by_index(entries[path])
+                    .map_err(anyhow::Error::from)
+                    .and_then(|mut entry| Ok(entry.read_to_end(&mut contents)?))
+                    .map_err(|error| anyhow::anyhow!("Cannot read {:?}: {:#}", path, error))?;
+                self.analyze_contents(path, &contents)
+            })?);
+        }
+        Ok(())
+    }
+
+    pub fn run(&self) -> anyhow::Result<CorpusReport> {
+        let mut report = CorpusReport::default();
+        self.for_each_project(|project| report.projects.push(project))?;
+        Ok(report)
+    }
+}
+
+// Hands out a fixed number of permits, blocking until one is returned when
+// there are none left.
+struct Semaphore {
+    permits: Mutex<usize>,
+    returned: Condvar,
+}
+
+struct Permit<'a>(&'a Semaphore);
+
+impl Semaphore {
+    fn new(permits: usize) -> Self {
+        Semaphore { permits: Mutex::new(permits), returned: Condvar::new() }
+    }
+
+    fn acquire(&self) -> Permit<'_> {
+        let permits = self.permits.lock().unwrap();
+        let mut permits = self.returned.wait_while(permits, |permits| *permits == 0).unwrap();
+        *permits -= 1;
+        Permit(self)
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        *self.0.permits.lock().unwrap() += 1;
+        self.0.returned.notify_one();
+    }
+}
+
+/// Whether the extension of `path` is one of `extensions` (given without the
+/// dot), ignoring ASCII case: `Foo.JAVA` has the extension `java`. Extensions
+/// that are not valid UTF-8 are compared byte by byte and never match a UTF-8
+/// extension that differs from them.
+pub fn has_extension(path: &Path, extensions: &[String]) -> bool {
+    path.extension().is_some_and(|extension| {
+        extensions.iter().any(|expected| extension.as_encoded_bytes().eq_ignore_ascii_case(expected.as_bytes()))
+    })
+}
+
+// The files in `dir` and its subdirectories, like `crate::read_dir_all`, but
+// failing instead of panicking when a directory cannot be read.
+fn read_dir_all(dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(dir).with_context(|| format!("Cannot read directory {:?}", dir))? {
+        let entry = entry.with_context(|| format!("Cannot read directory {:?}", dir))?;
+        if entry.file_type().with_context(|| format!("Cannot read {:?}", entry.path()))?.is_dir() {
+            files.extend(read_dir_all(&entry.path())?);
+        } else {
+            files.push(entry.path());
+        }
+    }
+    Ok(files)
+}
+
+fn count_lines(contents: &[u8]) -> usize {
+    let newlines = contents.iter().filter(|byte| **byte == b'\n').count();
+    match contents.last() {
+        Some(b'\n') | None => newlines,
+        Some(_) => newlines + 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(root: &Path, path: &str, contents: &str) {
+        let path = root.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, contents).unwrap();
+    }
+
+    fn sample_corpus() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), "alpha/src/main/A.java", "class A { void f() { a().b().c(); d(); } }");
+        write(dir.path(), "alpha/src/test/ATest.java", "class ATest { void t() { x().y(); } }");
+        write(dir.path(), "alpha/README.md", "a().b().c().d()");
+        write(dir.path(), "beta/B.java", "class B { void g() { e.f(); } }");
+        write(dir.path(), "beta/C.kt", "fun h() { e.f().g() }");
+        write(dir.path(), "not-a-project.java", "a().b();");
+        dir
+    }
+
+    #[test]
+    fn test_corpus_defaults() {
+        let dir = sample_corpus();
+        let report = Corpus::new(dir.path()).run().unwrap();
+        assert_eq!(report.projects.iter().map(|project| project.name.as_str()).collect::<Vec<_>>(), vec!["alpha", "beta"]);
+        assert_eq!(report.projects[0].files, 2);
+        assert_eq!(report.projects[0].histogram, BTreeMap::from([(1, 1), (2, 1), (3, 1)]));
+        assert_eq!(report.projects[1].files, 1);
+        assert_eq!(report.projects[1].histogram, BTreeMap::from([(1, 1)]));
+    }
+
+    #[test]
+    fn test_corpus_builder() {
+        let dir = sample_corpus();
+        let report = Corpus::new(dir.path())
+            .extensions(["java", "kt"])
+            .exclude(["**/test/**"])
+            .jobs(4)
+            .run()
+            .unwrap();
+        assert_eq!(report.projects[0].files, 1);
+        assert_eq!(report.projects[0].histogram, BTreeMap::from([(1, 1), (3, 1)]));
+        assert_eq!(report.projects[1].files, 2);
+        assert_eq!(report.projects[1].histogram, BTreeMap::from([(1, 1), (2, 1)]));
+    }
+
+    #[test]
+    fn test_corpus_mmap() {
+        let dir = sample_corpus();
+        let corpus = Corpus::new(dir.path()).extensions(["java", "kt"]);
+        assert_eq!(corpus.clone().mmap(true).run().unwrap(), corpus.run().unwrap());
+    }
+
+    #[test]
+    fn test_corpus_size() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), "gamma/A.java", "class A {\n  void f() {\n    a().b();\n  }\n}\n");
+        write(dir.path(), "gamma/pkg/B.java", "class B {}\n\n// no newline at the end");
+        write(dir.path(), "gamma/pkg/C.java", "");
+        let report = Corpus::new(dir.path()).run().unwrap();
+        assert_eq!(report.projects[0].files, 3);
+        assert_eq!(report.projects[0].lines, 5 + 3);
+        assert_eq!(report.projects[0].chains_per_kloc(), 125.0);
+        assert_eq!(report.projects[0].bytes, 42 + 36);
+    }
+
+    #[test]
+    fn test_corpus_by_package() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), "gamma/src/a/A.java", "package com.example.a;\nclass A { void f() { a().b(); c(); } }");
+        write(dir.path(), "gamma/src/b/B.java", "/* package c; */\npackage com.example.b;\nclass B { void g() { d().e(); } }");
+        write(dir.path(), "gamma/src/C.java", "class C { void h() { f().g().h(); } }");
+
+        let report = Corpus::new(dir.path()).run().unwrap();
+        assert!(report.projects[0].packages.is_empty());
+
+        let report = Corpus::new(dir.path()).by_package(true).run().unwrap();
+        assert_eq!(report.projects[0].histogram, BTreeMap::from([(1, 1), (2, 2), (3, 1)]));
+        assert_eq!(report.projects[0].packages, BTreeMap::from([
+            ("(default)".to_owned(), BTreeMap::from([(3, 1)])),
+            ("com.example.a".to_owned(), BTreeMap::from([(1, 1), (2, 1)])),
+            ("com.example.b".to_owned(), BTreeMap::from([(2, 1)])),
+        ]));
+    }
+
+    // Records the level, span, and message of every event.
+    #[derive(Clone, Default)]
+    struct Events(std::sync::Arc<Mutex<Vec<String>>>);
+
+    impl<S> tracing_subscriber::Layer<S> for Events
+    where S: tracing::Subscriber + for<'a> tracing_subscriber::registry::LookupSpan<'a> {
+        fn on_event(&self, event: &tracing::Event<'_>, context: tracing_subscriber::layer::Context<'_, S>) {
+            struct Message(String);
+            impl tracing::field::Visit for Message {
+                fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+                    if field.name() == "message" {
+                        self.0 = format!("{:?}", value);
+                    }
+                }
+            }
+            let mut message = Message(String::new());
+            event.record(&mut message);
+            let span = context.event_span(event).map(|span| span.name()).unwrap_or("-");
+            self.0.lock().unwrap().push(format!("{} {} {}", event.metadata().level(), span, message.0));
+        }
+    }
+
+    #[test]
+    fn test_corpus_events() {
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), "gamma/A.java", "class A { void f() { a().b(); } }");
+        write(dir.path(), "gamma/B.java", "class B { void g() { a(b(c(d()))); } }");
+
+        let events = Events::default();
+        let subscriber = tracing_subscriber::registry().with(events.clone());
+        let report = tracing::subscriber::with_default(subscriber, || {
+            Corpus::new(dir.path()).max_depth(3).run().unwrap()
+        });
+        assert_eq!(report.projects[0].histogram, BTreeMap::from([(2, 1)]));
+        assert_eq!(*events.0.lock().unwrap(), vec![
+            "INFO - found project directories",
+            "INFO - processing project",
+            "INFO project processing files",
+            "INFO project analyzed file",
+            "WARN project skipping file",
+        ]);
+    }
+
+    #[test]
+    fn test_corpus_sanity_max() {
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), "gamma/A.java", "class A { void f() { a().b(); } }");
+        write(dir.path(), "gamma/B.java", &format!("class B {{ void g() {{ x{}; }} }}", ".y()".repeat(60)));
+
+        let events = Events::default();
+        let subscriber = tracing_subscriber::registry().with(events.clone());
+        let report = tracing::subscriber::with_default(subscriber, || {
+            Corpus::new(dir.path()).sanity_max(Some(50)).run().unwrap()
+        });
+        assert_eq!(report.projects[0].histogram, BTreeMap::from([(2, 1), (60, 1)]));
+        let warnings = events.0.lock().unwrap().iter()
+            .filter(|event| event.starts_with("WARN"))
+            .cloned()
+            .collect::<Vec<String>>();
+        assert_eq!(warnings, vec!["WARN project suspiciously long chain"]);
+    }
+
+    #[test]
+    fn test_has_extension() {
+        let extensions = vec!["java".to_owned(), "kt".to_owned()];
+        assert!(has_extension(Path::new("src/Foo.java"), &extensions));
+        assert!(has_extension(Path::new("src/Foo.JAVA"), &extensions));
+        assert!(has_extension(Path::new("src/Foo.Java"), &extensions));
+        assert!(has_extension(Path::new("Foo.KT"), &extensions));
+        assert!(!has_extension(Path::new("Foo.javax"), &extensions));
+        assert!(!has_extension(Path::new("java"), &extensions));
+        assert!(!has_extension(Path::new("Foo.java"), &[]));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_has_extension_non_utf8() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let path = Path::new(OsStr::from_bytes(b"Foo.j\xffva"));
+        assert!(!has_extension(path, &["java".to_owned()]));
+        assert!(!has_extension(path, &["j\u{ff}va".to_owned()]));
+        assert!(has_extension(Path::new(OsStr::from_bytes(b"\xff.java")), &["java".to_owned()]));
+    }
+
+    #[test]
+    fn test_corpus_invalid_exclude() {
+        let dir = sample_corpus();
+        assert!(Corpus::new(dir.path()).exclude(["a/**/["]).run().is_err());
+    }
+
+    #[test]
+    fn test_corpus_report_over() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), "gamma/src/A.java", "class A {\n  void f() {\n    a().b();\n    builder\n      .c()\n      .d()\n      .e();\n  }\n}\n");
+        write(dir.path(), "gamma/B.java", "class B { void g() { f().g().h(); } }");
+
+        let report = Corpus::new(dir.path()).run().unwrap();
+        assert!(report.projects[0].long_chains.is_empty());
+
+        let report = Corpus::new(dir.path()).report_over(Some(2)).run().unwrap();
+        assert_eq!(report.projects[0].long_chains, vec![
+            LongChain { file: PathBuf::from("B.java"), start_line: 1, length: 3 },
+            LongChain { file: PathBuf::from("src/A.java"), start_line: 4, length: 3 },
+        ]);
+    }
+
+    #[test]
+    fn test_corpus_sample() {
+        let dir = tempfile::tempdir().unwrap();
+        for i in 0..20 {
+            write(dir.path(), &format!("gamma/A{}.java", i), "class A { void f() { a().b(); } }");
+        }
+        let corpus = Corpus::new(dir.path());
+        let files = |corpus: Corpus| corpus.run().unwrap().projects[0].files;
+        assert_eq!(files(corpus.clone().sample(0.0, 7)), 0);
+        assert_eq!(files(corpus.clone().sample(1.0, 7)), 20);
+
+        let half = corpus.clone().sample(0.5, 7).run().unwrap();
+        assert!(0 < half.projects[0].files && half.projects[0].files < 20);
+        assert_eq!(half.projects[0].histogram, BTreeMap::from([(2, half.projects[0].files)]));
+        assert_eq!(corpus.clone().sample(0.5, 7).run().unwrap(), half);
+
+        // The same files in another project, sampled differently.
+        for i in 0..20 {
+            write(dir.path(), &format!("delta/A{}.java", i), "class A { void f() { a().b(); } }");
+        }
+        let picks = |project: &str| corpus.clone().sample(0.5, 7).project_files(&dir.path().join(project)).unwrap()
+            .into_iter()
+            .map(|path| path.file_name().unwrap().to_owned())
+            .collect::<Vec<_>>();
+        assert_ne!(picks("gamma"), picks("delta"));
+    }
+
+    #[test]
+    fn test_corpus_missing_project() {
+        let dir = tempfile::tempdir().unwrap();
+        let error = Corpus::new(dir.path()).project_files(&dir.path().join("missing")).unwrap_err();
+        assert!(error.to_string().starts_with("Cannot read directory"), "{}", error);
+    }
+
+    #[test]
+    fn test_corpus_count_anonymous_classes() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), "gamma/A.java", r#"
+            class A {
+                void f() {
+                    executor.submit(new Runnable() {
+                        public void run() { queue.poll().run(); }
+                    });
+                }
+            }
+        "#);
+
+        let report = Corpus::new(dir.path()).run().unwrap();
+        assert_eq!(report.projects[0].histogram, BTreeMap::from([(1, 1), (2, 1)]));
+        assert!(report.projects[0].anonymous_histogram.is_empty());
+
+        let report = Corpus::new(dir.path()).count_anonymous_classes(true).run().unwrap();
+        assert_eq!(report.projects[0].histogram, BTreeMap::from([(1, 1)]));
+        assert_eq!(report.projects[0].anonymous_histogram, BTreeMap::from([(2, 1)]));
+    }
+
+    #[test]
+    fn test_corpus_assertion_chains() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), "gamma/ATest.java", r#"
+            class ATest {
+                void t() {
+                    assertThat(result).isNotNull().isEqualTo(expected);
+                    builder.withName(name).withAge(age).build();
+                    executor.submit(new Runnable() { public void run() { assertThat(x).isTrue(); queue.poll(); } });
+                }
+            }
+        "#);
+
+        let report = Corpus::new(dir.path()).run().unwrap();
+        assert_eq!(report.projects[0].histogram, BTreeMap::from([(1, 2), (2, 1), (3, 2)]));
+
+        let report = Corpus::new(dir.path()).assertion_chains(Some(AssertionRoots::default())).count_anonymous_classes(true).run().unwrap();
+        assert_eq!(report.projects[0].histogram, BTreeMap::from([(1, 1), (3, 1)]));
+        assert_eq!(report.projects[0].assertion_histogram, BTreeMap::from([(2, 1), (3, 1)]));
+        assert_eq!(report.projects[0].anonymous_histogram, BTreeMap::from([(1, 1)]));
+    }
+
+    #[test]
+    fn test_corpus_project_dirs() {
+        let first = tempfile::tempdir().unwrap();
+        let second = tempfile::tempdir().unwrap();
+        write(first.path(), "alpha/src/A.java", "class A { void f() { a().b().c(); } }");
+        write(first.path(), "alpha/test/ATest.java", "class ATest { void t() { x().y(); } }");
+        write(second.path(), "beta/B.java", "class B { void g() { e.f(); } }");
+
+        let list = first.path().join("projects.txt");
+        std::fs::write(&list, format!("# scattered projects\n{}\n\n{}\n",
+                                      second.path().join("beta").display(), first.path().join("alpha").display())).unwrap();
+        let report = Corpus::new("ignored")
+            .project_dirs(crate::file_list::read_file_list(&list).unwrap())
+            .exclude(["alpha/test/**"])
+            .run()
+            .unwrap();
+        assert_eq!(report.projects.iter().map(|project| project.name.as_str()).collect::<Vec<_>>(), vec!["beta", "alpha"]);
+        assert_eq!(report.projects[0].histogram, BTreeMap::from([(1, 1)]));
+        assert_eq!(report.projects[1].histogram, BTreeMap::from([(3, 1)]));
+    }
+
+    #[test]
+    fn test_corpus_require_balanced() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), "gamma/A.java", "class A { void f() { a().b(); } }");
+        write(dir.path(), "gamma/B.java", "class B { void g() { c().d().e(); ");
+
+        let report = Corpus::new(dir.path()).run().unwrap();
+        assert_eq!(report.projects[0].histogram, BTreeMap::from([(2, 1), (3, 1)]));
+
+        let report = Corpus::new(dir.path()).require_balanced(true).run().unwrap();
+        assert_eq!(report.projects[0].files, 2);
+        assert_eq!(report.projects[0].histogram, BTreeMap::from([(2, 1)]));
+    }
+
+    #[test]
+    fn test_corpus_min_files() {
+        let dir = sample_corpus();
+        let report = Corpus::new(dir.path()).min_files(Some(2)).run().unwrap();
+        assert_eq!(report.projects.iter().map(|project| project.name.as_str()).collect::<Vec<_>>(), vec!["alpha"]);
+        assert_eq!(report.projects[0], Corpus::new(dir.path()).run().unwrap().projects[0]);
+    }
+
+    #[test]
+    fn test_corpus_longest_chain_file() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), "alpha/src/A.java", "class A { void f() { a().b(); c(); } }");
+        write(dir.path(), "alpha/src/B.java", "class B { void g() { d().e().f().g(); h().i(); } }");
+        // As long as the chain of B, but later in path order.
+        write(dir.path(), "alpha/test/C.java", "class C { void t() { j().k().l().m(); } }");
+        write(dir.path(), "beta/D.java", "class D { int x; }");
+        let report = Corpus::new(dir.path()).run().unwrap();
+        assert_eq!(report.projects[0].longest_chain_file, Some(Path::new("src").join("B.java")));
+        assert_eq!(report.projects[0].histogram.keys().last(), Some(&4));
+        assert_eq!(report.projects[1].longest_chain_file, None);
+    }
+
+    #[test]
+    fn test_corpus_nesting_depth() {
+        let dir = sample_corpus();
+        assert!(Corpus::new(dir.path()).run().unwrap().projects.iter().all(|project| project.max_nesting_depth == 0));
+        let report = Corpus::new(dir.path()).nesting_depth(true).run().unwrap();
+        assert_eq!(report.projects.iter().map(|project| project.max_nesting_depth).collect::<Vec<_>>(), vec![3, 3]);
+    }
+
+    #[test]
+    fn test_corpus_shard() {
+        let dir = tempfile::tempdir().unwrap();
+        let projects = (0..40).map(|i| format!("project-{}", i)).collect::<Vec<_>>();
+        for project in &projects {
+            std::fs::create_dir(dir.path().join(project)).unwrap();
+        }
+        let mut all = Vec::new();
+        for index in 0..4 {
+            let shard = Corpus::new(dir.path()).shard(Some(Shard { index, total: 4 })).projects().unwrap();
+            assert!(!shard.is_empty());
+            all.extend(shard.into_iter().map(|project| project.file_name().unwrap().to_string_lossy().into_owned()));
+        }
+        // Every project once.
+        all.sort();
+        let mut expected = projects.clone();
+        expected.sort();
+        assert_eq!(all, expected);
+        assert_eq!(Corpus::new(dir.path()).shard(Some(Shard { index: 0, total: 1 })).projects().unwrap().len(), 40);
+    }
+
+    #[test]
+    fn test_corpus_shuffle() {
+        let dir = tempfile::tempdir().unwrap();
+        for project in ["a", "b", "c", "d", "e", "f", "g", "h"] {
+            std::fs::create_dir(dir.path().join(project)).unwrap();
+        }
+        let names = |seed: Option<u64>| Corpus::new(dir.path()).shuffle(seed).projects().unwrap()
+            .into_iter()
+            .map(|project| project.file_name().unwrap().to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        assert_eq!(names(None), vec!["a", "b", "c", "d", "e", "f", "g", "h"]);
+        assert_eq!(names(Some(1)), names(Some(1)));
+        assert_ne!(names(Some(1)), names(Some(2)));
+        assert_ne!(names(Some(1)), names(None));
+        let mut sorted = names(Some(1));
+        sorted.sort();
+        assert_eq!(sorted, names(None));
+    }
+
+    #[test]
+    fn test_corpus_stop_when() {
+        let dir = sample_corpus();
+        let stop = Arc::new(AtomicBool::new(false));
+        let corpus = Corpus::new(dir.path()).stop_when(stop.clone());
+        let mut names = Vec::new();
+        corpus.for_each_project(|project| {
+            names.push(project.name);
+            stop.store(true, Ordering::SeqCst);
+        }).unwrap();
+        assert_eq!(names, vec!["alpha"]);
+    }
+
+    #[test]
+    fn test_corpus_max_in_flight() {
+        let files = (0..16).map(|i| PathBuf::from(format!("{}.java", i))).collect::<Vec<_>>();
+        let in_flight = AtomicUsize::new(0);
+        let high_water_mark = AtomicUsize::new(0);
+        let corpus = Corpus::new("projects").jobs(8).max_in_flight(Some(3));
+        let reports = corpus.analyze_files(&files, |path| {
+            let now = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            high_water_mark.fetch_max(now, Ordering::SeqCst);
+            std::thread::sleep(std::time::Duration::from_millis(5));
+            in_flight.fetch_sub(1, Ordering::SeqCst);
+            Ok(FileReport { path: path.to_path_buf(), ..FileReport::default() })
+        });
+        assert_eq!(reports.iter().map(|report| report.path.clone()).collect::<Vec<_>>(), files);
+        assert!(high_water_mark.load(Ordering::SeqCst) <= 3);
+    }
+
+    #[test]
+    fn test_corpus_archive() {
+        let dir = sample_corpus();
+        let archive = dir.path().join("corpus.zip");
+        let mut zip = zip::ZipWriter::new(std::fs::File::create(&archive).unwrap());
+        for path in ["alpha/src/main/A.java", "alpha/src/test/ATest.java", "alpha/README.md", "beta/B.java", "beta/C.kt", "not-a-project.java"] {
+            zip.start_file(path, zip::write::SimpleFileOptions::default()).unwrap();
+            std::io::Write::write_all(&mut zip, &std::fs::read(dir.path().join(path)).unwrap()).unwrap();
+        }
+        zip.finish().unwrap();
+
+        let corpus = Corpus::new(dir.path()).exclude(["**/test/**"]).jobs(2);
+        let report = corpus.clone().archive(Some(archive.clone())).run().unwrap();
+        let expected = corpus.run().unwrap();
+        assert_eq!(report.projects.len(), 2);
+        for (project, expected) in report.projects.iter().zip(&expected.projects) {
+            assert_eq!(project.path, archive.join(&expected.name));
+            assert_eq!((&project.name, project.files, project.lines, &project.histogram), (&expected.name, expected.files, expected.lines, &expected.histogram));
+        }
+    }
+
+    #[test]
+    fn test_corpus_by_extension() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), "gamma/A.java", "class A { void f() { a().b(); } }");
+        write(dir.path(), "gamma/B.JAVA", "class B { void g() { c(); } }");
+        write(dir.path(), "gamma/C.kt", "fun h() { d.e().f() }");
+
+        let corpus = Corpus::new(dir.path()).extensions(["java", "kt"]);
+        assert!(corpus.run().unwrap().projects[0].extensions.is_empty());
+
+        let report = corpus.by_extension(true).run().unwrap();
+        assert_eq!(report.projects[0].histogram, BTreeMap::from([(1, 1), (2, 2)]));
+        assert_eq!(report.projects[0].extensions, BTreeMap::from([
+            ("java".to_owned(), BTreeMap::from([(1, 1), (2, 1)])),
+            ("kt".to_owned(), BTreeMap::from([(2, 1)])),
+        ]));
+    }
+
+    #[test]
+    fn test_corpus_rust() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), "gamma/A.java", "class A { void f() { /* /* a */ b().c(); */ } }");
+        write(dir.path(), "gamma/src/lib.rs", "fn f(s: &str) -> usize { /* /* a.b() */ */ s.trim().parse::<usize>().unwrap_or(0) }");
+
+        let report = Corpus::new(dir.path()).extensions(["java", "rs"]).run().unwrap();
+        assert_eq!(report.projects[0].files, 2);
+        assert_eq!(report.projects[0].histogram, BTreeMap::from([(2, 1), (3, 1)]));
+    }
+
+    #[test]
+    fn test_corpus_by_root_kind() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), "gamma/A.java", "class A { void f() { Stream.of(a).map(b); list.stream().count(); } }");
+        write(dir.path(), "gamma/B.java", "class B { void g() { new B().h(); Arrays.asList(c); } }");
+
+        let corpus = Corpus::new(dir.path());
+        assert!(corpus.run().unwrap().projects[0].root_kinds.is_empty());
+
+        let report = corpus.by_root_kind(true).run().unwrap();
+        assert_eq!(report.projects[0].root_kinds, BTreeMap::from([
+            (RootKind::New, BTreeMap::from([(1, 1)])),
+            (RootKind::ClassName, BTreeMap::from([(1, 1), (2, 1)])),
+            (RootKind::Variable, BTreeMap::from([(2, 1)])),
+        ]));
+    }
+}
@@ -0,0 +1,806 @@
+//! The method chain detector: a state machine fed one token at a time.
+//!
+//! Every opening parenthesis, bracket, or brace starts a nested frame with its
+//! own state and chain in progress, the matching closer completes that chain
+//! and returns to the enclosing frame. So in `a(b.c()).d()`, `b.c()` is a chain of
+//! length 1 inside the chain `a(...).d()` of length 2.
+
+use alloc::borrow::ToOwned;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::mem;
+
+use anyhow::bail;
+
+use crate::{Chain, ChainConfig, Language, Token};
+
+/// Where the detector is with respect to the chain in progress.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum State {
+    /// Not in a chain.
+    Start,
+    /// After an identifier that may be the root of a chain, like `a` in `a.b()`.
+    Potential,
+    /// After an identifier following a dot, like `b` in `a.b()`.
+    Member,
+    /// After the closing parenthesis of a call (or the bracket of a subscript).
+    ParenEnd,
+    /// After a dot.
+    Chain,
+    /// After `new`, up to the constructor arguments. Constructors are not links.
+    Constructor,
+    /// After a dimension of an array creation, like `[3]` in `new int[3]`,
+    /// up to its initializer, if any.
+    Dimensions,
+    /// Inside an `import` or `package` declaration, up to its `;`.
+    Declaration,
+}
+
+#[derive(Clone, Debug)]
+struct Frame {
+    state: State,
+    chain: Chain,
+    /// The last identifier seen, the name of the next link.
+    name: String,
+    /// Index of the token that started the chain in progress, see
+    /// [`Chain::start`].
+    root: usize,
+    /// The body of an annotation type (`@interface A { ... }`), where
+    /// `name()` declares an element rather than calls a method.
+    annotation_body: bool,
+    /// The frame is the body of an anonymous class (`new A() { ... }`) or
+    /// nested in one.
+    anonymous_class: bool,
+    /// The last token closed the arguments of a constructor, so a brace
+    /// opens the body of an anonymous class.
+    after_constructor: bool,
+    /// The frame is the body of a method or nested in one.
+    method_body: bool,
+    /// The frame is the body of a class, interface, enum, record, or
+    /// anonymous class, where the members are declared.
+    class_body: bool,
+    /// The frame is the argument list of a method call.
+    call: bool,
+    /// There is something between the parentheses of the call.
+    arguments: bool,
+    /// The call is the root of the enclosing chain, like `x()` in `x().y()`.
+    root_call: bool,
+    /// Number of chains completed directly in the frame.
+    chains: usize,
+    /// The frame is the argument list of a call to one of
+    /// [`ChainConfig::transparent_methods`].
+    transparent: bool,
+    /// The chains completed directly in a transparent frame, held back until
+    /// the frame is closed.
+    wrapped: Vec<Chain>,
+}
+
+impl Default for Frame {
+    fn default() -> Self {
+        Frame { state: State::Start, chain: Chain::default(), name: String::new(), root: 0, annotation_body: false, anonymous_class: false, after_constructor: false, method_body: false, class_body: false, call: false, arguments: false, root_call: false, chains: 0, transparent: false, wrapped: Vec::new() }
+    }
+}
+
+/// Detects method chains in a stream of tokens.
+///
+/// ```
+/// use method_chains::detector::Detector;
+///
+/// let mut detector = Detector::new(10);
+/// for token in method_chains::tokenize("a().b(c.d());") {
+///     detector.feed(token).unwrap();
+/// }
+/// assert_eq!(detector.finish(), vec![1, 2]);
+/// ```
+#[derive(Clone, Debug)]
+pub struct Detector {
+    max_depth: usize,
+    config: ChainConfig,
+    /// The outermost frame, plus one for each open parenthesis, bracket, or
+    /// brace.
+    /// Empty once an unmatched closer stopped the detection.
+    frames: Vec<Frame>,
+    /// The last token opened a subscript of the enclosing frame's chain,
+    /// unless the next token closes it right away (`String[]`).
+    pending_index: bool,
+    /// The last token was `@`.
+    after_at: bool,
+    /// The last token was the `::` of a method reference.
+    after_double_colon: bool,
+    /// The position of the Rust `::` just seen, which is a path separator
+    /// unless type arguments follow.
+    rust_path: Option<usize>,
+    /// Number of `<` open in the Rust type arguments being skipped.
+    type_arguments: usize,
+    /// Number of `{` open in the Rust use tree being skipped, like the one
+    /// of `use a::{b, c};`.
+    declaration_braces: usize,
+    /// An `@interface` was seen, its body is the next brace.
+    pending_annotation_type: bool,
+    /// A class, interface, enum, or record declaration was seen, its body is
+    /// the next brace.
+    pending_class_body: bool,
+    /// The last tokens closed what may be a parameter list, maybe followed by
+    /// a `throws` clause, so a brace opens the body of a method.
+    after_parameters: bool,
+    completed: Vec<Chain>,
+    /// For every closed argument list that was not empty, the number of
+    /// chains among the arguments.
+    argument_fanouts: Vec<usize>,
+    /// Number of tokens fed so far.
+    position: usize,
+    /// The largest [`Detector::depth`] reached so far.
+    max_nesting_depth: usize,
+}
+
+impl Detector {
+    pub fn new(max_depth: usize) -> Self {
+        Detector::with_config(max_depth, &ChainConfig::default())
+    }
+
+    /// A detector allowing `max_depth` levels of nested parentheses,
+    /// brackets, and braces. Opening one more makes [`Detector::feed`] fail.
+    pub fn with_config(max_depth: usize, config: &ChainConfig) -> Self {
+        Detector {
+            max_depth,
+            config: config.clone(),
+            frames: vec![Frame::default()],
+            pending_index: false,
+            after_at: false,
+            after_double_colon: false,
+            rust_path: None,
+            type_arguments: 0,
+            declaration_braces: 0,
+            pending_annotation_type: false,
+            pending_class_body: false,
+            after_parameters: false,
+            completed: Vec::new(),
+            argument_fanouts: Vec::new(),
+            position: 0,
+            max_nesting_depth: 0,
+        }
+    }
+
+    /// The state of the innermost frame, or `None` if an unmatched closing
+    /// parenthesis, bracket, or brace stopped the detection. All later tokens
+    /// are ignored.
+    pub fn state(&self) -> Option<State> {
+        self.frames.last().map(|frame| frame.state)
+    }
+
+    /// Number of currently open parentheses, brackets, and braces.
+    pub fn depth(&self) -> usize {
+        self.frames.len().saturating_sub(1)
+    }
+
+    /// The deepest parentheses, brackets, and braces have nested so far: 4
+    /// for `a(b(c(d())))`. A [`Token::End`] does not reset it.
+    pub fn max_nesting_depth(&self) -> usize {
+        self.max_nesting_depth
+    }
+
+    /// Advances the state machine by one token. Fails if the token nests
+    /// deeper than the maximum depth; the detector should not be fed any
+    /// further then, except for a [`Token::End`] starting over.
+    pub fn feed(&mut self, token: Token) -> anyhow::Result<()> {
+        let position = self.position;
+        self.position += 1;
+        if token == Token::End {
+            // Start over, even after an unmatched closer, keeping the results
+            // and the positions, which count the tokens of all inputs.
+            self.complete_all();
+            *self = Detector {
+                completed: mem::take(&mut self.completed),
+                argument_fanouts: mem::take(&mut self.argument_fanouts),
+                position: self.position,
+                max_nesting_depth: self.max_nesting_depth,
+                ..Detector::with_config(self.max_depth, &self.config)
+            };
+            return Ok(());
+        }
+        let token = if token == Token::SafeDot { Token::Dot } else { token };
+        if let Token::Whitespace(_) = token {
+            return Ok(());
+        }
+        if self.config.language == Language::Rust && self.skip_rust_syntax(&token, position)? {
+            return Ok(());
+        }
+        let state = match self.frames.last() {
+            Some(frame) => frame.state,
+            None => return Ok(()),
+        };
+
+        if self.top().call && token != Token::CloseParen {
+            self.top().arguments = true;
+        }
+
+        if mem::take(&mut self.pending_index) && token != Token::CloseBracket {
+            self.index_found();
+        }
+
+        let after_at = mem::replace(&mut self.after_at, token == Token::Punctuation('@'));
+        if after_at && token == Token::Keyword("interface".to_owned()) {
+            self.pending_annotation_type = true;
+        }
+        let after_double_colon = mem::replace(&mut self.after_double_colon, token == Token::DoubleColon);
+        let method_reference = after_double_colon && self.config.count_method_references;
+        if after_double_colon && self.config.count_method_refs_in_args && self.top().call && self.depth() > 0 {
+            // A link of the chain making the call, after the called method.
+            let link = match &token {
+                Token::Identifier(identifier) => Some(identifier.clone()),
+                Token::New => Some("new".to_owned()),
+                _ => None,
+            };
+            if let Some(link) = link {
+                let caller = self.frames.len() - 2;
+                self.frames[caller].push_link(link);
+            }
+        }
+        let annotation_body = token == Token::OpenBrace && mem::take(&mut self.pending_annotation_type);
+        let anonymous_class = mem::take(&mut self.top().after_constructor) && token == Token::OpenBrace;
+        let class_body = token == Token::OpenBrace && mem::take(&mut self.pending_class_body) || anonymous_class;
+        match &token {
+            // Not the class literal `A.class`.
+            Token::Keyword(keyword) if matches!(keyword.as_str(), "class" | "interface" | "enum") && state != State::Chain
+                => self.pending_class_body = true,
+            Token::Identifier(identifier) if identifier == "record" && state == State::Start
+                && (self.top().class_body || self.depth() == 0) => self.pending_class_body = true,
+            Token::Punctuation(';') => self.pending_class_body = false,
+            _ => {}
+        }
+        let after_parameters = match &token {
+            Token::CloseParen => true,
+            Token::Identifier(_) | Token::Dot | Token::Punctuation(',') => self.after_parameters,
+            Token::Keyword(keyword) => keyword == "throws" && self.after_parameters,
+            _ => false,
+        };
+        // Only the outermost brace after the parameters of a member, not the
+        // header of a record or a block like `if (x) { ... }`.
+        let method_body = mem::replace(&mut self.after_parameters, after_parameters)
+            && token == Token::OpenBrace && !class_body && self.top().class_body && !self.top().method_body;
+        let depth = self.depth();
+
+        macro_rules! goto {
+            ($state:ident) => { self.top().state = State::$state }
+        }
+        macro_rules! method_found {
+            () => { self.top().link_found() }
+        }
+        macro_rules! field_found {
+            () => { if self.config.count_fields { self.top().link_found() } }
+        }
+        macro_rules! call_found {
+            () => { self.top().push_link("()".to_owned()) }
+        }
+        macro_rules! chain_complete {
+            () => { self.chain_complete() }
+        }
+        macro_rules! method_reference_found {
+            ($name:expr) => {{ self.top().root = position; self.top().push_link($name); chain_complete!(); goto!(Start); }}
+        }
+        macro_rules! element_declared {
+            () => {{ self.top().chain = Chain::default(); goto!(Start); }}
+        }
+        macro_rules! stop {
+            () => {{ self.chain_complete(); self.close(); }}
+        }
+        macro_rules! open {
+            () => { self.open(false)? };
+            (index) => { self.open(self.config.count_indexing)? };
+            (call) => {{
+                let transparent = self.calls_transparent_method();
+                self.open(false)?;
+                self.top().call = true;
+                self.top().transparent = transparent;
+            }};
+        }
+
+        match (state, token) {
+            (State::Declaration, Token::Punctuation(';')) => { self.declaration_braces = 0; goto!(Start); }
+            (State::Declaration, Token::CloseParen)  => { stop!(); }
+            (State::Declaration, Token::CloseBracket) => { stop!(); }
+            (State::Declaration, Token::OpenBrace)   => { self.declaration_braces += 1; }
+            (State::Declaration, Token::CloseBrace) if self.declaration_braces > 0
+                                                    => { self.declaration_braces -= 1; }
+            (State::Declaration, Token::CloseBrace)  => { stop!(); }
+            (State::Declaration, _)                  => { /*skip to the end of the declaration*/ }
+            (_, Token::Keyword(ref keyword)) if crate::is_declaration_keyword(keyword)
+                                                    => { chain_complete!(); goto!(Declaration); }
+            (_, Token::Ellipsis)                    => { /*varargs, ignore*/ }
+            (_, Token::Identifier(identifier)) if method_reference
+                                                    => { method_reference_found!(identifier); }
+            (_, Token::New) if method_reference     => { method_reference_found!("new".to_owned()); }
+
+            (State::Start, Token::OpenParen)        => { self.top().root = position; goto!(ParenEnd); open!(); /*maybe a receiver*/ }
+            (State::Start, Token::OpenBracket)      => { open!(); }
+            (State::Start, Token::CloseParen)       => { stop!(); }
+            (State::Start, Token::OpenBrace)        => { open!(); }
+            (State::Start, Token::CloseBracket)     => { stop!(); }
+            (State::Start, Token::CloseBrace)       => { stop!(); }
+            (State::Start, Token::Punctuation(';')) => { chain_complete!(); /*a chain still open after a call, like `f()()`*/ }
+            (State::Start, Token::Identifier(identifier)) => { self.top().name = identifier; self.top().root = position; goto!(Potential); }
+            (State::Start, Token::New)              => { self.top().root = position; goto!(Constructor); }
+            (State::Start, Token::String(ref literal)) if literal.starts_with('"')
+                                                    => { self.top().root = position; goto!(ParenEnd); /*like a call, not a link*/ }
+            (State::Start, _)                       => { /*nothing*/ }
+
+            (State::Constructor, Token::OpenParen)  => { goto!(ParenEnd); self.top().after_constructor = true; open!(); /*not a method*/ }
+            (State::Constructor, Token::OpenBracket) => { goto!(Dimensions); open!(); }
+            (State::Constructor, Token::OpenBrace)  => { goto!(ParenEnd); open!(); /*array initializer*/ }
+            (State::Constructor, Token::Punctuation(';')) => { goto!(Start); chain_complete!(); }
+            (State::Constructor, _)                 => { /*nothing*/ }
+
+            (State::Dimensions, Token::OpenBracket) => { open!(); }
+            (State::Dimensions, Token::OpenBrace)   => { goto!(ParenEnd); open!(); /*array initializer*/ }
+            (State::Dimensions, Token::CloseParen)  => { stop!(); }
+            (State::Dimensions, Token::CloseBracket) => { stop!(); }
+            (State::Dimensions, Token::CloseBrace)  => { stop!(); }
+            (State::Dimensions, Token::Dot)         => { goto!(Chain); }
+            (State::Dimensions, _)                  => { goto!(Start); chain_complete!(); }
+
+            (State::Potential, Token::OpenParen)    => { method_found!(); goto!(ParenEnd); open!(call); self.top().root_call = true; }
+            (State::Potential, Token::OpenBracket)  => { goto!(ParenEnd); open!(index); /*not a method*/ }
+            (State::Potential, Token::CloseParen)   => { stop!(); }
+            (State::Potential, Token::OpenBrace)    => { goto!(Start); chain_complete!(); open!(); }
+            (State::Potential, Token::CloseBracket) => { stop!(); }
+            (State::Potential, Token::CloseBrace)   => { stop!(); }
+            (State::Potential, Token::Dot)          => { goto!(Chain); }
+            (State::Potential, _)                   => { goto!(Start); chain_complete!(); }
+
+            (State::Member, Token::OpenParen)       => { method_found!(); goto!(ParenEnd); open!(call); }
+            (State::Member, Token::OpenBracket)     => { field_found!(); goto!(ParenEnd); open!(index); }
+            (State::Member, Token::CloseParen)      => { field_found!(); stop!(); }
+            (State::Member, Token::OpenBrace)       => { field_found!(); goto!(Start); chain_complete!(); open!(); }
+            (State::Member, Token::CloseBracket)    => { field_found!(); stop!(); }
+            (State::Member, Token::CloseBrace)      => { field_found!(); stop!(); }
+            (State::Member, Token::Dot)             => { field_found!(); goto!(Chain); }
+            (State::Member, _)                      => { field_found!(); goto!(Start); chain_complete!(); }
+
+            (State::ParenEnd, Token::OpenParen) if self.config.count_immediate_calls
+                                                    => { call_found!(); open!(); }
+            (State::ParenEnd, Token::OpenParen)     => { open!(); /*the chain goes on after the immediate call*/ }
+            (State::ParenEnd, Token::OpenBracket) if self.config.count_indexing
+                                                    => { open!(index); }
+            (State::ParenEnd, Token::OpenBracket)   => { goto!(Start); open!(); }
+            (State::ParenEnd, Token::Punctuation(';')) if self.declares_element()
+                                                    => { element_declared!(); }
+            (State::ParenEnd, Token::Keyword(ref keyword)) if keyword == "default" && self.declares_element()
+                                                    => { element_declared!(); }
+            (State::ParenEnd, Token::CloseParen)    => { stop!(); }
+            (State::ParenEnd, Token::OpenBrace)     => { goto!(Start); chain_complete!(); open!(); }
+            (State::ParenEnd, Token::CloseBracket)  => { stop!(); }
+            (State::ParenEnd, Token::CloseBrace)    => { stop!(); }
+            (State::ParenEnd, Token::Dot)           => { goto!(Chain); }
+            (State::ParenEnd, Token::Identifier(identifier)) => {
+                chain_complete!(); self.top().name = identifier; self.top().root = position; goto!(Potential); /*after a cast or a condition*/
+            }
+            (State::ParenEnd, Token::New)           => { chain_complete!(); self.top().root = position; goto!(Constructor); }
+            (State::ParenEnd, _)                    => { goto!(Start); chain_complete!(); }
+
+            (State::Chain, Token::OpenParen)        => { goto!(Start); open!(); }
+            (State::Chain, Token::OpenBracket)      => { goto!(Start); open!(); }
+            (State::Chain, Token::CloseParen)       => { stop!(); }
+            (State::Chain, Token::OpenBrace)        => { goto!(Start); chain_complete!(); open!(); }
+            (State::Chain, Token::CloseBracket)     => { stop!(); }
+            (State::Chain, Token::CloseBrace)       => { stop!(); }
+            (State::Chain, Token::Identifier(identifier)) => { self.top().name = identifier; goto!(Member); }
+            (State::Chain, _)                       => { goto!(Start); chain_complete!(); }
+        }
+
+        if annotation_body && self.depth() > depth {
+            self.top().annotation_body = true;
+        }
+        if anonymous_class && self.depth() > depth {
+            self.top().anonymous_class = true;
+        }
+        if method_body && self.depth() > depth {
+            self.top().method_body = true;
+        }
+        if class_body && self.depth() > depth {
+            self.top().class_body = true;
+        }
+        Ok(())
+    }
+
+    /// Feeds every token of `tokens`, stopping at the first error.
+    pub fn feed_all<I: IntoIterator<Item = Token>>(&mut self, tokens: I) -> anyhow::Result<()> {
+        tokens.into_iter().try_for_each(|token| self.feed(token))
+    }
+
+    /// Removes and returns the chains completed so far, in the order they
+    /// were completed.
+    pub fn drain_chains(&mut self) -> vec::Drain<'_, Chain> {
+        self.completed.drain(..)
+    }
+
+    /// For every argument list closed so far that was not empty, in the order
+    /// they were closed, the number of chains directly among its arguments:
+    /// 3 for the `f` of `f(a().b(), c(), d.e())`, but 1 for the `f` of
+    /// `f(g(a().b()))`, whose only argument is the chain `g(...)`.
+    pub fn argument_fanouts(&self) -> &[usize] {
+        &self.argument_fanouts
+    }
+
+    /// Ends the input and returns the lengths of all chains not drained yet.
+    pub fn finish(self) -> Vec<usize> {
+        self.finish_chains().iter().map(Chain::length).collect()
+    }
+
+    /// Ends the input and returns all chains not drained yet.
+    ///
+    /// End of input completes whatever chains are in progress, even without
+    /// a terminating `;` or closing parentheses and braces. A dangling dot
+    /// (`a().b().`) has nothing after it to count, so it is ignored and the
+    /// chain so far is recorded.
+    pub fn finish_chains(mut self) -> Vec<Chain> {
+        self.complete_all();
+        self.completed
+    }
+
+    // Completes the chains in progress in all frames, innermost first, and
+    // closes the frames.
+    fn complete_all(&mut self) {
+        if mem::take(&mut self.pending_index) {
+            self.index_found();
+        }
+        while let Some(frame) = self.frames.last_mut() {
+            if frame.state == State::Member && self.config.count_fields {
+                frame.link_found();
+            }
+            self.chain_complete();
+            if let Some(frame) = self.frames.pop() {
+                self.leave(frame);
+            }
+        }
+    }
+
+    // Skips the `?` operator and the type arguments of `collect::<Vec<_>>()`,
+    // and feeds a `::` path separator as a dot once the token after it shows
+    // that it is one. Returns whether `token` was skipped.
+    fn skip_rust_syntax(&mut self, token: &Token, position: usize) -> anyhow::Result<bool> {
+        if self.type_arguments > 0 {
+            match token {
+                Token::Punctuation('<') => self.type_arguments += 1,
+                Token::Punctuation('>') => self.type_arguments -= 1,
+                _ => {}
+            }
+            return Ok(true);
+        }
+        if let Some(path_position) = self.rust_path.take() {
+            if *token == Token::Punctuation('<') {
+                self.type_arguments = 1;
+                return Ok(true);
+            }
+            self.position = path_position;
+            self.feed(Token::Dot)?;
+            self.position = position + 1;
+        }
+        match token {
+            Token::DoubleColon => { self.rust_path = Some(position); Ok(true) }
+            Token::Punctuation('?') => Ok(true),
+            _ => Ok(false),
+        }
+    }
+
+    // `int size();` or `int size() default 1;` directly in the body of an
+    // annotation type: the single link is the name of an element.
+    fn declares_element(&self) -> bool {
+        self.frames.last().is_some_and(|frame| frame.annotation_body && frame.chain.length() == 1)
+    }
+
+    fn top(&mut self) -> &mut Frame {
+        self.frames.last_mut().expect("detection already stopped")
+    }
+
+    fn open(&mut self, index: bool) -> anyhow::Result<()> {
+        let depth = self.frames.len();
+        if depth > self.max_depth {
+            bail!("Chain method detection reached recursion depth of {} (max: {}). \
+                   Stopping recursion, keeping partial result.", depth, self.max_depth);
+        }
+        let (anonymous_class, method_body) = self.frames.last()
+            .map_or((false, false), |frame| (frame.anonymous_class, frame.method_body));
+        self.frames.push(Frame { anonymous_class, method_body, ..Frame::default() });
+        self.max_nesting_depth = self.max_nesting_depth.max(self.depth());
+        self.pending_index = index;
+        Ok(())
+    }
+
+    // Whether the link just found is a call to a transparent method.
+    fn calls_transparent_method(&self) -> bool {
+        self.frames.last()
+            .and_then(|frame| frame.chain.links.last())
+            .is_some_and(|link| self.config.transparent_methods.contains(link))
+    }
+
+    fn close(&mut self) {
+        if let Some(frame) = self.frames.pop() {
+            if frame.call && frame.arguments {
+                self.argument_fanouts.push(frame.chains);
+            }
+            self.leave(frame);
+        }
+    }
+
+    // Passes what is left of the popped `frame` on to the enclosing one.
+    fn leave(&mut self, mut frame: Frame) {
+        let enclosing = match self.frames.last_mut() {
+            Some(enclosing) => enclosing,
+            None => {
+                self.completed.append(&mut frame.wrapped);
+                return;
+            }
+        };
+        if frame.transparent {
+            // The call is no link, and a single chain in its arguments goes
+            // on in the chain of the call: `requireNonNull(a.b()).c()` is
+            // `a.b().c()`.
+            enclosing.chain.links.pop();
+            if frame.wrapped.len() == 1 {
+                let inner = frame.wrapped.remove(0);
+                if enclosing.chain.links.is_empty() {
+                    enclosing.chain.start = inner.start;
+                }
+                enclosing.chain.links.extend(inner.links);
+                enclosing.chain.calls_with_arguments += inner.calls_with_arguments;
+            }
+            self.completed.append(&mut frame.wrapped);
+        } else if frame.call && frame.arguments {
+            enclosing.chain.calls_with_arguments += 1;
+        } else if frame.root_call && self.config.count_fields && self.config.treat_noarg_calls_as_fields {
+            // The accessor is the root, as the field would be: `x().y()` is
+            // `x.y`.
+            enclosing.chain.links.pop();
+        }
+    }
+
+    // The subscript opened by the last token is a link of the enclosing chain.
+    fn index_found(&mut self) {
+        let enclosing = self.frames.len() - 2;
+        self.frames[enclosing].push_link("[]".to_owned());
+    }
+
+    fn chain_complete(&mut self) {
+        let Frame { anonymous_class, method_body, .. } = *self.top();
+        let mut chain = mem::take(&mut self.top().chain);
+        chain.in_anonymous_class = anonymous_class;
+        if self.config.bodies_only && !method_body {
+            return;
+        }
+        if chain.length() != 0 {
+            self.top().chains += 1;
+            if self.top().transparent {
+                self.top().wrapped.push(chain);
+            } else {
+                self.completed.push(chain);
+            }
+        }
+    }
+}
+
+impl Frame {
+    fn link_found(&mut self) {
+        let name = mem::take(&mut self.name);
+        self.push_link(name);
+    }
+
+    fn push_link(&mut self, link: String) {
+        if self.chain.links.is_empty() {
+            self.chain.start = self.root;
+        }
+        self.chain.links.push(link);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn identifier(name: &str) -> Token {
+        Token::Identifier(name.to_owned())
+    }
+
+    #[test]
+    fn test_potential_chain_member() {
+        let mut detector = Detector::new(10);
+        assert_eq!(detector.state(), Some(State::Start));
+        detector.feed(identifier("a")).unwrap();
+        assert_eq!(detector.state(), Some(State::Potential));
+        detector.feed(Token::Dot).unwrap();
+        assert_eq!(detector.state(), Some(State::Chain));
+        detector.feed(identifier("b")).unwrap();
+        assert_eq!(detector.state(), Some(State::Member));
+        detector.feed(Token::OpenParen).unwrap();
+        assert_eq!((detector.state(), detector.depth()), (Some(State::Start), 1));
+        detector.feed(Token::CloseParen).unwrap();
+        assert_eq!((detector.state(), detector.depth()), (Some(State::ParenEnd), 0));
+        assert_eq!(detector.drain_chains().count(), 0);
+        detector.feed(Token::Punctuation(';')).unwrap();
+        assert_eq!(detector.state(), Some(State::Start));
+        assert_eq!(detector.drain_chains().map(|chain| chain.links).collect::<Vec<_>>(), vec![vec!["b".to_owned()]]);
+        assert_eq!(detector.finish(), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn test_constructor() {
+        let mut detector = Detector::new(10);
+        detector.feed(Token::New).unwrap();
+        assert_eq!(detector.state(), Some(State::Constructor));
+        detector.feed(identifier("A")).unwrap();
+        assert_eq!(detector.state(), Some(State::Constructor));
+        detector.feed_all(vec![Token::OpenParen, Token::CloseParen]).unwrap();
+        assert_eq!(detector.state(), Some(State::ParenEnd));
+        detector.feed_all(vec![Token::Dot, identifier("b"), Token::OpenParen, Token::CloseParen]).unwrap();
+        assert_eq!(detector.finish(), vec![1]);
+    }
+
+    #[test]
+    fn test_declaration() {
+        let mut detector = Detector::new(10);
+        detector.feed(Token::Keyword("import".to_owned())).unwrap();
+        assert_eq!(detector.state(), Some(State::Declaration));
+        detector.feed_all(vec![identifier("a"), Token::Dot, identifier("b")]).unwrap();
+        assert_eq!(detector.state(), Some(State::Declaration));
+        detector.feed(Token::Punctuation(';')).unwrap();
+        assert_eq!(detector.state(), Some(State::Start));
+        assert_eq!(detector.finish(), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn test_nested_chains_complete_first() {
+        let mut detector = Detector::new(10);
+        detector.feed_all(crate::tokenize("a(b.c().d()).e()")).unwrap();
+        assert_eq!(detector.depth(), 0);
+        assert_eq!(detector.drain_chains().map(|chain| chain.length()).collect::<Vec<_>>(), vec![2]);
+        assert_eq!(detector.finish(), vec![2]);
+    }
+
+    #[test]
+    fn test_brace() {
+        let mut detector = Detector::new(10);
+        detector.feed_all(crate::tokenize("a().b() { c.d()")).unwrap();
+        assert_eq!((detector.state(), detector.depth()), (Some(State::ParenEnd), 1));
+        assert_eq!(detector.drain_chains().map(|chain| chain.length()).collect::<Vec<_>>(), vec![2]);
+        detector.feed(Token::CloseBrace).unwrap();
+        assert_eq!((detector.state(), detector.depth()), (Some(State::Start), 0));
+        assert_eq!(detector.finish(), vec![1]);
+
+        let mut detector = Detector::new(10);
+        detector.feed_all(vec![Token::New, identifier("int"), Token::OpenBracket, Token::CloseBracket]).unwrap();
+        assert_eq!(detector.state(), Some(State::Dimensions));
+        detector.feed(Token::OpenBrace).unwrap();
+        assert_eq!(detector.depth(), 1);
+        detector.feed(Token::CloseBrace).unwrap();
+        assert_eq!((detector.state(), detector.depth()), (Some(State::ParenEnd), 0));
+    }
+
+    #[test]
+    fn test_unmatched_closer_stops() {
+        let mut detector = Detector::new(10);
+        detector.feed_all(crate::tokenize("a().b()) c().d();")).unwrap();
+        assert_eq!(detector.state(), None);
+        assert_eq!(detector.finish(), vec![2]);
+    }
+
+    #[test]
+    fn test_end() {
+        let config = crate::TokenizerConfig { end: true, ..crate::TokenizerConfig::default() };
+        let first = crate::tokenize_with("a().b(c", &config).unwrap();
+        assert_eq!(first.last(), Some(&Token::End));
+        let second = crate::tokenize_with(".d()))", &config).unwrap();
+
+        let mut detector = Detector::new(10);
+        detector.feed_all(first.iter().chain(&second).filter(|token| **token != Token::End).cloned()).unwrap();
+        assert_eq!(detector.finish(), vec![1, 2]);
+
+        // The closers left over from the first input do not stop the second.
+        let mut detector = Detector::new(10);
+        detector.feed_all(first.iter().chain(&second).cloned()).unwrap();
+        assert_eq!(detector.state(), Some(State::Start));
+        detector.feed_all(crate::tokenize("g().h();")).unwrap();
+        let chains = detector.finish_chains();
+        assert_eq!(chains.iter().map(Chain::length).collect::<Vec<_>>(), vec![2, 1, 2]);
+        assert_eq!(chains[2].start, first.len() + second.len());
+    }
+
+    #[test]
+    fn test_max_depth() {
+        let mut detector = Detector::new(1);
+        detector.feed(Token::OpenParen).unwrap();
+        assert_eq!(detector.depth(), 1);
+        assert!(detector.feed(Token::OpenParen).is_err());
+    }
+
+    #[test]
+    fn test_pending_index() {
+        let config = ChainConfig { count_indexing: true, ..ChainConfig::default() };
+        let mut detector = Detector::with_config(10, &config);
+        detector.feed_all(vec![identifier("a"), Token::OpenBracket]).unwrap();
+        assert_eq!(detector.clone().finish(), vec![1]);
+        detector.feed(Token::CloseBracket).unwrap();
+        assert_eq!(detector.finish(), Vec::<usize>::new());
+    }
+
+    // Both complete the chain of the innermost frame only: `,` separates the
+    // arguments of a call that goes on after them, `;` ends a statement.
+    #[test]
+    fn test_comma_and_semicolon() {
+        for separator in [',', ';'] {
+            let mut detector = Detector::new(10);
+            detector.feed_all(crate::tokenize(&format!("a().b(){} c()", separator))).unwrap();
+            assert_eq!(detector.drain_chains().map(|chain| chain.length()).collect::<Vec<_>>(), vec![2]);
+            assert_eq!((detector.state(), detector.depth()), (Some(State::ParenEnd), 0));
+            assert_eq!(detector.finish(), vec![1]);
+        }
+
+        // A semicolon ends the chain in progress whatever the state.
+        for (program, expected) in [("f()(); g();", vec![1, 1]), ("f()(); g().h();", vec![1, 2]), ("new A; g();", vec![1])] {
+            let mut detector = Detector::new(10);
+            detector.feed_all(crate::tokenize(program)).unwrap();
+            assert_eq!(detector.finish(), expected, "{}", program);
+        }
+
+        let mut detector = Detector::new(10);
+        detector.feed_all(crate::tokenize("f(a().b(), c()")).unwrap();
+        assert_eq!(detector.drain_chains().map(|chain| chain.length()).collect::<Vec<_>>(), vec![2]);
+        assert_eq!((detector.state(), detector.depth()), (Some(State::ParenEnd), 1));
+        detector.feed_all(crate::tokenize(").d();")).unwrap();
+        assert_eq!(detector.drain_chains().map(|chain| chain.length()).collect::<Vec<_>>(), vec![1, 2]);
+    }
+
+    #[test]
+    fn test_anonymous_class() {
+        let mut detector = Detector::new(10);
+        detector.feed_all(crate::tokenize(r#"
+            executor.submit(new Runnable() {
+                public void run() { queue.poll().run(); log(f(g.h())); }
+            });
+            new A(b.c()).d();
+            new int[] { e.f() };
+        "#)).unwrap();
+        let chains = detector.finish_chains().into_iter()
+            .map(|chain| (chain.links.join("."), chain.in_anonymous_class))
+            .collect::<Vec<_>>();
+        assert_eq!(chains, vec![
+            ("poll.run".to_owned(), true), ("h".to_owned(), true), ("f".to_owned(), true), ("log".to_owned(), true),
+            ("submit".to_owned(), false), ("c".to_owned(), false), ("d".to_owned(), false), ("f".to_owned(), false),
+        ]);
+    }
+
+    #[test]
+    fn test_bodies_only() {
+        let program = r#"
+            class A {
+                static final List<String> NAMES = Stream.of("a").map(String::trim).collect(toList());
+                static { REGISTRY.register(A.class).enable(); }
+                A(int x) { this.x = x.y(); }
+                void f() throws IOException, java.io.UncheckedIOException {
+                    if (x) { list.stream().count(); }
+                    Runnable r = () -> { a.b(); };
+                }
+                Runnable r = new Runnable() { String s = c.d(); public void run() { e.f(); } };
+            }
+        "#;
+        let links = |config: &ChainConfig| {
+            let mut detector = Detector::with_config(10, config);
+            detector.feed_all(crate::tokenize(program)).unwrap();
+            detector.finish_chains().into_iter().map(|chain| chain.links.join(".")).collect::<Vec<_>>()
+        };
+        assert_eq!(links(&ChainConfig::default()),
+                   vec!["toList", "of.map.collect", "register.enable", "A", "y", "stream.count", "b", "d", "f"]);
+        assert_eq!(links(&ChainConfig { bodies_only: true, ..ChainConfig::default() }), vec!["y", "stream.count", "b", "f"]);
+
+        // Blocks with parentheses outside of methods, and a record header.
+        let config = ChainConfig { bodies_only: true, ..ChainConfig::default() };
+        for program in ["class A { static { if (x) { a.b(); } } }", "class A { { for(;;) { a.b(); } } }",
+                        "record P(int x) { static int q = a.b(); }"] {
+            let mut detector = Detector::with_config(10, &config);
+            detector.feed_all(crate::tokenize(program)).unwrap();
+            assert_eq!(detector.finish(), Vec::<usize>::new(), "{}", program);
+        }
+        let mut detector = Detector::with_config(10, &config);
+        detector.feed_all(crate::tokenize("record P(int x) { int q = a.b(); int q() { return x.y(); } }")).unwrap();
+        assert_eq!(detector.finish(), vec![1]);
+    }
+}
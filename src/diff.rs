@@ -0,0 +1,73 @@
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::Context;
+
+pub const DIFF_HEADER: &str = "project, chain length, delta";
+
+/// Computes `new - old` frequency for every `(project, chain length)` pair
+/// present in either histogram. Pairs whose frequency did not change are
+/// omitted; pairs present in only one input are reported with the whole
+/// frequency as a positive (added) or negative (removed) delta.
+pub fn diff_histograms(old: &[(String, usize, usize)], new: &[(String, usize, usize)]) -> Vec<(String, usize, i64)> {
+    let mut deltas: BTreeMap<(String, usize), i64> = BTreeMap::new();
+    for (project, chain_length, frequency) in old {
+        *deltas.entry((project.clone(), *chain_length)).or_insert(0) -= *frequency as i64;
+    }
+    for (project, chain_length, frequency) in new {
+        *deltas.entry((project.clone(), *chain_length)).or_insert(0) += *frequency as i64;
+    }
+    deltas.into_iter()
+        .filter(|(_, delta)| *delta != 0)
+        .map(|((project, chain_length), delta)| (project, chain_length, delta))
+        .collect()
+}
+
+/// Diffs two histogram CSV files produced by the analysis and writes the
+/// deltas as CSV to `writer`.
+pub fn diff_files<W: Write>(old: &Path, new: &Path, writer: &mut W) -> anyhow::Result<()> {
+    let read = |path: &Path| -> anyhow::Result<Vec<(String, usize, usize)>> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Cannot read file {:?}", path))?;
+        crate::output::parse_csv(&contents)
+            .with_context(|| format!("Cannot parse file {:?}", path))
+    };
+    let deltas = diff_histograms(&read(old)?, &read(new)?);
+    writeln!(writer, "{}", DIFF_HEADER)?;
+    for (project, chain_length, delta) in deltas {
+        writeln!(writer, "{}, {}, {}", crate::output::csv_field(&project), chain_length, delta)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let old = dir.path().join("old.csv");
+        let new = dir.path().join("new.csv");
+        std::fs::write(&old, "project, chain length, frequency\n\
+                              Calculator, 2, 8\n\
+                              Calculator, 1, 416\n\
+                              lan-chat-app, 7, 1\n\
+                              lan-chat-app, 1, 205\n").unwrap();
+        std::fs::write(&new, "project, chain length, frequency\n\
+                              Calculator, 3, 1\n\
+                              Calculator, 2, 8\n\
+                              Calculator, 1, 420\n\
+                              onlinebookstore, 1, 226\n").unwrap();
+
+        let mut output = Vec::new();
+        diff_files(&old, &new, &mut output).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "project, chain length, delta\n\
+                                                        Calculator, 1, 4\n\
+                                                        Calculator, 3, 1\n\
+                                                        lan-chat-app, 1, -205\n\
+                                                        lan-chat-app, 7, -1\n\
+                                                        onlinebookstore, 1, 226\n");
+    }
+}
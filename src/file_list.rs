@@ -0,0 +1,168 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+
+use crate::ChainConfig;
+use crate::histogram;
+
+/// How files read from a file list are aggregated into histograms.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum GroupBy {
+    /// All files in a single histogram named `all`.
+    All,
+    /// One histogram per directory containing the files.
+    Directory,
+    /// One histogram per file.
+    File,
+}
+
+/// Parses a file list: one path per line. Blank lines and lines starting with
+/// `#` are ignored, surrounding whitespace is trimmed.
+pub fn parse_file_list(contents: &str) -> Vec<PathBuf> {
+    contents.lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(PathBuf::from)
+        .collect()
+}
+
+pub fn read_file_list(path: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("Cannot read file list {:?}", path))?;
+    Ok(parse_file_list(&contents))
+}
+
+pub fn group_key(path: &Path, group_by: GroupBy) -> String {
+    match group_by {
+        GroupBy::All => "all".to_owned(),
+        GroupBy::Directory => path.parent()
+            .map(|parent| parent.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+        GroupBy::File => path.to_string_lossy().into_owned(),
+    }
+}
+
+/// The deepest directory containing every file of `paths`: `/home/me/repo`
+/// for `/home/me/repo/src/A.java` and `/home/me/repo/test/B.java`. Empty if
+/// they have nothing in common, or if there are no paths.
+pub fn common_root(paths: &[PathBuf]) -> PathBuf {
+    let mut directories = paths.iter().map(|path| path.parent().unwrap_or(Path::new("")));
+    let Some(first) = directories.next() else {
+        return PathBuf::new();
+    };
+    let mut root: Vec<_> = first.components().collect();
+    for directory in directories {
+        let common = root.iter().zip(directory.components()).take_while(|(a, b)| **a == *b).count();
+        root.truncate(common);
+    }
+    root.iter().collect()
+}
+
+/// `group`, a path named by [`group_key`], relative to `root`: `src/A.java`
+/// for `/home/me/repo/src/A.java` in `/home/me/repo`, and `.` for the root
+/// itself. Groups outside `root` are left as they are.
+pub fn relative_group(group: &str, root: &Path) -> String {
+    match Path::new(group).strip_prefix(root) {
+        Ok(relative) if relative.as_os_str().is_empty() => ".".to_owned(),
+        Ok(relative) => relative.to_string_lossy().into_owned(),
+        Err(_) => group.to_owned(),
+    }
+}
+
+/// The histograms of the files of a file list, see [`analyze_file_list`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FileListReport {
+    /// A histogram per group.
+    pub groups: BTreeMap<String, BTreeMap<usize, usize>>,
+    /// Number of files analyzed, leaving out those that could not be.
+    pub files: usize,
+    /// Total size of the analyzed files.
+    pub bytes: u64,
+}
+
+/// Analyzes every file in `paths` and folds the chain lengths into one
+/// histogram per group. Files that cannot be analyzed are reported and skipped.
+/// With `mmap`, files are memory-mapped rather than read.
+pub fn analyze_file_list(paths: &[PathBuf], group_by: GroupBy, max_depth: usize, config: &ChainConfig, mmap: bool) -> FileListReport {
+    let mut report = FileListReport::default();
+    for path in paths {
+        let group = report.groups.entry(group_key(path, group_by)).or_default();
+        let config = config.for_path(path);
+        let method_chain_counts = if mmap {
+            crate::analyze_path_mmap_with(path, max_depth, &config)
+        } else {
+            crate::analyze_path_with(path, max_depth, &config)
+        };
+        match method_chain_counts {
+            Err(error) => {
+                tracing::warn!(path = ?path, error = %error, "skipping file");
+            }
+            Ok(method_chain_counts) => {
+                for chain_length in method_chain_counts {
+                    histogram::add_to_histogram(group, chain_length, 1);
+                }
+                report.files += 1;
+                report.bytes += std::fs::metadata(path).map(|metadata| metadata.len()).unwrap_or(0);
+            }
+        }
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_file_list() {
+        let contents = "# changed files\nsrc/A.java\n\n   \n  src/B.java  \n#src/C.java\n";
+        assert_eq!(parse_file_list(contents), vec![PathBuf::from("src/A.java"), PathBuf::from("src/B.java")]);
+    }
+
+    #[test]
+    fn test_common_root() {
+        let paths = [PathBuf::from("/home/me/repo/src/A.java"), PathBuf::from("/home/me/repo/test/B.java"), PathBuf::from("/home/me/repo/C.java")];
+        let root = common_root(&paths);
+        assert_eq!(root, PathBuf::from("/home/me/repo"));
+        assert_eq!(paths.iter().map(|path| relative_group(&group_key(path, GroupBy::File), &root)).collect::<Vec<_>>(), vec!["src/A.java", "test/B.java", "C.java"]);
+        assert_eq!(relative_group(&group_key(&paths[2], GroupBy::Directory), &root), ".");
+
+        assert_eq!(common_root(&paths[..1]), PathBuf::from("/home/me/repo/src"));
+        assert_eq!(common_root(&[PathBuf::from("src/A.java"), PathBuf::from("test/B.java")]), PathBuf::new());
+        assert_eq!(common_root(&[]), PathBuf::new());
+    }
+
+    #[test]
+    fn test_analyze_file_list() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("a")).unwrap();
+        std::fs::create_dir(dir.path().join("b")).unwrap();
+        let first = dir.path().join("a").join("First.java");
+        let second = dir.path().join("b").join("Second.java");
+        std::fs::write(&first, "class First { void f() { a().b(); } }").unwrap();
+        std::fs::write(&second, "class Second { void g() { c(); d.e().f(); } }").unwrap();
+
+        let manifest = dir.path().join("files.txt");
+        std::fs::write(&manifest, format!("# two files\n{}\n\n{}\n", first.display(), second.display())).unwrap();
+        let paths = read_file_list(&manifest).unwrap();
+        assert_eq!(paths, vec![first.clone(), second.clone()]);
+
+        let report = analyze_file_list(&paths, GroupBy::All, 10, &ChainConfig::default(), false);
+        let expected = BTreeMap::from([("all".to_owned(), BTreeMap::from([(1, 1), (2, 2)]))]);
+        assert_eq!(report.groups, expected);
+        assert_eq!((report.files, report.bytes), (2, std::fs::metadata(&first).unwrap().len() + std::fs::metadata(&second).unwrap().len()));
+
+        let groups = analyze_file_list(&paths, GroupBy::Directory, 10, &ChainConfig::default(), true).groups;
+        let expected = BTreeMap::from([
+            (group_key(&first, GroupBy::Directory), BTreeMap::from([(2, 1)])),
+            (group_key(&second, GroupBy::Directory), BTreeMap::from([(1, 1), (2, 1)])),
+        ]);
+        assert_eq!(groups, expected);
+
+        // A file that fails to be read counts neither as a file nor by size.
+        let paths = vec![first.clone(), dir.path().join("Missing.java")];
+        let report = analyze_file_list(&paths, GroupBy::All, 10, &ChainConfig::default(), false);
+        assert_eq!((report.files, report.bytes), (1, std::fs::metadata(&first).unwrap().len()));
+    }
+}
@@ -0,0 +1,261 @@
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::vec::Vec;
+
+/// A histogram bucket counter. Additions saturate at the maximum value
+/// instead of wrapping around, so an overflowing bucket reads as "at least
+/// this many" rather than as a small, wrong number.
+pub trait Frequency: Copy + Ord {
+    const ZERO: Self;
+    const ONE: Self;
+    const MAX: Self;
+    fn checked_add(self, other: Self) -> Option<Self>;
+}
+
+macro_rules! impl_frequency {
+    ($($t:ty),*) => {
+        $(impl Frequency for $t {
+            const ZERO: Self = 0;
+            const ONE: Self = 1;
+            const MAX: Self = <$t>::MAX;
+            fn checked_add(self, other: Self) -> Option<Self> {
+                <$t>::checked_add(self, other)
+            }
+        })*
+    }
+}
+
+impl_frequency!(u8, u16, u32, u64, usize);
+
+/// Adds `frequency` to the bucket for `chain_length`. Returns `false` if the
+/// bucket overflowed and was saturated.
+pub fn add_to_histogram<F: Frequency>(histogram: &mut BTreeMap<usize, F>, chain_length: usize, frequency: F) -> bool {
+    let bucket = histogram.entry(chain_length).or_insert(F::ZERO);
+    match bucket.checked_add(frequency) {
+        Some(sum) => { *bucket = sum; true }
+        None => { *bucket = F::MAX; false }
+    }
+}
+
+/// Folds chain lengths into a histogram. Overflowing buckets saturate, see
+/// [`add_to_histogram`].
+pub fn histogram<F: Frequency, I: IntoIterator<Item = usize>>(chain_lengths: I) -> BTreeMap<usize, F> {
+    chain_lengths.into_iter().fold(BTreeMap::new(), |mut accumulator, chain_length| {
+        add_to_histogram(&mut accumulator, chain_length, F::ONE);
+        accumulator
+    })
+}
+
+/// Clamps every chain length of `cap` or more to `cap`, so that the histogram
+/// folded from them has a single bucket for all the chains that long. The
+/// bucket of `cap` then stands for "`cap` or more", with nothing to tell it
+/// from an exact `cap`.
+pub fn cap_chain_lengths(chain_lengths: &mut [usize], cap: usize) {
+    for chain_length in chain_lengths {
+        *chain_length = (*chain_length).min(cap);
+    }
+}
+
+/// Adds every bucket of `other` into `into`. Returns `false` if any bucket
+/// overflowed and was saturated.
+pub fn merge_histograms<F: Frequency>(into: &mut BTreeMap<usize, F>, other: &BTreeMap<usize, F>) -> bool {
+    other.iter().fold(true, |exact, (chain_length, frequency)| {
+        add_to_histogram(into, *chain_length, *frequency) && exact
+    })
+}
+
+/// The cumulative distribution of `histogram`: for each chain length, the
+/// fraction of the chains that are at most that long. The fraction of the
+/// longest chains is exactly 1.0; an empty histogram has an empty
+/// distribution.
+pub fn histogram_cdf(histogram: &BTreeMap<usize, usize>) -> BTreeMap<usize, f64> {
+    let total: usize = histogram.values().sum();
+    let mut cumulative = 0;
+    histogram.iter()
+        .map(|(chain_length, frequency)| {
+            cumulative += frequency;
+            (*chain_length, cumulative as f64 / total as f64)
+        })
+        .collect()
+}
+
+/// The distinct chain lengths in the order they first occur in
+/// `chain_lengths`.
+pub fn first_appearances<I: IntoIterator<Item = usize>>(chain_lengths: I) -> Vec<usize> {
+    let mut seen = BTreeSet::new();
+    chain_lengths.into_iter().filter(|chain_length| seen.insert(*chain_length)).collect()
+}
+
+/// The buckets of `histogram` in the order of `chain_lengths`, like the one
+/// of [`first_appearances`]. Lengths without a bucket are skipped.
+pub fn in_order<F: Frequency>(histogram: &BTreeMap<usize, F>, chain_lengths: &[usize]) -> Vec<(usize, F)> {
+    chain_lengths.iter()
+        .filter_map(|chain_length| histogram.get(chain_length).map(|frequency| (*chain_length, *frequency)))
+        .collect()
+}
+
+/// The Shannon entropy of the chain lengths of `histogram`, in bits: 0 if all
+/// chains have the same length or there are none, and the largest, the
+/// logarithm of the number of lengths, if all lengths are equally frequent.
+/// The logarithm needs `std`.
+#[cfg(feature = "std")]
+pub fn histogram_entropy(histogram: &BTreeMap<usize, usize>) -> f64 {
+    let total: usize = histogram.values().sum();
+    histogram.values()
+        .filter(|frequency| **frequency != 0)
+        .map(|frequency| {
+            let probability = *frequency as f64 / total as f64;
+            -probability * probability.log2()
+        })
+        .sum::<f64>()
+        .max(0.0)
+}
+
+/// The Kullback-Leibler divergence of the chain length distribution of `p`
+/// from that of `q`, in bits: 0 for equal histograms, close to 0 for
+/// histograms of the same shape, and larger the less `q` predicts `p`. It is
+/// not symmetric. So that lengths missing from one histogram do not make it
+/// infinite, both are smoothed first by adding one to the frequency of every
+/// length of either (Laplace smoothing), which changes the shape of a small
+/// histogram more than that of a large one.
+#[cfg(feature = "std")]
+pub fn histogram_kl_divergence(p: &BTreeMap<usize, usize>, q: &BTreeMap<usize, usize>) -> f64 {
+    let lengths = p.keys().chain(q.keys()).collect::<BTreeSet<_>>();
+    let smoothed = |histogram: &BTreeMap<usize, usize>, chain_length: &usize| {
+        let total: usize = histogram.values().sum();
+        (histogram.get(chain_length).copied().unwrap_or(0) + 1) as f64 / (total + lengths.len()) as f64
+    };
+    lengths.iter()
+        .map(|chain_length| {
+            let (p, q) = (smoothed(p, chain_length), smoothed(q, chain_length));
+            p * (p / q).log2()
+        })
+        .sum::<f64>()
+        .max(0.0)
+}
+
+/// The cosine of the angle between the chain length distributions of `p` and
+/// `q`: 1 for histograms of the same shape, 0 for ones without a length in
+/// common, or if either is empty.
+#[cfg(feature = "std")]
+pub fn histogram_cosine_similarity(p: &BTreeMap<usize, usize>, q: &BTreeMap<usize, usize>) -> f64 {
+    let squared_norm = |histogram: &BTreeMap<usize, usize>| histogram.values().map(|frequency| (*frequency as f64).powi(2)).sum::<f64>();
+    let dot: f64 = p.iter()
+        .filter_map(|(chain_length, frequency)| q.get(chain_length).map(|other| *frequency as f64 * *other as f64))
+        .sum();
+    // One square root, so that histograms of the same shape come out as 1
+    // exactly.
+    if dot == 0.0 { 0.0 } else { (dot / (squared_norm(p) * squared_norm(q)).sqrt()).min(1.0) }
+}
+
+/// The `n` most frequent `(chain length, frequency)` buckets, most frequent
+/// first. Buckets with the same frequency are ordered by chain length, longest
+/// first. If there are fewer than `n` buckets, all are returned.
+pub fn top_n<F: Frequency>(histogram: &BTreeMap<usize, F>, n: usize) -> Vec<(usize, F)> {
+    let mut buckets = histogram.iter()
+        .map(|(chain_length, frequency)| (*chain_length, *frequency))
+        .collect::<Vec<(usize, F)>>();
+    buckets.sort_by(|(length_a, frequency_a), (length_b, frequency_b)| {
+        frequency_b.cmp(frequency_a).then(length_b.cmp(length_a))
+    });
+    buckets.truncate(n);
+    buckets
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_histogram() {
+        let histogram: BTreeMap<usize, usize> = histogram(vec![1, 1, 2, 1, 1, 1, 3]);
+        assert_eq!(histogram, BTreeMap::from([(1, 5), (2, 1), (3, 1)]));
+    }
+
+    #[test]
+    fn test_cap_chain_lengths() {
+        let mut chain_lengths = vec![1, 3, 2, 4, 5, 1];
+        cap_chain_lengths(&mut chain_lengths, 3);
+        assert_eq!(chain_lengths, vec![1, 3, 2, 3, 3, 1]);
+        let histogram: BTreeMap<usize, usize> = histogram(chain_lengths);
+        assert_eq!(histogram, BTreeMap::from([(1, 2), (2, 1), (3, 3)]));
+    }
+
+    #[test]
+    fn test_histogram_cdf() {
+        let cdf = histogram_cdf(&BTreeMap::from([(1, 5), (2, 3), (4, 1), (7, 1)]));
+        assert_eq!(cdf.keys().copied().collect::<Vec<_>>(), vec![1, 2, 4, 7]);
+        assert!(cdf.values().zip(cdf.values().skip(1)).all(|(a, b)| a < b));
+        assert_eq!(cdf[&1], 0.5);
+        assert_eq!(cdf[&7], 1.0);
+        assert!(histogram_cdf(&BTreeMap::new()).is_empty());
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_histogram_entropy() {
+        // -(3/4 log2 3/4 + 1/4 log2 1/4) = 2 - 3/4 log2 3
+        assert!((histogram_entropy(&BTreeMap::from([(1, 3), (2, 1)])) - (2.0 - 0.75 * 3f64.log2())).abs() < 1e-12);
+        assert_eq!(histogram_entropy(&BTreeMap::from([(1, 5), (4, 5)])), 1.0);
+        assert_eq!(histogram_entropy(&BTreeMap::from([(1, 2), (2, 2), (3, 2), (4, 2)])), 2.0);
+        assert_eq!(histogram_entropy(&BTreeMap::from([(3, 7)])), 0.0);
+        assert_eq!(histogram_entropy(&BTreeMap::new()), 0.0);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_histogram_comparison() {
+        let p = BTreeMap::from([(1, 6), (2, 3), (5, 1)]);
+        // The same shape, with more chains.
+        let q = BTreeMap::from([(1, 12), (2, 6), (5, 2)]);
+        assert_eq!(histogram_kl_divergence(&p, &p), 0.0);
+        assert!(histogram_kl_divergence(&p, &q) < 0.01);
+        assert_eq!(histogram_cosine_similarity(&p, &p), 1.0);
+        assert_eq!(histogram_cosine_similarity(&p, &q), 1.0);
+
+        // Disjoint, so smoothed to (3/4, 1/4) and (1/4, 3/4).
+        let (a, b) = (BTreeMap::from([(1, 2)]), BTreeMap::from([(2, 2)]));
+        assert!((histogram_kl_divergence(&a, &b) - 0.5 * 3f64.log2()).abs() < 1e-12);
+        assert_eq!(histogram_cosine_similarity(&a, &b), 0.0);
+        assert!(histogram_kl_divergence(&p, &a) != histogram_kl_divergence(&a, &p));
+
+        assert_eq!(histogram_kl_divergence(&BTreeMap::new(), &BTreeMap::new()), 0.0);
+        assert_eq!(histogram_cosine_similarity(&p, &BTreeMap::new()), 0.0);
+    }
+
+    #[test]
+    fn test_first_appearances() {
+        let chain_lengths = vec![5, 2, 5, 1, 2, 3];
+        assert_eq!(first_appearances(chain_lengths.iter().copied()), vec![5, 2, 1, 3]);
+        let histogram: BTreeMap<usize, usize> = histogram(chain_lengths);
+        assert_eq!(in_order(&histogram, &[5, 2, 1, 3]), vec![(5, 2), (2, 2), (1, 1), (3, 1)]);
+        assert_eq!(in_order(&histogram, &[4, 3]), vec![(3, 1)]);
+    }
+
+    #[test]
+    fn test_merge_histograms() {
+        let mut into: BTreeMap<usize, usize> = BTreeMap::from([(1, 5), (2, 1)]);
+        assert!(merge_histograms(&mut into, &BTreeMap::from([(2, 2), (7, 1)])));
+        assert_eq!(into, BTreeMap::from([(1, 5), (2, 3), (7, 1)]));
+    }
+
+    #[test]
+    fn test_merge_histograms_saturates() {
+        let mut into: BTreeMap<usize, u32> = BTreeMap::from([(1, u32::MAX - 1), (2, 1)]);
+        assert!(!merge_histograms(&mut into, &BTreeMap::from([(1, 5), (2, 1)])));
+        assert_eq!(into, BTreeMap::from([(1, u32::MAX), (2, 2)]));
+
+        let mut small: BTreeMap<usize, u8> = histogram(std::iter::repeat_n(1, 300));
+        assert_eq!(small, BTreeMap::from([(1, u8::MAX)]));
+        assert!(!add_to_histogram(&mut small, 1, 1));
+        assert_eq!(small, BTreeMap::from([(1, u8::MAX)]));
+    }
+
+    #[test]
+    fn test_top_n() {
+        // The histogram of `test_chain7`.
+        let histogram: BTreeMap<usize, usize> = BTreeMap::from([(3, 1), (2, 1), (1, 7)]);
+        assert_eq!(top_n(&histogram, 2), vec![(1, 7), (3, 1)]);
+        assert_eq!(top_n(&histogram, 10), vec![(1, 7), (3, 1), (2, 1)]);
+        assert_eq!(top_n(&histogram, 0), vec![]);
+    }
+}
@@ -0,0 +1,129 @@
+//! Chain detection over source text that arrives in chunks, for example as
+//! an editor buffer is loaded or a file is read piece by piece.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::detector::Detector;
+use crate::{ChainConfig, CommentRemover, Token, Tokenizer, TokenizerConfig};
+
+/// Counts the chains of a text fed in chunks. Comment removal, tokenizing,
+/// and detection all carry their state from one chunk to the next, so the
+/// counts are the same as for the whole text however it is split: a chunk may
+/// end in the middle of a word or a comment.
+///
+/// Javadoc snippets ([`ChainConfig::analyze_javadoc_code`]) are not analyzed.
+///
+/// ```
+/// use method_chains::incremental::IncrementalAnalysis;
+///
+/// let mut analysis = IncrementalAnalysis::new(10);
+/// analysis.push_str("a().b(); c.").unwrap();
+/// assert_eq!(analysis.chain_counts(), &[2]);
+/// analysis.push_str("d().e()").unwrap();
+/// assert_eq!(analysis.finish().unwrap(), vec![2, 2]);
+/// ```
+#[derive(Clone, Debug)]
+pub struct IncrementalAnalysis {
+    comments: CommentRemover,
+    tokenizer: Tokenizer,
+    detector: Detector,
+    /// The last chunk without comments.
+    clean: String,
+    /// Tokens not fed to the detector yet.
+    tokens: Vec<Token>,
+    chain_counts: Vec<usize>,
+}
+
+impl IncrementalAnalysis {
+    pub fn new(max_depth: usize) -> Self {
+        IncrementalAnalysis::with_config(max_depth, &ChainConfig::default())
+    }
+
+    pub fn with_config(max_depth: usize, config: &ChainConfig) -> Self {
+        IncrementalAnalysis {
+            comments: CommentRemover::new(config.language.comment_syntax()),
+            tokenizer: Tokenizer::new(&TokenizerConfig { language: config.language, ..TokenizerConfig::default() }),
+            detector: Detector::with_config(max_depth, config),
+            clean: String::new(),
+            tokens: Vec::new(),
+            chain_counts: Vec::new(),
+        }
+    }
+
+    /// Analyzes the next chunk of the text.
+    pub fn push_str(&mut self, chunk: &str) -> anyhow::Result<()> {
+        self.clean.clear();
+        self.comments.push_str(chunk, &mut self.clean);
+        self.tokenizer.push_str(&self.clean, &mut self.tokens)
+            .expect("lenient tokenizer does not fail");
+        let settled = self.tokens.len() - self.tokenizer.unsettled();
+        self.feed(settled)
+    }
+
+    /// Lengths of the chains completed so far, in the order they were
+    /// completed.
+    pub fn chain_counts(&self) -> &[usize] {
+        &self.chain_counts
+    }
+
+    /// Ends the text and returns the lengths of all its chains.
+    pub fn finish(mut self) -> anyhow::Result<Vec<usize>> {
+        self.tokenizer.finish(&mut self.tokens)
+            .expect("lenient tokenizer does not fail");
+        self.feed(self.tokens.len())?;
+        self.chain_counts.extend(self.detector.finish());
+        Ok(self.chain_counts)
+    }
+
+    fn feed(&mut self, tokens: usize) -> anyhow::Result<()> {
+        self.detector.feed_all(self.tokens.drain(..tokens))?;
+        self.chain_counts.extend(self.detector.drain_chains().map(|chain| chain.length()));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MethodChaining;
+
+    const PROGRAM: &str = r#"
+        class A {
+            void f(String... args) {
+                /* a().b().c(); */
+                list.stream().map(x -> x.y()).collect(toList());  // d().e();
+                g(h().i(), new int[]{ j.k() });
+            }
+        }
+    "#;
+
+    fn analyze_chunks(chunks: &[&str]) -> Vec<usize> {
+        let mut analysis = IncrementalAnalysis::new(100);
+        for chunk in chunks {
+            analysis.push_str(chunk).unwrap();
+        }
+        analysis.finish().unwrap()
+    }
+
+    #[test]
+    fn test_three_chunks() {
+        let whole = PROGRAM.method_chain_counts(100).unwrap();
+        assert_eq!(whole, vec![1, 1, 3, 2, 1, 1]);
+
+        // Split in the middle of a block comment and of a word.
+        let first = PROGRAM.find("b().c").unwrap();
+        let second = PROGRAM.find("stream").unwrap() + 3;
+        let chunks = [&PROGRAM[..first], &PROGRAM[first..second], &PROGRAM[second..]];
+        assert_eq!(analyze_chunks(&chunks), whole);
+    }
+
+    // Every character on its own, so also the dots of `...`.
+    #[test]
+    fn test_character_chunks() {
+        let chunks = PROGRAM.char_indices()
+            .map(|(i, character)| &PROGRAM[i..i + character.len_utf8()])
+            .collect::<Vec<&str>>();
+        assert_eq!(analyze_chunks(&chunks), PROGRAM.method_chain_counts(100).unwrap());
+    }
+}
@@ -1,11 +1,38 @@
-use std::borrow::Cow;
-use std::collections::BTreeMap;
-use std::collections::VecDeque;
-use std::iter::FromIterator;
-use std::path::PathBuf;
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
 
-use anyhow::bail;
+extern crate alloc;
 
+use alloc::borrow::{Cow, ToOwned};
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use alloc::vec;
+#[cfg(feature = "std")]
+use std::path::{Path, PathBuf};
+
+#[cfg(feature = "std")]
+use anyhow::Context;
+
+use detector::Detector;
+
+pub mod accuracy;
+#[cfg(feature = "std")]
+pub mod corpus;
+pub mod detector;
+#[cfg(feature = "std")]
+pub mod diff;
+#[cfg(feature = "std")]
+pub mod file_list;
+pub mod histogram;
+pub mod incremental;
+#[cfg(feature = "std")]
+pub mod output;
+#[cfg(feature = "std")]
+pub mod patch;
+
+// The restricted keywords of module declarations, like `open`, `requires`, or
+// `to`, are identifiers everywhere else, so `open()` is a call.
+#[allow(clippy::match_like_matches_macro)]
 fn is_keyword(string: &str) -> bool {
     match string {
         "abstract" => true,
@@ -24,7 +51,6 @@ fn is_keyword(string: &str) -> bool {
         "double" => true,
         "else" => true,
         "enum" => true,
-        "exports" => true,
         "extends" => true,
         "final" => true,
         "finally" => true,
@@ -38,17 +64,12 @@ fn is_keyword(string: &str) -> bool {
         "int" => true,
         "interface" => true,
         "long" => true,
-        "module" => true,
         "native" => true,
         "new" => true,
-        "open" => true,
-        "opens" => true,
         "package" => true,
         "private" => true,
         "protected" => true,
-        "provides" => true,
         "public" => true,
-        "requires" => true,
         "return" => true,
         "short" => true,
         "static" => true,
@@ -59,15 +80,26 @@ fn is_keyword(string: &str) -> bool {
         "this" => true,
         "throw" => true,
         "throws" => true,
-        "to" => true,
         "transient" => true,
-        "transitive" => true,
         "try" => true,
-        "uses" => true,
         _ => false,
     }
 }
 
+// Rust keywords, except `fn`: `fn f(x: T)` then reads like the declaration
+// `T f(T x)` of Java, a type followed by a name, so `f` is not taken for a call.
+// `await` is left out too, since `.await` continues a chain like a field.
+#[allow(clippy::match_like_matches_macro)]
+fn is_rust_keyword(string: &str) -> bool {
+    match string {
+        "as" | "break" | "const" | "continue" | "crate" | "dyn" | "else" | "enum" | "extern" | "false" | "for" | "if" | "impl" | "in" => true,
+        "let" | "loop" | "match" | "mod" | "move" | "mut" | "pub" | "ref" | "return" | "self" | "Self" | "static" | "struct" | "super" => true,
+        "trait" | "true" | "type" | "unsafe" | "use" | "where" | "while" => true,
+        _ => false,
+    }
+}
+
+#[allow(clippy::needless_return)]
 fn is_identifier(token: &str) -> bool {
     if token.is_empty() {
         return false;
@@ -85,88 +117,661 @@ fn is_identifier(token: &str) -> bool {
     return true;
 }
 
-pub(crate) fn remove_comments(contents: &str) -> String {
+// Strict version of `is_identifier`: Java identifiers may also contain digits,
+// but must not start with one.
+fn is_java_identifier(token: &str) -> bool {
+    let mut characters = token.chars();
+    match characters.next() {
+        Some(ch) if ch.is_alphabetic() || ch == '$' || ch == '_' => {}
+        _ => return false,
+    }
+    characters.all(|ch| ch.is_alphanumeric() || ch == '$' || ch == '_')
+}
+
+// Numeric literals such as `10`, `0x1F`, `1_000L`, or `1e10`. The fractional
+// part of `1.5` arrives as a separate token after a `Dot`.
+fn is_numeric_literal(token: &str) -> bool {
+    token.starts_with(|ch: char| ch.is_ascii_digit())
+        && token.chars().all(|ch| ch.is_alphanumeric() || ch == '_')
+}
+
+/// Replaces the Unicode escapes of `source` (`\u002e`, also with several
+/// `u`s: `\uu002e`) by the characters they stand for, as Java does before
+/// tokenizing. A backslash preceded by an odd number of backslashes does not
+/// start an escape (`\\u002e` is not one). Malformed escapes and unpaired
+/// surrogates are kept as they are.
+pub fn decode_unicode_escapes(source: &str) -> Cow<'_, str> {
+    if !source.contains("\\u") {
+        return Cow::Borrowed(source);
+    }
+    let mut output = String::with_capacity(source.len());
+    let mut rest = source;
+    let mut backslashes = 0;
+    while let Some(ch) = rest.chars().next() {
+        if ch == '\\' && backslashes % 2 == 0 {
+            if let Some((unit, length)) = unicode_escape(rest) {
+                let decoded = match unit {
+                    0xD800..=0xDBFF => unicode_escape(&rest[length..])
+                        .filter(|(low, _)| (0xDC00..=0xDFFF).contains(low))
+                        .and_then(|(low, low_length)| {
+                            let code = 0x10000 + ((unit - 0xD800) << 10) + (low - 0xDC00);
+                            char::from_u32(code).map(|ch| (ch, length + low_length))
+                        }),
+                    _ => char::from_u32(unit).map(|ch| (ch, length)),
+                };
+                if let Some((ch, length)) = decoded {
+                    output.push(ch);
+                    rest = &rest[length..];
+                    backslashes = 0;
+                    continue;
+                }
+            }
+        }
+        backslashes = if ch == '\\' { backslashes + 1 } else { 0 };
+        output.push(ch);
+        rest = &rest[ch.len_utf8()..];
+    }
+    Cow::Owned(output)
+}
+
+// The UTF-16 code unit of the escape `\\u+XXXX` at the start of `text`, and
+// the length of the escape.
+fn unicode_escape(text: &str) -> Option<(u32, usize)> {
+    let after_backslash = text.strip_prefix('\\')?;
+    let digits = after_backslash.trim_start_matches('u');
+    if digits.len() == after_backslash.len() {
+        return None;
+    }
+    let hex = digits.get(..4).filter(|hex| hex.chars().all(|ch| ch.is_ascii_hexdigit()))?;
+    let unit = u32::from_str_radix(hex, 16).ok()?;
+    Some((unit, text.len() - digits.len() + 4))
+}
+
+/// Removes `//` line comments and `/* */` block comments, but not what only
+/// looks like one in a string or character literal. Like Java, treats
+/// each comment as whitespace: a block comment becomes a single space and a
+/// line comment keeps the line break ending it, so neither joins the words
+/// around it (`a/**/b` is `a b`). The result is never longer than `contents`.
+pub fn remove_comments(contents: &str) -> String {
+    remove_comments_with(contents, &CommentSyntax::java())
+}
+
+/// Removes the comments written in `syntax` from `contents`, like
+/// [`remove_comments`] does for Java.
+pub fn remove_comments_with(contents: &str, syntax: &CommentSyntax) -> String {
+    let mut output = String::new();
+    CommentRemover::new(syntax.clone()).push_str(contents, &mut output);
+    output
+}
+
+/// How comments are written, for [`remove_comments_with`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CommentSyntax {
+    /// What starts a comment running to the end of the line, like `//`.
+    pub line_prefixes: Vec<String>,
+    /// What starts and what ends a block comment, like `/*` and `*/`.
+    pub block: Option<(String, String)>,
+    /// Whether block comments nest, so that `/* a /* b */ c */` is a single
+    /// comment rather than one followed by ` c */`.
+    pub nested: bool,
+    /// What starts and ends string and character literals, like `"`. Inside
+    /// one, up to the end of the line, `//` is text rather than a comment.
+    pub quotes: Vec<char>,
+}
+
+impl CommentSyntax {
+    /// `//` and `/* */`, which do not nest.
+    pub fn java() -> Self {
+        CommentSyntax {
+            line_prefixes: vec!["//".to_owned()],
+            block: Some(("/*".to_owned(), "*/".to_owned())),
+            nested: false,
+            quotes: vec!['"', '\''],
+        }
+    }
+
+    /// `//` and `/* */`, which nest. Only `"` quotes, a `'` may start a
+    /// lifetime.
+    pub fn rust() -> Self {
+        CommentSyntax { nested: true, quotes: vec!['"'], ..CommentSyntax::java() }
+    }
+
+    /// `#` to the end of the line, as in Python and shell scripts.
+    pub fn hash() -> Self {
+        CommentSyntax { line_prefixes: vec!["#".to_owned()], block: None, nested: false, quotes: vec!['"', '\''] }
+    }
+}
+
+impl Default for CommentSyntax {
+    fn default() -> Self {
+        CommentSyntax::java()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum CommentState {
+    Code,
+    LineComment,
+    /// In a block comment, with this many more open inside it.
+    BlockComment(usize),
+    /// In a literal ending with `quote`, right after a backslash if `escaped`.
+    Literal { quote: char, escaped: bool },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Delimiter { Line, Open, Close }
+
+/// Removes comments from text that arrives in chunks, like
+/// [`remove_comments`] does for a whole text. A comment may start in one
+/// chunk and end in another.
+#[derive(Clone, Debug)]
+pub struct CommentRemover {
+    state: CommentState,
+    syntax: CommentSyntax,
+    /// The characters read last, which may be the start of a delimiter.
+    pending: String,
+}
+
+impl Default for CommentRemover {
+    fn default() -> Self {
+        CommentRemover::new(CommentSyntax::java())
+    }
+}
+
+impl CommentRemover {
+    pub fn new(syntax: CommentSyntax) -> Self {
+        CommentRemover { state: CommentState::Code, syntax, pending: String::new() }
+    }
+
+    /// Appends `chunk` without its comments to `output`.
+    pub fn push_str(&mut self, chunk: &str, output: &mut String) {
+        chunk.chars().for_each(|character| self.push_char(character, output))
+    }
+
+    pub fn push_char(&mut self, character: char, output: &mut String) {
+        if self.state == CommentState::LineComment {
+            if matches!(character, '\n' | '\r') {
+                self.state = CommentState::Code;
+                output.push(character);
+            }
+            return;
+        }
+        if self.pending.is_empty() && !self.delimiters().any(|(text, _)| text.starts_with(character)) {
+            self.push_code(character, output);
+            return;
+        }
+        self.pending.push(character);
+        loop {
+            let found = self.delimiters().find(|(text, _)| *text == self.pending).map(|(_, delimiter)| delimiter);
+            if let Some(delimiter) = found {
+                self.pending.clear();
+                self.state = match (self.state, delimiter) {
+                    (_, Delimiter::Line) => CommentState::LineComment,
+                    (CommentState::BlockComment(nested), Delimiter::Open) => CommentState::BlockComment(nested + 1),
+                    (CommentState::BlockComment(nested), Delimiter::Close) if nested > 0 => CommentState::BlockComment(nested - 1),
+                    (_, Delimiter::Open) => CommentState::BlockComment(0),
+                    (_, Delimiter::Close) => {
+                        // Whitespace, so the words around do not join.
+                        output.push(' ');
+                        CommentState::Code
+                    }
+                };
+                return;
+            }
+            if self.pending.is_empty() || self.delimiters().any(|(text, _)| text.starts_with(self.pending.as_str())) {
+                return;
+            }
+            // The first character starts no delimiter, but the next ones may.
+            let first = self.pending.remove(0);
+            self.push_code(first, output);
+        }
+    }
+
+    // Appends a character that is not part of a delimiter, unless in a
+    // comment, and keeps track of the literals.
+    fn push_code(&mut self, character: char, output: &mut String) {
+        self.state = match self.state {
+            CommentState::LineComment | CommentState::BlockComment(_) => return,
+            CommentState::Code if self.syntax.quotes.contains(&character) => CommentState::Literal { quote: character, escaped: false },
+            CommentState::Code => CommentState::Code,
+            // An unterminated literal ends with the line.
+            CommentState::Literal { .. } if matches!(character, '\n' | '\r') => CommentState::Code,
+            CommentState::Literal { quote, escaped: false } if character == quote => CommentState::Code,
+            CommentState::Literal { quote, escaped } => CommentState::Literal { quote, escaped: !escaped && character == '\\' },
+        };
+        output.push(character);
+    }
+
+    // The delimiters that may come next.
+    fn delimiters(&self) -> impl Iterator<Item = (&str, Delimiter)> {
+        let (lines, open, close) = match (self.state, &self.syntax.block) {
+            (CommentState::Code, block) => (true, block.as_ref().map(|(open, _)| open), None),
+            (CommentState::BlockComment(_), Some((open, close))) => (false, Some(open).filter(|_| self.syntax.nested), Some(close)),
+            _ => (false, None, None),
+        };
+        let lines = self.syntax.line_prefixes.iter().filter(move |_| lines).map(|prefix| (prefix.as_str(), Delimiter::Line));
+        let open = open.map(|open| (open.as_str(), Delimiter::Open));
+        let close = close.map(|close| (close.as_str(), Delimiter::Close));
+        close.into_iter().chain(lines).chain(open)
+    }
+}
+
+// The bodies of all block comments, without the `/*` and `*/` delimiters.
+fn block_comments(contents: &str) -> Vec<String> {
     #[derive(Debug)]
     enum State { Basic, SlashFound, LineComment, BlockComment, StarFoundInComment }
     let mut state = State::Basic;
-    let mut output = String::new();
+    let mut comments = Vec::new();
+    let mut comment = String::new();
     for character in contents.chars() {
-        //println!("{:?} {:?}", state, character);
         match (&state, character) {
             (State::Basic, '/') => { state = State::SlashFound; }
-            (State::Basic, any) => { output.push(any); }
+            (State::Basic, _) => { }
 
             (State::SlashFound, '/') =>  { state = State::LineComment; }
             (State::SlashFound, '*') =>  { state = State::BlockComment; }
-            (State::SlashFound, any) =>  { state = State::Basic; output.push('/'); output.push(any);  }
+            (State::SlashFound, _) =>  { state = State::Basic; }
 
             (State::LineComment, '\n') => {state = State::Basic; }
             (State::LineComment, '\r') => {state = State::Basic; }
-            (State::LineComment, _) => { /*ignore*/ }
+            (State::LineComment, _) => { }
 
             (State::BlockComment, '*') => {state = State::StarFoundInComment; }
-            (State::BlockComment, _) => { /*ignore*/ }
+            (State::BlockComment, any) => { comment.push(any); }
 
-            (State::StarFoundInComment, '/') => { state = State::Basic; }
-            (State::StarFoundInComment, '*') => { /* ignore */ }
-            (State::StarFoundInComment, _) => { state = State::BlockComment;  }
+            (State::StarFoundInComment, '/') => { state = State::Basic; comments.push(core::mem::take(&mut comment)); }
+            (State::StarFoundInComment, '*') => { comment.push('*'); }
+            (State::StarFoundInComment, any) => { state = State::BlockComment; comment.push('*'); comment.push(any); }
         }
-    };
-    output
+    }
+    comments
+}
+
+/// Extracts the code inside `{@code ...}` and `{@link ...}` Javadoc tags from
+/// the block comments of `contents`, so that it can be analyzed as source.
+/// Nested braces are balanced and the leading `*` of continuation lines is
+/// dropped.
+pub fn javadoc_code_snippets(contents: &str) -> Vec<String> {
+    let mut snippets = Vec::new();
+    for comment in block_comments(contents) {
+        let mut rest = comment.as_str();
+        while let Some(start) = ["{@code", "{@link"].iter().filter_map(|tag| rest.find(tag)).min() {
+            rest = &rest[start + "{@code".len()..];
+            if !rest.starts_with(char::is_whitespace) {
+                continue;
+            }
+            let mut depth = 1;
+            let end = rest.char_indices().find(|(_, character)| {
+                match character {
+                    '{' => depth += 1,
+                    '}' => depth -= 1,
+                    _ => {}
+                }
+                depth == 0
+            }).map_or(rest.len(), |(i, _)| i);
+            let snippet = rest[..end].lines()
+                .map(|line| line.trim_start().strip_prefix('*').unwrap_or(line))
+                .collect::<Vec<&str>>()
+                .join("\n");
+            snippets.push(snippet.trim().to_owned());
+            rest = &rest[end..];
+        }
+    }
+    snippets
+}
+
+/// The name of the package declared by `package a.b.c;`, if any. A package
+/// declaration can only be preceded by comments and annotations, so only the
+/// first statement is looked at.
+pub fn package_name(contents: &str) -> Option<String> {
+    let clean = remove_comments(contents);
+    let first_statement = clean.split(';').next()?;
+    let mut words = first_statement.split_whitespace();
+    words.position(|word| word == "package")?;
+    let name = words.collect::<String>();
+    if name.is_empty() { None } else { Some(name) }
 }
 
 #[derive(Clone, Debug,PartialEq, Eq, PartialOrd, Ord)]
 pub enum Token {
-    Punctuation,//(char),
-    String,//(String),
+    Punctuation(char),
+    /// A word that is neither a keyword nor an identifier, like a number, or
+    /// a whole string or character literal with its quotes, like `"a.b()"`.
+    String(String),
     Dot,
+    /// The `?.` of a Kotlin or Groovy safe call, which the detector treats as
+    /// a [`Token::Dot`]: `a?.b()?.c()` is a chain of length 2. Never emitted
+    /// for Rust, whose `?` ends an expression.
+    SafeDot,
+    /// The `...` of a varargs parameter.
+    Ellipsis,
+    /// The `->` of a lambda or of a `switch` rule (`case A -> ...`).
+    Arrow,
+    /// The `::` of a method reference.
+    DoubleColon,
     OpenParen,
     CloseParen,
     OpenBracket,
     CloseBracket,
-    // End,
-    Keyword,
-    Identifier,
+    OpenBrace,
+    CloseBrace,
+    /// The end of an input, only emitted with [`TokenizerConfig::end`]. The
+    /// detector completes its chains and starts over, so that no chain spans
+    /// two inputs fed one after the other.
+    End,
+    Keyword(String),
+    Identifier(String),
     New,
+    /// A run of this many whitespace characters, only emitted with
+    /// [`TokenizerConfig::whitespace`]. The detector ignores it.
+    Whitespace(usize),
+}
+
+/// Options for [`tokenize_with`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TokenizerConfig {
+    /// Reject words that are neither keywords, valid Java identifiers, nor
+    /// numeric literals instead of emitting them as [`Token::String`].
+    pub strict: bool,
+    /// Emit every run of whitespace as a [`Token::Whitespace`] instead of
+    /// dropping it, so the spacing of the text can be approximated.
+    pub whitespace: bool,
+    /// Emit a [`Token::End`] after the last token of the input.
+    pub end: bool,
+    pub language: Language,
+}
+
+/// The language of the source, which decides the syntax of its comments,
+/// literals, and paths.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Language {
+    #[default]
+    Java,
+    /// Block comments nest, raw strings like `r#"a "quoted" b"#` have no
+    /// escapes, `'a` is a lifetime rather than a character literal, and `new`
+    /// is an ordinary name. A `::` path chains like a dot, so
+    /// `Vec::new().iter()` has 2 links, and the detector skips the `?`
+    /// operator and the type arguments of `collect::<Vec<_>>()`.
+    Rust,
+}
+
+impl Language {
+    /// The language of files with `extension`, ignoring ASCII case, if it is
+    /// one of the languages above.
+    pub fn from_extension(extension: &str) -> Option<Language> {
+        match extension.to_ascii_lowercase().as_str() {
+            "java" => Some(Language::Java),
+            "rs" => Some(Language::Rust),
+            _ => None,
+        }
+    }
+
+    /// How comments are written in the language.
+    pub fn comment_syntax(&self) -> CommentSyntax {
+        match self {
+            Language::Java => CommentSyntax::java(),
+            Language::Rust => CommentSyntax::rust(),
+        }
+    }
+}
+
+/// A word the strict tokenizer could not classify.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TokenizeError {
+    pub token: String,
+}
+
+impl core::fmt::Display for TokenizeError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "invalid token `{}`", self.token)
+    }
 }
 
+impl core::error::Error for TokenizeError {}
+
 pub fn tokenize(contents: &str) -> Vec<Token> {
-    let mut token = String::new();
+    tokenize_with(contents, &TokenizerConfig::default())
+        .expect("lenient tokenizer does not fail")
+}
+
+/// Removes the comments of `source` and tokenizes the rest, both the way of
+/// `language`.
+pub fn tokenize_source(source: &str, language: Language) -> Vec<Token> {
+    let config = TokenizerConfig { language, ..TokenizerConfig::default() };
+    tokenize_with(&remove_comments_with(source, &language.comment_syntax()), &config)
+        .expect("lenient tokenizer does not fail")
+}
+
+pub fn tokenize_with(contents: &str, config: &TokenizerConfig) -> Result<Vec<Token>, TokenizeError> {
     let mut output = Vec::new();
-    macro_rules! push { 
-        (Token::String) => {
-            if !token.is_empty() {
-                if token.as_str() == "new" {
-                    output.push(Token::New/*(token.clone())*/);                    
-                }
-                if is_keyword(token.as_str()) {
-                    output.push(Token::Keyword/*(token.clone())*/);
-                } else if is_identifier(token.as_str()) {
-                    output.push(Token::Identifier/*(token.clone())*/);
-                } else {
-                    output.push(Token::String)
-                }                
-            }
-            token.clear();
-        };        
-        (Token::Punctuation($a:expr)) => {
-            output.push(Token::Punctuation);
-        };
-        ($t:path) => {
-            output.push($t);
-        };
+    let mut tokenizer = Tokenizer::new(config);
+    tokenizer.push_str(contents, &mut output)?;
+    tokenizer.finish(&mut output)?;
+    Ok(output)
+}
+
+/// Removes the comments of `source` and tokenizes the rest, like
+/// `tokenize(&remove_comments(source))`, along with the line of `source` each
+/// token starts on, counting from 1.
+pub fn tokenize_with_lines(source: &str) -> (Vec<Token>, Vec<usize>) {
+    tokenize_with_lines_as(source, Language::Java)
+}
+
+fn tokenize_with_lines_as(source: &str, language: Language) -> (Vec<Token>, Vec<usize>) {
+    let mut comments = CommentRemover::new(language.comment_syntax());
+    let mut tokenizer = Tokenizer::new(&TokenizerConfig { language, ..TokenizerConfig::default() });
+    let mut clean = String::new();
+    let mut tokens = Vec::new();
+    let mut lines = Vec::new();
+    let mut line = 1;
+    // The line of the first character of the word read so far.
+    let mut word_line = 1;
+
+    for character in source.chars() {
+        comments.push_char(character, &mut clean);
+        for clean_character in clean.drain(..) {
+            let in_word = !tokenizer.token.is_empty();
+            tokenizer.push_char(clean_character, &mut tokens)
+                .expect("lenient tokenizer does not fail");
+            record_lines(&tokens, &mut lines, line, word_line);
+            if !in_word && !tokenizer.token.is_empty() {
+                word_line = line;
+            }
+        }
+        if character == '\n' {
+            line += 1;
+        }
     }
-    for character in contents.chars() {
+    tokenizer.finish(&mut tokens).expect("lenient tokenizer does not fail");
+    record_lines(&tokens, &mut lines, line, word_line);
+    (tokens, lines)
+}
+
+// Gives the tokens just appended to `tokens` their line: a word completed by
+// the last character may have started on an earlier line. An ellipsis
+// replaces its dots and keeps the line of the first.
+fn record_lines(tokens: &[Token], lines: &mut Vec<usize>, line: usize, word_line: usize) {
+    lines.truncate(tokens.len());
+    for token in &tokens[lines.len()..] {
+        let is_word = matches!(token, Token::String(_) | Token::Keyword(_) | Token::Identifier(_) | Token::New);
+        lines.push(if is_word { word_line } else { line });
+    }
+}
+
+// The number of `#` of a Rust raw string starting with `prefix` and then a
+// quote: 2 for `r##`, none for a prefix like `x` that starts no raw string.
+fn raw_string_prefix(prefix: &str) -> Option<usize> {
+    let hashes = prefix.strip_prefix('b').unwrap_or(prefix).strip_prefix('r')?;
+    hashes.chars().all(|character| character == '#').then_some(hashes.len())
+}
+
+// Whether the raw string `literal`, from its prefix on, ends with its closing
+// quote and `hashes` hashes.
+fn closes_raw_string(literal: &str, hashes: usize) -> bool {
+    let opening = literal.find('"').unwrap_or(0);
+    let body = &literal[opening + 1..];
+    body.len() > hashes && body[..body.len() - hashes].ends_with('"') && body[body.len() - hashes..].bytes().all(|byte| byte == b'#')
+}
+
+// Whether `literal`, a quote and one character, continues with `next` as a
+// Rust lifetime or label like `'a` or `'outer` rather than as a character
+// literal like `'a'` or `'\n'`.
+fn is_lifetime(literal: &str, next: char) -> bool {
+    let mut characters = literal.chars();
+    characters.next() == Some('\'')
+        && characters.next().is_some_and(|character| character != '\\')
+        && characters.next().is_none()
+        && next != '\''
+}
+
+/// Tokenizes text that arrives in chunks, like [`tokenize_with`] does for a
+/// whole text. A word may start in one chunk and end in another.
+#[derive(Clone, Debug)]
+pub struct Tokenizer {
+    config: TokenizerConfig,
+    /// The word read so far.
+    token: String,
+    consecutive_dots: usize,
+    consecutive_minuses: usize,
+    /// The last character was a `:` that may start a `::`.
+    after_colon: bool,
+    /// The last character was a `?` that may start a `?.`.
+    after_question: bool,
+    /// The quote of the string or character literal read so far, which is
+    /// then in `token`.
+    literal: Option<char>,
+    /// The last character of the literal was a backslash.
+    escaped: bool,
+    /// Number of `#` around the Rust raw string read so far: 0 for
+    /// `r"..."`, 1 for `r#"..."#`.
+    raw_hashes: Option<usize>,
+    /// Length of the whitespace run read so far, with
+    /// [`TokenizerConfig::whitespace`].
+    whitespace: usize,
+}
+
+impl Tokenizer {
+    pub fn new(config: &TokenizerConfig) -> Self {
+        Tokenizer { config: config.clone(), token: String::new(), consecutive_dots: 0, consecutive_minuses: 0, after_colon: false, after_question: false, literal: None, escaped: false, raw_hashes: None, whitespace: 0 }
+    }
+
+    /// Number of tokens at the end of the output that the next chunk may
+    /// still replace: the dots of what may turn out to be an ellipsis, the
+    /// `-` of what may turn out to be an arrow, or the `:` or `?` of what may
+    /// turn out to be a `::` or a `?.`.
+    pub fn unsettled(&self) -> usize {
+        self.consecutive_dots + self.consecutive_minuses % 2 + self.after_colon as usize + self.after_question as usize
+    }
+
+    /// Appends the tokens of `chunk` to `output`. The word at the end of the
+    /// chunk is only appended once it is complete.
+    pub fn push_str(&mut self, chunk: &str, output: &mut Vec<Token>) -> Result<(), TokenizeError> {
+        chunk.chars().try_for_each(|character| self.push_char(character, output))
+    }
+
+    pub fn push_char(&mut self, character: char, output: &mut Vec<Token>) -> Result<(), TokenizeError> {
+        let Tokenizer { config, token, consecutive_dots, consecutive_minuses, after_colon, after_question, literal, escaped, raw_hashes, whitespace } = self;
+        let rust = config.language == Language::Rust;
+        macro_rules! push {
+            (Token::String) => {
+                if !token.is_empty() {
+                    if token.as_str() == "new" && !rust {
+                        output.push(Token::New);
+                    }
+                    if if rust { is_rust_keyword(token.as_str()) } else { is_keyword(token.as_str()) } {
+                        output.push(Token::Keyword(token.clone()));
+                    } else if config.strict {
+                        if is_java_identifier(token.as_str()) {
+                            output.push(Token::Identifier(token.clone()));
+                        } else if is_numeric_literal(token.as_str()) {
+                            output.push(Token::String(token.clone()));
+                        } else {
+                            return Err(TokenizeError { token: token.clone() });
+                        }
+                    } else if is_identifier(token.as_str()) {
+                        output.push(Token::Identifier(token.clone()));
+                    } else {
+                        output.push(Token::String(token.clone()))
+                    }
+                }
+                token.clear();
+            };        
+            (Token::Punctuation($a:expr)) => {
+                output.push(Token::Punctuation($a));
+            };
+            ($t:path) => {
+                output.push($t);
+            };
+        }
+        let is_whitespace = matches!(character, ' ' | '\t' | '\n' | '\r');
+        if let Some(quote) = *literal {
+            if rust && quote == '\'' && is_lifetime(token, character) {
+                // Not a literal after all, but the start of a word.
+                *literal = None;
+            } else if !rust && !*escaped && matches!(character, '\n' | '\r') {
+                // A Java literal cannot span lines, so a line break ends an
+                // unclosed one (or the `"""` opening a text block).
+                output.push(Token::String(core::mem::take(token)));
+                *literal = None;
+            } else {
+                token.push(character);
+                let closed = match *raw_hashes {
+                    Some(hashes) => matches!(character, '"' | '#') && closes_raw_string(token, hashes),
+                    None => !*escaped && character == quote,
+                };
+                if closed {
+                    output.push(Token::String(core::mem::take(token)));
+                    *literal = None;
+                    *raw_hashes = None;
+                }
+                *escaped = raw_hashes.is_none() && !*escaped && character == '\\';
+                return Ok(());
+            }
+        }
+        if config.whitespace && !is_whitespace && *whitespace > 0 {
+            output.push(Token::Whitespace(core::mem::take(whitespace)));
+        }
+        *consecutive_dots = if character == '.' { *consecutive_dots + 1 } else { 0 };
+        // `--` is a decrement, so only an odd `-` may start an arrow: `a-->b`
+        // is `a -- > b`.
+        let arrow = character == '>' && *consecutive_minuses % 2 == 1;
+        *consecutive_minuses = if character == '-' { *consecutive_minuses + 1 } else { 0 };
+        let double_colon = character == ':' && *after_colon;
+        *after_colon = character == ':' && !double_colon;
+        // Only a `?` right before the dot makes a safe call, so `x ? .5 : 1`
+        // stays a conditional, but `x?.5:1` does not.
+        let safe_dot = character == '.' && *after_question;
+        *after_question = character == '?' && !rust;
         match character {
             // Whitespace
             ' ' | '\t' | '\n' | '\r' => {
                 push!(Token::String);
+                if config.whitespace {
+                    *whitespace += 1;
+                }
             }
             // Punctuation
+            '.' if *consecutive_dots == 3 => {
+                output.truncate(output.len() - 2);
+                push!(Token::Ellipsis);
+                *consecutive_dots = 0;
+            }
+            '.' if safe_dot => {
+                output.pop();
+                push!(Token::SafeDot);
+            }
             '.' => {
                 push!(Token::String);
                 push!(Token::Dot);
             }
+            '>' if arrow => {
+                output.pop();
+                push!(Token::Arrow);
+            }
+            ':' if double_colon => {
+                output.pop();
+                push!(Token::DoubleColon);
+            }
             '(' => {
                 push!(Token::String);
                 push!(Token::OpenParen);
@@ -183,219 +788,872 @@ pub fn tokenize(contents: &str) -> Vec<Token> {
                 push!(Token::String);
                 push!(Token::CloseBracket);
             }
+            '{' => {
+                push!(Token::String);
+                push!(Token::OpenBrace);
+            }
+            '}' => {
+                push!(Token::String);
+                push!(Token::CloseBrace);
+            }
             '*' | '/' | '+' | '-' | '%' | 
             '\\' |
             ';' | ',' | '@' | ':' | '=' | 
-            '{' | '}' | '<' | '>' | 
-            '!' | '~' | '?' | '&' | '|' | '^' |
-            '"' | '\'' => {
+            '<' | '>' | 
+            '!' | '~' | '?' | '&' | '|' | '^' => {
                 push!(Token::String);
                 push!(Token::Punctuation(character));
-            }, 
+            },
+            '"' | '\'' => {
+                // The `b` of a byte literal and the `r#` of a raw string
+                // belong to the literal.
+                *raw_hashes = raw_string_prefix(token).filter(|_| rust && character == '"');
+                if !(rust && (token == "b" || raw_hashes.is_some())) {
+                    push!(Token::String);
+                }
+                token.push(character);
+                *literal = Some(character);
+            }
             // Alphanumeric        
             _ => {
                 token.push(character);
             },
         }
+        Ok(())
     }
 
-    // push!(Token::End);
-    output
+    /// Ends the input, appending the last word to `output`.
+    pub fn finish(&mut self, output: &mut Vec<Token>) -> Result<(), TokenizeError> {
+        // A word or literal that runs up to the end of the input.
+        self.raw_hashes = None;
+        if self.literal.take().is_some() {
+            output.push(Token::String(core::mem::take(&mut self.token)));
+        }
+        let whitespace = core::mem::take(&mut self.whitespace);
+        self.push_str(" ", output)?;
+        self.whitespace = 0;
+        if whitespace > 0 {
+            output.push(Token::Whitespace(whitespace));
+        }
+
+        if self.config.end {
+            output.push(Token::End);
+        }
+        Ok(())
+    }
 }
 
-#[allow(dead_code)]
-fn sloppy_method_chain_detection(tokens: Vec<Token>, max_depth: usize) -> anyhow::Result<BTreeMap<usize, usize>> {
-    let mut tokens = VecDeque::from_iter(tokens.into_iter());
-    let counters = sloppy_method_chain_detection_rec(&mut tokens, 0, max_depth)?;
-    let histogram = counters.into_iter().fold(BTreeMap::new(), |mut accumulator, chain_length| {
-        *accumulator.entry(chain_length).or_insert(0) += 1;
-        accumulator
-    });
-    Ok(histogram)
+/// Knobs that change what the detector considers to be a link in a chain,
+/// and which parts of the source it looks at.
+///
+/// The default configuration counts method calls in code only.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ChainConfig {
+    /// Also count field accesses as links, so `System.out.println()` is a
+    /// chain of length 2 (`out`, `println`). The root of a chain (`System`)
+    /// is never counted.
+    pub count_fields: bool,
+    /// Also count subscripts as links, so `list.get(0)[1].foo()` is a chain
+    /// of length 3. Empty brackets (`String[] args`) are array types, not
+    /// subscripts, and are never counted.
+    pub count_indexing: bool,
+    /// Also count calling the result of a call as a link, so `f()()` is a
+    /// chain of length 2 (`f`, `()`). By default, only the first call counts
+    /// and `f()()` has length 1.
+    pub count_immediate_calls: bool,
+    /// Also analyze the code in `{@code ...}` and `{@link ...}` Javadoc tags
+    /// (see [`javadoc_code_snippets`]). Their chains are reported after the
+    /// chains of the code itself.
+    pub analyze_javadoc_code: bool,
+    /// Decode Unicode escapes (see [`decode_unicode_escapes`]) before looking
+    /// for chains, so that `a\u002eb()` is the chain `a.b()`.
+    pub decode_unicode_escapes: bool,
+    /// Only count the chains inside method and constructor bodies, the
+    /// braces that follow the parameter list of a class member, leaving out
+    /// field initializers and initializer blocks.
+    pub bodies_only: bool,
+    /// Count a method reference like `System.out::println` as a chain of
+    /// length 1 (`println`), separate from what comes before the `::`. A
+    /// method reference names a call that happens elsewhere, so by default it
+    /// is not a link; but code written as `map(String::trim)` could as well
+    /// have been a chained call, and counting it shows how much chaining the
+    /// references replace.
+    pub count_method_references: bool,
+    /// Count a method reference passed to a call as a link of the chain
+    /// making the call, so that `a().map(Foo::bar).count()` has the 4 links
+    /// `a`, `map`, `bar`, and `count` rather than 3. Independent of
+    /// [`ChainConfig::count_method_references`].
+    pub count_method_refs_in_args: bool,
+    /// With [`ChainConfig::count_fields`], also count a call without
+    /// arguments, like a record accessor or a fluent getter, as the field it
+    /// stands for, so that `point.x().y()` is counted as `point.x.y` is. After
+    /// a dot, both are links anyway; but at the root of a chain the call is
+    /// not a link either, and `x().y()` is a chain of length 1, as `x.y` is.
+    pub treat_noarg_calls_as_fields: bool,
+    /// The language of the source. See [`ChainConfig::for_path`] for going by
+    /// the extension of a file.
+    pub language: Language,
+    /// Remove type arguments like the `<String>` of `Collections.<String>
+    /// emptyList()` before looking for chains, see [`strip_generics`].
+    pub strip_generics: bool,
+    /// Names of methods that only pass their argument on, like
+    /// `requireNonNull` or `ofNullable`, whose calls are not links. A single chain among their
+    /// arguments goes on in the chain of the call, so
+    /// `requireNonNull(a.b()).c()` is a chain of length 2, as `a.b().c()` is.
+    /// This is closer to the depth of a chain as its reader sees it.
+    pub transparent_methods: Vec<String>,
+}
+
+impl ChainConfig {
+    /// This configuration for analyzing `path`: with the language of its
+    /// extension, if that is the extension of a [`Language`].
+    #[cfg(feature = "std")]
+    pub fn for_path(&self, path: &Path) -> Cow<'_, ChainConfig> {
+        let language = path.extension()
+            .and_then(|extension| extension.to_str())
+            .and_then(Language::from_extension);
+        match language {
+            Some(language) if language != self.language => Cow::Owned(ChainConfig { language, ..self.clone() }),
+            _ => Cow::Borrowed(self),
+        }
+    }
 }
 
-#[allow(unused_assignments)]
-fn sloppy_method_chain_detection_rec(tokens: &mut VecDeque<Token>, depth: usize, max_depth: usize) -> anyhow::Result<Vec<usize>> {
+/// A single method chain found by the detector.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Chain {
+    /// Names of the methods (and, with [`ChainConfig::count_fields`], the
+    /// fields) making up the chain, in source order.
+    pub links: Vec<String>,
+    /// Index of the token the chain starts at among the tokens fed to the
+    /// detector: its root, like `a` in `a.b()`, or the `new` of
+    /// `new A().b()`.
+    pub start: usize,
+    /// The chain is in the body of an anonymous class, like `b.c()` in
+    /// `new A() { void f() { b.c(); } }`.
+    pub in_anonymous_class: bool,
+    /// Number of the chain's method calls with something between their
+    /// parentheses: 2 for `a(x).b().c(y)`.
+    pub calls_with_arguments: usize,
+}
 
-    if depth > max_depth {
-        bail!("Chain method detection reached recursion depth of {} (max: {}). 
-               Stopping recursion, keeping partial result.", depth, max_depth);
-        
+impl Chain {
+    pub fn length(&self) -> usize {
+        self.links.len()
     }
 
-    #[derive(Clone, Debug,PartialEq, Eq, PartialOrd, Ord)]
-    enum State { Start, Potential, ParenEnd, Chain, Constructor }
+    /// Whether every link of the chain is an accessor like `getA()`.
+    pub fn is_getters_only(&self, getters: &GetterPatterns) -> bool {
+        self.links.iter().all(|link| getters.is_getter(link))
+    }
 
-    let mut counter: usize = 0;
-    let mut state = State::Start;
-    let mut counters: Vec<usize> = Vec::new();
+    /// Whether the chain starts with a call to one of the assertion roots,
+    /// like `assertThat(x).isEqualTo(y)`.
+    pub fn is_assertion(&self, roots: &AssertionRoots) -> bool {
+        self.links.first().is_some_and(|link| roots.methods.iter().any(|method| method == link))
+    }
 
-    macro_rules! method_found {
-        () => { counter += 1 }
+    /// Whether the same method is called twice in a row somewhere in the
+    /// chain, like `add` in `builder.add(a).add(b).build()`: most likely a
+    /// fluent accumulator returning `this`.
+    pub fn repeats_call(&self) -> bool {
+        self.links.windows(2).any(|pair| pair[0] == pair[1])
     }
-    macro_rules! chain_complete {
-        () => { 
-            if counter != 0 {
-                counters.push(counter); 
-                counter = 0; 
-            }
+
+    /// What the chain is called on. `tokens` are the tokens the chain was
+    /// detected in, see [`Chain::start`].
+    pub fn root_kind(&self, tokens: &[Token]) -> RootKind {
+        let qualifier = self.start.checked_sub(2)
+            .filter(|qualifier| tokens[qualifier + 1] == Token::Dot)
+            .map(|qualifier| &tokens[qualifier]);
+        match (tokens.get(self.start), qualifier) {
+            (Some(Token::New), _) => RootKind::New,
+            (_, Some(Token::Keyword(keyword))) if keyword == "this" || keyword == "self" => RootKind::This,
+            (_, Some(Token::Keyword(keyword))) if keyword == "super" => RootKind::Super,
+            _ if tokens.get(self.start + 1) == Some(&Token::OpenParen) => RootKind::This,
+            (Some(Token::Identifier(name)), _) if name.starts_with(|c: char| c.is_uppercase()) => RootKind::ClassName,
+            _ => RootKind::Variable,
         }
     }
+}
+
+/// What a chain is called on, guessed from the tokens at its root.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum RootKind {
+    /// `this.a().b()`, and also a call without a receiver like `a().b()`.
+    This,
+    /// `super.a().b()`.
+    Super,
+    /// A constructor call, `new A().b()`.
+    New,
+    /// A name starting with an upper case letter, so most likely a class and
+    /// a chain of static calls: `Stream.of(a).map(f)`.
+    ClassName,
+    /// Any other name, like `list.stream()`.
+    Variable,
+}
 
-    macro_rules! stop {
-        () => {
-            chain_complete!();
-            return Ok(counters);
+impl RootKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            RootKind::This => "this",
+            RootKind::Super => "super",
+            RootKind::New => "new",
+            RootKind::ClassName => "class",
+            RootKind::Variable => "variable",
         }
     }
+}
 
-    macro_rules! recurse {
-        () => {{
-            let recursion_result = sloppy_method_chain_detection_rec(tokens, depth + 1, max_depth)?;
-            counters.extend(recursion_result.into_iter());
-        }}
+/// The chain lengths of `tokens`, split by [`RootKind`]. Kinds without any
+/// chains are left out.
+pub fn chain_counts_by_root_kind(tokens: &[Token], max_depth: usize, config: &ChainConfig) -> anyhow::Result<BTreeMap<RootKind, Vec<usize>>> {
+    let mut detector = Detector::with_config(max_depth, config);
+    detector.feed_all(tokens.iter().cloned())?;
+    let mut counts: BTreeMap<RootKind, Vec<usize>> = BTreeMap::new();
+    for chain in detector.finish_chains() {
+        counts.entry(chain.root_kind(tokens)).or_default().push(chain.length());
     }
+    Ok(counts)
+}
 
-    while let Some(token) = tokens.pop_front() {
-        //println!("{:?} {:?} counter={}, counters={:?}", state, token, counter, counters);
-        match (&state, token) {
-
-            (State::Start, Token::OpenParen)        => { recurse!(); }
-            (State::Start, Token::OpenBracket)      => { recurse!(); }
-            (State::Start, Token::CloseParen)       => { stop!(); }
-            (State::Start, Token::CloseBracket)     => { stop!(); }            
-            (State::Start, Token::Identifier)       => { state = State::Potential; }
-            (State::Start, Token::New)              => { state = State::Constructor; }
-            (State::Start, _)                       => { /*nothing*/ }
+/// What counts as an accessor: a name made of one of the prefixes followed
+/// by an upper case letter, like `getName` or `isEmpty`. A bare prefix (`get`)
+/// or a longer word (`island`) is not an accessor.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GetterPatterns {
+    pub prefixes: Vec<String>,
+}
 
-            (State::Constructor, Token::OpenParen)  => { recurse!(); state = State::ParenEnd; /*not a method*/  }
-            (State::Constructor, _)                 => { /*nothing*/ }
+impl Default for GetterPatterns {
+    /// The `get` and `is` prefixes.
+    fn default() -> Self {
+        GetterPatterns { prefixes: vec!["get".to_owned(), "is".to_owned()] }
+    }
+}
 
-            (State::Potential, Token::OpenParen)    => { recurse!(); state = State::ParenEnd; method_found!() }
-            (State::Potential, Token::OpenBracket)  => { recurse!(); state = State::ParenEnd; /*not a method*/ }
-            (State::Potential, Token::CloseParen)   => { stop!(); }
-            (State::Potential, Token::CloseBracket) => { stop!(); }
-            (State::Potential, Token::Dot)          => { state = State::Chain; }
-            (State::Potential, _)                   => { state = State::Start; chain_complete!(); }    
+impl GetterPatterns {
+    pub fn is_getter(&self, name: &str) -> bool {
+        self.prefixes.iter().any(|prefix| {
+            name.strip_prefix(prefix.as_str())
+                .and_then(|rest| rest.chars().next())
+                .is_some_and(char::is_uppercase)
+        })
+    }
+}
 
-            (State::ParenEnd, Token::OpenParen)     => { recurse!(); state = State::Start;  }
-            (State::ParenEnd, Token::OpenBracket)   => { recurse!(); state = State::Start;  }
-            (State::ParenEnd, Token::CloseParen)    => { stop!(); }
-            (State::ParenEnd, Token::CloseBracket)  => { stop!(); }
-            (State::ParenEnd, Token::Dot)           => { state = State::Chain; }
-            (State::ParenEnd, _)                    => { state = State::Start; chain_complete!(); }
+/// The methods that start fluent assertions in tests, like `assertThat` in
+/// `assertThat(x).isEqualTo(y)`. The root of the chain (`Assertions` in
+/// `Assertions.assertThat(x)`) does not matter.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AssertionRoots {
+    pub methods: Vec<String>,
+}
 
-            (State::Chain, Token::OpenParen)        => { recurse!(); state = State::Start; }
-            (State::Chain, Token::OpenBracket)      => { recurse!(); state = State::Start; }
-            (State::Chain, Token::CloseParen)       => { stop!(); }
-            (State::Chain, Token::CloseBracket)     => { stop!(); }
-            (State::Chain, Token::Identifier)       => { state = State::Potential; }
-            (State::Chain, _)                       => { state = State::Start; chain_complete!(); }
-        }
-        //println!(" => {:?} counter={}, counters={:?}", state, counter, counters);
+impl Default for AssertionRoots {
+    /// `assertThat` (AssertJ, Truth, Hamcrest), `assertj`, `verify`
+    /// (Mockito), and `then` (BDD style).
+    fn default() -> Self {
+        let methods = ["assertThat", "assertj", "verify", "then"];
+        AssertionRoots { methods: methods.iter().map(|method| method.to_string()).collect() }
     }
-    chain_complete!();
-    //println!("counter={}, counters={:?}", counter, counters);
-    Ok(counters)
 }
 
-pub trait MethodChaining {
-    fn method_chain_counts(&self, max_depth: usize) -> anyhow::Result<Vec<usize>>;
-    fn method_chain_histogram(&self, max_depth: usize) -> anyhow::Result<BTreeMap<usize, usize>> {
-        let histogram = self.method_chain_counts(max_depth)?
-            .into_iter()
-            .fold(BTreeMap::new(), |mut accumulator, chain_length| {
-                *accumulator.entry(chain_length).or_insert(0) += 1;
-                accumulator
-            });
-        Ok(histogram)
-    }
+#[allow(dead_code)]
+fn sloppy_method_chain_detection(tokens: Vec<Token>, max_depth: usize) -> anyhow::Result<BTreeMap<usize, usize>> {
+    sloppy_method_chain_detection_with(tokens, max_depth, &ChainConfig::default())
 }
 
-impl MethodChaining for &str {
-    fn method_chain_counts(&self, max_depth: usize) -> anyhow::Result<Vec<usize>> {
-        let clean = remove_comments(self);
-        let tokens = tokenize(clean.as_str());
-        let mut tokens = VecDeque::from_iter(tokens.into_iter());
-        let counters = sloppy_method_chain_detection_rec(&mut tokens, 0, max_depth);
-        counters
-    }
+#[allow(dead_code)]
+fn sloppy_method_chain_detection_with(tokens: Vec<Token>, max_depth: usize, config: &ChainConfig) -> anyhow::Result<BTreeMap<usize, usize>> {
+    let mut counts = Vec::new();
+    for_each_chain_with(tokens, max_depth, config, |chain_length| counts.push(chain_length))?;
+    Ok(histogram::histogram(counts))
 }
 
-impl MethodChaining for String {
-    fn method_chain_counts(&self, max_depth: usize) -> anyhow::Result<Vec<usize>> {
-        self.as_str().method_chain_counts(max_depth)
-    }
+// `import a.b.C;` and `package a.b;` are dotted names, not chains.
+fn is_declaration_keyword(keyword: &str) -> bool {
+    keyword == "import" || keyword == "package" || keyword == "use"
 }
 
-impl<'a> MethodChaining for Cow<'a, str> {
-    fn method_chain_counts(&self, max_depth: usize) -> anyhow::Result<Vec<usize>> {
-        self.as_ref().method_chain_counts(max_depth)
-    }
+/// Where a chain starts in the source, and how long it is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ChainSpan {
+    /// The line of the chain's root, counting from 1.
+    pub start_line: usize,
+    pub length: usize,
 }
 
+/// The chain lengths of a source both as found and as a histogram, see
+/// [`MethodChaining::method_chain_analysis`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ChainAnalysis {
+    /// The length of each chain, in the order they were completed.
+    pub counts: Vec<usize>,
+    /// How many chains have each length.
+    pub histogram: BTreeMap<usize, usize>,
+}
 
+/// Runs the detector over `tokens`, calling `f` with the length of each chain
+/// as soon as it is complete. Nothing but the current nesting is kept in
+/// memory, so this can build a histogram of arbitrarily large inputs.
+pub fn for_each_chain<I: IntoIterator<Item = Token>, F: FnMut(usize)>(tokens: I, max_depth: usize, f: F) -> anyhow::Result<()> {
+    for_each_chain_with(tokens, max_depth, &ChainConfig::default(), f)
+}
 
-pub fn read_dir_all(path: &PathBuf) -> Vec<PathBuf> {
-    std::fs::read_dir(&path)
-        .expect(&format!("Cannot read directory {:?}", path))
-        .into_iter()
-        .map(|entry| entry.unwrap())
-        .flat_map(|entry| {
-            if entry.file_type().unwrap().is_dir() {
-                read_dir_all(&entry.path())
-            } else {                
-                vec![entry.path()]
-            }
-        }).collect()
+pub fn for_each_chain_with<I: IntoIterator<Item = Token>, F: FnMut(usize)>(tokens: I, max_depth: usize, config: &ChainConfig, mut f: F) -> anyhow::Result<()> {
+    let mut detector = Detector::with_config(max_depth, config);
+    for token in tokens {
+        detector.feed(token)?;
+        detector.drain_chains().for_each(|chain| f(chain.length()));
+    }
+    detector.finish().into_iter().for_each(f);
+    Ok(())
 }
 
+/// Number of call sites: identifiers directly followed by `(`, counted in a
+/// single pass without detecting chains. This is a cheap baseline, so method
+/// declarations and constructor calls (`new A()`) count too, while keywords
+/// like `if (` do not.
+pub fn count_method_calls(tokens: &[Token]) -> usize {
+    tokens.windows(2)
+        .filter(|pair| matches!(pair, [Token::Identifier(_), Token::OpenParen]))
+        .count()
+}
 
-#[cfg(test)]
-mod tests { 
-    use std::collections::BTreeMap;
-    use std::iter::FromIterator;
-    use crate::*;
+/// Removes the groups of type arguments from `tokens`, like the
+/// `<String, Integer>` of `Map.<String, Integer>of().size()` or the
+/// `<String>` of `List<String> names`, leaving `Map.of().size()` and
+/// `List names`.
+///
+/// Tokens do not tell types apart from expressions, so a group is guessed: a
+/// `<` right after a name or a dot, up to its matching `>`, with only names,
+/// keywords, dots, commas, `?`, `&`, and brackets in between. The guess is
+/// wrong for comparisons that look like one, as in `a < b & c > d`, whose
+/// `< b & c >` is removed. Groups with something else in them are kept
+/// whole, like the annotated `List<@NonNull String>`.
+pub fn strip_generics(tokens: &[Token]) -> Vec<Token> {
+    tokens.iter()
+        .zip(type_arguments(tokens))
+        .filter(|(_, is_type_argument)| !is_type_argument)
+        .map(|(token, _)| token.clone())
+        .collect()
+}
 
-    #[test]
-    fn test_comment_removal() {
-        let string = "// aaaaa\na/*   \n\n/**/*/b//c\nd";
-        assert_eq!(remove_comments(string), "a*/bd");
+// For each of `tokens`, whether it belongs to a group removed by
+// `strip_generics`.
+fn type_arguments(tokens: &[Token]) -> Vec<bool> {
+    let mut mask = vec![false; tokens.len()];
+    let mut i = 1;
+    while i < tokens.len() {
+        let opens = tokens[i] == Token::Punctuation('<') && matches!(tokens[i - 1], Token::Identifier(_) | Token::Dot);
+        match type_arguments_length(&tokens[i..]).filter(|_| opens) {
+            Some(length) => {
+                mask[i..i + length].iter_mut().for_each(|is_type_argument| *is_type_argument = true);
+                i += length;
+            }
+            None => i += 1,
+        }
     }
+    mask
+}
 
-    #[test]
-    fn test_tokenizer() {
-        let string = "a(); bb(); c.dddd().e(); main {}; if (x == 1)";
-        let tokens = vec![
-            Token::Identifier/*("a".to_owned())*/, Token::OpenParen, Token::CloseParen, Token::Punctuation/*(';')*/, 
-            Token::Identifier/*("bb".to_owned())*/, Token::OpenParen, Token::CloseParen, Token::Punctuation/*(';')*/, 
-            Token::Identifier/*("c".to_owned())*/, Token::Dot, 
-            Token::Identifier/*("dddd".to_owned())*/, Token::OpenParen, Token::CloseParen, Token::Dot, 
-            Token::Identifier/*("e".to_owned())*/, Token::OpenParen, Token::CloseParen, Token::Punctuation/*(';')*/, 
-            Token::Identifier/*("main".to_owned())*/, Token::Punctuation/*('{')*/, Token::Punctuation/*('}')*/, Token::Punctuation/*(';')*/, 
-            Token::Keyword/*("if".to_owned())*/, Token::OpenParen, Token::Identifier, Token::Punctuation, Token::Punctuation, Token::String, Token::CloseParen,
-        ];
-        assert_eq!(tokenize(string), tokens);
+// The number of tokens up to the `>` matching the `<` that `tokens` start
+// with, if they could all be type arguments.
+fn type_arguments_length(tokens: &[Token]) -> Option<usize> {
+    let mut depth = 0;
+    for (i, token) in tokens.iter().enumerate() {
+        match token {
+            Token::Punctuation('<') => depth += 1,
+            Token::Punctuation('>') => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i + 1);
+                }
+            }
+            // `&&` is a condition, while a bound is `A & B`.
+            Token::Punctuation('&') if tokens.get(i + 1) == Some(&Token::Punctuation('&')) => return None,
+            Token::Identifier(_) | Token::Keyword(_) | Token::Dot | Token::OpenBracket | Token::CloseBracket
+            | Token::Punctuation(',' | '?' | '&') | Token::Whitespace(_) => {}
+            _ => return None,
+        }
     }
+    None
+}
 
+// The tokens the detector looks for chains in when configured with `config`.
+pub(crate) fn chain_tokens(source: &str, config: &ChainConfig) -> Vec<Token> {
+    let tokens = tokenize_source(source, config.language);
+    if config.strip_generics { strip_generics(&tokens) } else { tokens }
+}
 
-    #[test]
-    fn test_chain1() {
-        let tokens = vec![
-            Token::Identifier, Token::OpenParen, Token::CloseParen
-        ];
-        let histogram: BTreeMap<usize, usize> = BTreeMap::from_iter(vec![
-            (1, 1)
-        ].into_iter());
-        assert_eq!(sloppy_method_chain_detection(tokens, 1000).unwrap(), histogram);
+/// Whether every parenthesis, bracket, and brace of `tokens` is closed by
+/// the matching closer, in the right order. The detector copes with
+/// unbalanced input, but counts chains of truncated or preprocessed code
+/// unreliably.
+pub fn is_balanced(tokens: &[Token]) -> bool {
+    let mut open = Vec::new();
+    for token in tokens {
+        match token {
+            Token::OpenParen | Token::OpenBracket | Token::OpenBrace => open.push(token.clone()),
+            Token::CloseParen | Token::CloseBracket | Token::CloseBrace => {
+                let expected = match token {
+                    Token::CloseParen => Token::OpenParen,
+                    Token::CloseBracket => Token::OpenBracket,
+                    _ => Token::OpenBrace,
+                };
+                if open.pop() != Some(expected) {
+                    return false;
+                }
+            }
+            _ => {}
+        }
     }
+    open.is_empty()
+}
+
+pub trait MethodChaining {
+    fn method_chains_with(&self, max_depth: usize, config: &ChainConfig) -> anyhow::Result<Vec<Chain>>;
+    /// See [`count_method_calls`].
+    fn method_call_count(&self) -> usize;
+    /// How deep parentheses, brackets, and braces nest, see
+    /// [`Detector::max_nesting_depth`]. Javadoc snippets are left out.
+    fn max_nesting_depth_with(&self, config: &ChainConfig) -> usize;
+    fn max_nesting_depth(&self) -> usize {
+        self.max_nesting_depth_with(&ChainConfig::default())
+    }
+    /// Where each chain starts, in the same order as
+    /// [`MethodChaining::method_chains_with`]. Javadoc snippets have no lines
+    /// of their own, so their chains are left out.
+    fn method_chain_spans_with(&self, max_depth: usize, config: &ChainConfig) -> anyhow::Result<Vec<ChainSpan>>;
+    fn method_chain_spans(&self, max_depth: usize) -> anyhow::Result<Vec<ChainSpan>> {
+        self.method_chain_spans_with(max_depth, &ChainConfig::default())
+    }
+    /// For every method call with arguments, the number of chains among its
+    /// arguments, see [`Detector::argument_fanouts`]. Javadoc snippets are
+    /// left out.
+    fn argument_chain_fanout_with(&self, max_depth: usize, config: &ChainConfig) -> anyhow::Result<Vec<usize>>;
+    fn argument_chain_fanout(&self, max_depth: usize) -> anyhow::Result<Vec<usize>> {
+        self.argument_chain_fanout_with(max_depth, &ChainConfig::default())
+    }
+    fn method_chain_counts_with(&self, max_depth: usize, config: &ChainConfig) -> anyhow::Result<Vec<usize>> {
+        let counts = self.method_chains_with(max_depth, config)?
+            .iter()
+            .map(Chain::length)
+            .collect();
+        Ok(counts)
+    }
+    fn method_chain_counts(&self, max_depth: usize) -> anyhow::Result<Vec<usize>> {
+        self.method_chain_counts_with(max_depth, &ChainConfig::default())
+    }
+    /// Chain lengths that do not count the links named in `names`, like the
+    /// `as` of `a.as(B.class).c()`: the chain goes on through them, so that is a
+    /// chain of length 2. Chains made of ignored links only are left out.
+    fn method_chain_counts_ignoring(&self, max_depth: usize, names: &[&str]) -> anyhow::Result<Vec<usize>> {
+        let counts = self.method_chains_with(max_depth, &ChainConfig::default())?
+            .iter()
+            .map(|chain| chain.links.iter().filter(|link| !names.contains(&link.as_str())).count())
+            .filter(|length| *length != 0)
+            .collect();
+        Ok(counts)
+    }
+    /// For every chain, its length and how many of its calls have arguments,
+    /// to tell `a().b()` apart from `a(x).b(y)`.
+    fn method_chain_argument_calls(&self, max_depth: usize) -> anyhow::Result<Vec<(usize, usize)>> {
+        let counts = self.method_chains_with(max_depth, &ChainConfig::default())?
+            .iter()
+            .map(|chain| (chain.length(), chain.calls_with_arguments))
+            .collect();
+        Ok(counts)
+    }
+    fn method_chain_histogram(&self, max_depth: usize) -> anyhow::Result<BTreeMap<usize, usize>> {
+        Ok(histogram::histogram(self.method_chain_counts(max_depth)?))
+    }
+    /// The chain lengths and their histogram, tokenized and detected once.
+    fn method_chain_analysis(&self, max_depth: usize) -> anyhow::Result<ChainAnalysis> {
+        let counts = self.method_chain_counts(max_depth)?;
+        let histogram = histogram::histogram(counts.iter().copied());
+        Ok(ChainAnalysis { counts, histogram })
+    }
+    /// Names of the links of the longest chain. If several chains share the
+    /// maximum length, the one starting first wins, even if a chain nested
+    /// in its arguments is reported before it.
+    fn longest_chain_methods_with(&self, max_depth: usize, config: &ChainConfig) -> anyhow::Result<Vec<String>> {
+        let longest = self.method_chains_with(max_depth, config)?
+            .into_iter()
+            .fold(Chain::default(), |longest, chain| {
+                let tie = chain.length() == longest.length() && chain.length() > 0 && chain.start < longest.start;
+                if chain.length() > longest.length() || tie { chain } else { longest }
+            });
+        Ok(longest.links)
+    }
+    fn longest_chain_methods(&self, max_depth: usize) -> anyhow::Result<Vec<String>> {
+        self.longest_chain_methods_with(max_depth, &ChainConfig::default())
+    }
+    /// The chains that contain at least one link that is not an accessor,
+    /// see [`GetterPatterns`].
+    fn non_getter_chains_with(&self, max_depth: usize, config: &ChainConfig, getters: &GetterPatterns) -> anyhow::Result<Vec<Chain>> {
+        let chains = self.method_chains_with(max_depth, config)?
+            .into_iter()
+            .filter(|chain| !chain.is_getters_only(getters))
+            .collect();
+        Ok(chains)
+    }
+    /// Lengths of the chains calling the same method twice in a row, see
+    /// [`Chain::repeats_call`].
+    fn repeated_call_chains(&self, max_depth: usize) -> anyhow::Result<Vec<usize>> {
+        let counts = self.method_chains_with(max_depth, &ChainConfig::default())?
+            .iter()
+            .filter(|chain| chain.repeats_call())
+            .map(Chain::length)
+            .collect();
+        Ok(counts)
+    }
+    fn non_getter_chains(&self, max_depth: usize) -> anyhow::Result<Vec<Chain>> {
+        self.non_getter_chains_with(max_depth, &ChainConfig::default(), &GetterPatterns::default())
+    }
+}
+
+impl MethodChaining for &str {
+    fn method_chains_with(&self, max_depth: usize, config: &ChainConfig) -> anyhow::Result<Vec<Chain>> {
+        if config.decode_unicode_escapes {
+            let decoded = decode_unicode_escapes(self);
+            let config = ChainConfig { decode_unicode_escapes: false, ..config.clone() };
+            return decoded.as_ref().method_chains_with(max_depth, &config);
+        }
+        let mut detector = Detector::with_config(max_depth, config);
+        detector.feed_all(chain_tokens(self, config))?;
+        let mut chains = detector.finish_chains();
+        if config.analyze_javadoc_code {
+            for snippet in javadoc_code_snippets(self) {
+                let mut detector = Detector::with_config(max_depth, config);
+                detector.feed_all(tokenize(snippet.as_str()))?;
+                chains.extend(detector.finish_chains());
+            }
+        }
+        Ok(chains)
+    }
+    fn method_call_count(&self) -> usize {
+        count_method_calls(&tokenize(remove_comments(self).as_str()))
+    }
+    fn max_nesting_depth_with(&self, config: &ChainConfig) -> usize {
+        let source = if config.decode_unicode_escapes { decode_unicode_escapes(self) } else { Cow::Borrowed(*self) };
+        let mut detector = Detector::with_config(usize::MAX, config);
+        detector.feed_all(chain_tokens(&source, config)).expect("nesting is not limited");
+        detector.max_nesting_depth()
+    }
+    fn method_chain_spans_with(&self, max_depth: usize, config: &ChainConfig) -> anyhow::Result<Vec<ChainSpan>> {
+        // A decoded `\u000a` is a line break, so lines are those of the
+        // decoded source.
+        let source = if config.decode_unicode_escapes { decode_unicode_escapes(self) } else { Cow::Borrowed(*self) };
+        let (mut tokens, mut lines) = tokenize_with_lines_as(&source, config.language);
+        if config.strip_generics {
+            let mask = type_arguments(&tokens);
+            lines = lines.into_iter().zip(&mask).filter(|(_, is_type_argument)| !**is_type_argument).map(|(line, _)| line).collect();
+            tokens = strip_generics(&tokens);
+        }
+        let mut detector = Detector::with_config(max_depth, config);
+        detector.feed_all(tokens)?;
+        let spans = detector.finish_chains()
+            .iter()
+            .map(|chain| ChainSpan { start_line: lines[chain.start], length: chain.length() })
+            .collect();
+        Ok(spans)
+    }
+    fn argument_chain_fanout_with(&self, max_depth: usize, config: &ChainConfig) -> anyhow::Result<Vec<usize>> {
+        let source = if config.decode_unicode_escapes { decode_unicode_escapes(self) } else { Cow::Borrowed(*self) };
+        let mut detector = Detector::with_config(max_depth, config);
+        detector.feed_all(chain_tokens(&source, config))?;
+        Ok(detector.argument_fanouts().to_vec())
+    }
+}
+
+impl MethodChaining for String {
+    fn method_chains_with(&self, max_depth: usize, config: &ChainConfig) -> anyhow::Result<Vec<Chain>> {
+        self.as_str().method_chains_with(max_depth, config)
+    }
+    fn method_call_count(&self) -> usize {
+        self.as_str().method_call_count()
+    }
+    fn max_nesting_depth_with(&self, config: &ChainConfig) -> usize {
+        self.as_str().max_nesting_depth_with(config)
+    }
+    fn method_chain_spans_with(&self, max_depth: usize, config: &ChainConfig) -> anyhow::Result<Vec<ChainSpan>> {
+        self.as_str().method_chain_spans_with(max_depth, config)
+    }
+    fn argument_chain_fanout_with(&self, max_depth: usize, config: &ChainConfig) -> anyhow::Result<Vec<usize>> {
+        self.as_str().argument_chain_fanout_with(max_depth, config)
+    }
+}
+
+impl<'a> MethodChaining for Cow<'a, str> {
+    fn method_chains_with(&self, max_depth: usize, config: &ChainConfig) -> anyhow::Result<Vec<Chain>> {
+        self.as_ref().method_chains_with(max_depth, config)
+    }
+    fn method_call_count(&self) -> usize {
+        self.as_ref().method_call_count()
+    }
+    fn max_nesting_depth_with(&self, config: &ChainConfig) -> usize {
+        self.as_ref().max_nesting_depth_with(config)
+    }
+    fn method_chain_spans_with(&self, max_depth: usize, config: &ChainConfig) -> anyhow::Result<Vec<ChainSpan>> {
+        self.as_ref().method_chain_spans_with(max_depth, config)
+    }
+    fn argument_chain_fanout_with(&self, max_depth: usize, config: &ChainConfig) -> anyhow::Result<Vec<usize>> {
+        self.as_ref().argument_chain_fanout_with(max_depth, config)
+    }
+}
+
+/// Source given as bytes is decoded as UTF-8 first, lossily: invalid bytes
+/// become replacement characters. [`method_chain_counts_strict_with`] refuses
+/// invalid source instead.
+impl MethodChaining for &[u8] {
+    fn method_chains_with(&self, max_depth: usize, config: &ChainConfig) -> anyhow::Result<Vec<Chain>> {
+        String::from_utf8_lossy(self).method_chains_with(max_depth, config)
+    }
+    fn method_call_count(&self) -> usize {
+        String::from_utf8_lossy(self).method_call_count()
+    }
+    fn max_nesting_depth_with(&self, config: &ChainConfig) -> usize {
+        String::from_utf8_lossy(self).max_nesting_depth_with(config)
+    }
+    fn method_chain_spans_with(&self, max_depth: usize, config: &ChainConfig) -> anyhow::Result<Vec<ChainSpan>> {
+        String::from_utf8_lossy(self).method_chain_spans_with(max_depth, config)
+    }
+    fn argument_chain_fanout_with(&self, max_depth: usize, config: &ChainConfig) -> anyhow::Result<Vec<usize>> {
+        String::from_utf8_lossy(self).argument_chain_fanout_with(max_depth, config)
+    }
+}
+
+impl MethodChaining for Vec<u8> {
+    fn method_chains_with(&self, max_depth: usize, config: &ChainConfig) -> anyhow::Result<Vec<Chain>> {
+        self.as_slice().method_chains_with(max_depth, config)
+    }
+    fn method_call_count(&self) -> usize {
+        self.as_slice().method_call_count()
+    }
+    fn max_nesting_depth_with(&self, config: &ChainConfig) -> usize {
+        self.as_slice().max_nesting_depth_with(config)
+    }
+    fn method_chain_spans_with(&self, max_depth: usize, config: &ChainConfig) -> anyhow::Result<Vec<ChainSpan>> {
+        self.as_slice().method_chain_spans_with(max_depth, config)
+    }
+    fn argument_chain_fanout_with(&self, max_depth: usize, config: &ChainConfig) -> anyhow::Result<Vec<usize>> {
+        self.as_slice().argument_chain_fanout_with(max_depth, config)
+    }
+}
+
+/// Counts the chains of source given as bytes, like
+/// [`MethodChaining::method_chain_counts_with`] for `&[u8]`, but fails on
+/// invalid UTF-8 instead of replacing it.
+pub fn method_chain_counts_strict_with(bytes: &[u8], max_depth: usize, config: &ChainConfig) -> anyhow::Result<Vec<usize>> {
+    core::str::from_utf8(bytes)
+        .map_err(|error| anyhow::anyhow!("Source is not valid UTF-8: {}", error))?
+        .method_chain_counts_with(max_depth, config)
+}
+
+/// Stands in for every non-ASCII byte read by [`method_chain_counts_bytes`].
+const NON_ASCII_BYTE: char = '_';
+
+/// Counts the chains of source given as bytes, without decoding it (or
+/// copying it into a string) first. The input is read byte by byte: ASCII
+/// bytes as themselves, and any other byte as a character of an identifier.
+///
+/// For ASCII source, the result is the same as that of
+/// [`MethodChaining::method_chain_counts`]. It may differ for non-ASCII
+/// characters outside identifiers: a non-breaking space between two words,
+/// for example, joins them into one identifier here. Javadoc snippets are
+/// never analyzed.
+pub fn method_chain_counts_bytes(bytes: &[u8], max_depth: usize) -> anyhow::Result<Vec<usize>> {
+    method_chain_counts_bytes_with(bytes, max_depth, &ChainConfig::default())
+}
+
+pub fn method_chain_counts_bytes_with(bytes: &[u8], max_depth: usize, config: &ChainConfig) -> anyhow::Result<Vec<usize>> {
+    let mut comments = CommentRemover::new(config.language.comment_syntax());
+    let mut tokenizer = Tokenizer::new(&TokenizerConfig { language: config.language, ..TokenizerConfig::default() });
+    let mut clean = String::new();
+    let mut tokens = Vec::new();
+    for byte in bytes {
+        let character = if byte.is_ascii() { *byte as char } else { NON_ASCII_BYTE };
+        comments.push_char(character, &mut clean);
+        for character in clean.drain(..) {
+            tokenizer.push_char(character, &mut tokens).expect("lenient tokenizer does not fail");
+        }
+    }
+    tokenizer.finish(&mut tokens).expect("lenient tokenizer does not fail");
+    if config.strip_generics {
+        tokens = strip_generics(&tokens);
+    }
+
+    let mut detector = Detector::with_config(max_depth, config);
+    detector.feed_all(tokens)?;
+    Ok(detector.finish())
+}
+
+/// Reads all of `reader` asynchronously, then runs the usual (synchronous)
+/// detection over the collected source.
+#[cfg(feature = "async")]
+pub async fn analyze_reader<R: tokio::io::AsyncRead + Unpin>(mut reader: R, max_depth: usize) -> std::io::Result<Vec<usize>> {
+    use tokio::io::AsyncReadExt;
+    let mut buffer = Vec::new();
+    reader.read_to_end(&mut buffer).await?;
+    buffer
+        .method_chain_counts(max_depth)
+        .map_err(std::io::Error::other)
+}
+
+/// Reads the file at `path` (lossily decoded as UTF-8) and counts its chains.
+#[cfg(feature = "std")]
+pub fn analyze_path(path: &Path, max_depth: usize) -> anyhow::Result<Vec<usize>> {
+    analyze_path_with(path, max_depth, &ChainConfig::default())
+}
+
+#[cfg(feature = "std")]
+pub fn analyze_path_with(path: &Path, max_depth: usize, config: &ChainConfig) -> anyhow::Result<Vec<usize>> {
+    let contents: &[u8] = &SourceBytes::read(path)?;
+    contents.method_chain_counts_with(max_depth, config)
+}
+
+/// Like [`analyze_path_with`], but memory-maps the file and analyzes it in
+/// place instead of reading it into a fresh buffer. Valid UTF-8 is not copied;
+/// a file that is not valid UTF-8 is decoded into a new string, as
+/// [`analyze_path_with`] does. The mapping goes through the same detection as
+/// read files rather than through [`method_chain_counts_bytes_with`], which
+/// treats non-ASCII characters differently and skips Unicode escapes and
+/// Javadoc snippets, so that the results do not depend on `--mmap`.
+#[cfg(feature = "std")]
+pub fn analyze_path_mmap_with(path: &Path, max_depth: usize, config: &ChainConfig) -> anyhow::Result<Vec<usize>> {
+    let contents: &[u8] = &SourceBytes::map(path)?;
+    contents.method_chain_counts_with(max_depth, config)
+}
+
+/// The contents of a source file, either read into memory or memory-mapped.
+#[cfg(feature = "std")]
+pub enum SourceBytes {
+    Read(Vec<u8>),
+    Mapped(memmap2::Mmap),
+}
+
+#[cfg(feature = "std")]
+impl SourceBytes {
+    pub fn read(path: &Path) -> anyhow::Result<Self> {
+        let contents = std::fs::read(path)
+            .with_context(|| format!("Cannot read file {:?}", path))?;
+        Ok(SourceBytes::Read(contents))
+    }
+
+    /// Memory-maps the file at `path`. Falls back to reading the file if it
+    /// cannot be mapped.
+    pub fn map(path: &Path) -> anyhow::Result<Self> {
+        let file = std::fs::File::open(path)
+            .with_context(|| format!("Cannot read file {:?}", path))?;
+        // Safety: the mapping is only ever read. Source files are not expected
+        // to be modified during a scan; if one is truncated meanwhile, reading
+        // the mapping may fault.
+        match unsafe { memmap2::Mmap::map(&file) } {
+            Ok(map) => Ok(SourceBytes::Mapped(map)),
+            Err(_) => SourceBytes::read(path),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::ops::Deref for SourceBytes {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        match self {
+            SourceBytes::Read(contents) => contents,
+            SourceBytes::Mapped(map) => map,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+#[allow(clippy::expect_fun_call, clippy::needless_borrows_for_generic_args, clippy::useless_conversion)]
+pub fn read_dir_all(path: &PathBuf) -> Vec<PathBuf> {
+    std::fs::read_dir(&path)
+        .expect(&format!("Cannot read directory {:?}", path))
+        .into_iter()
+        .map(|entry| entry.unwrap())
+        .flat_map(|entry| {
+            if entry.file_type().unwrap().is_dir() {
+                read_dir_all(&entry.path())
+            } else {                
+                vec![entry.path()]
+            }
+        }).collect()
+}
+
+
+#[cfg(test)]
+#[allow(clippy::useless_conversion)]
+mod tests { 
+    use std::collections::BTreeMap;
+    use std::iter::FromIterator;
+    use crate::*;
+
+    #[test]
+    fn test_comment_removal() {
+        let string = "// aaaaa\na/*   \n\n/**/*/b//c\nd";
+        assert_eq!(remove_comments(string), "\na */b\nd");
+    }
+
+    #[test]
+    fn test_tokenizer() {
+        let string = "a(); bb(); c.dddd().e(); main {}; if (x == 1)";
+        let tokens = vec![
+            Token::Identifier("a".to_owned()), Token::OpenParen, Token::CloseParen, Token::Punctuation(';'), 
+            Token::Identifier("bb".to_owned()), Token::OpenParen, Token::CloseParen, Token::Punctuation(';'), 
+            Token::Identifier("c".to_owned()), Token::Dot, 
+            Token::Identifier("dddd".to_owned()), Token::OpenParen, Token::CloseParen, Token::Dot, 
+            Token::Identifier("e".to_owned()), Token::OpenParen, Token::CloseParen, Token::Punctuation(';'), 
+            Token::Identifier("main".to_owned()), Token::OpenBrace, Token::CloseBrace, Token::Punctuation(';'), 
+            Token::Keyword("if".to_owned()), Token::OpenParen, Token::Identifier("x".to_owned()), Token::Punctuation('='), Token::Punctuation('='), Token::String("1".to_owned()), Token::CloseParen,
+        ];
+        assert_eq!(tokenize(string), tokens);
+    }
+
+
+    #[test]
+    fn test_literals() {
+        let literal = |text: &str| Token::String(text.to_owned());
+        assert_eq!(tokenize(r#""a.b()".c()"#), vec![
+            literal(r#""a.b()""#), Token::Dot, Token::Identifier("c".to_owned()), Token::OpenParen, Token::CloseParen,
+        ]);
+        assert_eq!(tokenize(r#"f("a\"b", '"', '\'', "")"#), vec![
+            Token::Identifier("f".to_owned()), Token::OpenParen, literal(r#""a\"b""#), Token::Punctuation(','),
+            literal("'\"'"), Token::Punctuation(','), literal(r"'\''"), Token::Punctuation(','), literal(r#""""#),
+            Token::CloseParen,
+        ]);
+        // Unclosed at the end of the line, and at the end of the input.
+        assert_eq!(tokenize("\"a (\nb) \"c"), vec![literal("\"a ("), Token::Identifier("b".to_owned()), Token::CloseParen, literal("\"c")]);
+    }
+
+    #[test]
+    fn test_chain_rooted_at_literal() {
+        let chains = r#"String s = "abc".length().toString() + "d.e()";"#.method_chains_with(10, &ChainConfig::default()).unwrap();
+        assert_eq!(chains.len(), 1);
+        assert_eq!(chains[0].links, vec!["length".to_owned(), "toString".to_owned()]);
+        assert_eq!(chains[0].start, 3);
+    }
+
+    #[test]
+    fn test_chain1() {
+        let tokens = vec![
+            Token::Identifier("x".to_owned()), Token::OpenParen, Token::CloseParen
+        ];
+        let histogram: BTreeMap<usize, usize> = BTreeMap::from_iter(vec![
+            (1, 1)
+        ].into_iter());
+        assert_eq!(sloppy_method_chain_detection(tokens, 1000).unwrap(), histogram);
+    }
+
 
-
     #[test]
     fn test_chain2() {
         let tokens = vec![
-            Token::Identifier, Token::OpenParen, Token::CloseParen, Token::Dot,
-            Token::Identifier, Token::OpenParen, Token::CloseParen
+            Token::Identifier("x".to_owned()), Token::OpenParen, Token::CloseParen, Token::Dot,
+            Token::Identifier("x".to_owned()), Token::OpenParen, Token::CloseParen
         ];
         let histogram: BTreeMap<usize, usize> = BTreeMap::from_iter(vec![
             (2, 1)
@@ -406,9 +1664,9 @@ mod tests {
     #[test]
     fn test_chain3() {
         let tokens = vec![
-            Token::Identifier, Token::OpenParen, Token::CloseParen, Token::Dot,
-            Token::Identifier, Token::OpenParen, Token::CloseParen, Token::Dot,
-            Token::Identifier, Token::OpenParen, Token::CloseParen
+            Token::Identifier("x".to_owned()), Token::OpenParen, Token::CloseParen, Token::Dot,
+            Token::Identifier("x".to_owned()), Token::OpenParen, Token::CloseParen, Token::Dot,
+            Token::Identifier("x".to_owned()), Token::OpenParen, Token::CloseParen
         ];
         let histogram: BTreeMap<usize, usize> = BTreeMap::from_iter(vec![
             (3, 1)
@@ -419,9 +1677,9 @@ mod tests {
     #[test]
     fn test_chain4() {
         let tokens = vec![
-            Token::Identifier, Token::OpenParen, Token::CloseParen, Token::Dot,
-            Token::Identifier, Token::Dot,
-            Token::Identifier, Token::OpenParen, Token::CloseParen
+            Token::Identifier("x".to_owned()), Token::OpenParen, Token::CloseParen, Token::Dot,
+            Token::Identifier("x".to_owned()), Token::Dot,
+            Token::Identifier("x".to_owned()), Token::OpenParen, Token::CloseParen
         ];
         let histogram: BTreeMap<usize, usize> = BTreeMap::from_iter(vec![
             (2, 1)
@@ -432,10 +1690,10 @@ mod tests {
     #[test]
     fn test_chain5() {
         let tokens = vec![
-            Token::Identifier, Token::OpenParen, Token::CloseParen, Token::Dot,
-            Token::Identifier, Token::Dot,
-            Token::Identifier, Token::OpenParen, Token::CloseParen, Token::Punctuation/*(';')*/,
-            Token::Identifier, Token::OpenParen, Token::CloseParen
+            Token::Identifier("x".to_owned()), Token::OpenParen, Token::CloseParen, Token::Dot,
+            Token::Identifier("x".to_owned()), Token::Dot,
+            Token::Identifier("x".to_owned()), Token::OpenParen, Token::CloseParen, Token::Punctuation(';'),
+            Token::Identifier("x".to_owned()), Token::OpenParen, Token::CloseParen
         ];
         let histogram: BTreeMap<usize, usize> = BTreeMap::from_iter(vec![
             (2, 1), (1, 1)
@@ -447,16 +1705,16 @@ mod tests {
     #[test]
     fn test_chain6() {
         let tokens = vec![
-            Token::Identifier, Token::OpenParen, 
-                           Token::Identifier, Token::OpenParen, Token::CloseParen, Token::Punctuation/*(',')*/, // 1
-                           Token::Identifier, Token::OpenParen, Token::CloseParen,                              // 1
+            Token::Identifier("x".to_owned()), Token::OpenParen, 
+                           Token::Identifier("x".to_owned()), Token::OpenParen, Token::CloseParen, Token::Punctuation(','), // 1
+                           Token::Identifier("x".to_owned()), Token::OpenParen, Token::CloseParen,                              // 1
                            Token::CloseParen, Token::Dot,
-            Token::Identifier, Token::Dot,
-            Token::Identifier, Token::OpenParen, 
-                           Token::Identifier, Token::OpenParen, Token::CloseParen, Token::Punctuation/*(',')*/, // 1
-                           Token::Identifier, Token::OpenParen, Token::CloseParen,                              // 1
-                           Token::CloseParen, Token::Punctuation/*(';')*/,                                  // 2
-            Token::Identifier, Token::OpenParen, Token::CloseParen                                              // 1
+            Token::Identifier("x".to_owned()), Token::Dot,
+            Token::Identifier("x".to_owned()), Token::OpenParen, 
+                           Token::Identifier("x".to_owned()), Token::OpenParen, Token::CloseParen, Token::Punctuation(','), // 1
+                           Token::Identifier("x".to_owned()), Token::OpenParen, Token::CloseParen,                              // 1
+                           Token::CloseParen, Token::Punctuation(';'),                                  // 2
+            Token::Identifier("x".to_owned()), Token::OpenParen, Token::CloseParen                                              // 1
         ];
         let histogram: BTreeMap<usize, usize> = BTreeMap::from_iter(vec![
             (2, 1), (1, 5)
@@ -467,26 +1725,26 @@ mod tests {
     #[test]
     fn test_chain7() {
         let tokens = vec![
-            Token::Identifier, Token::OpenParen, 
-                           Token::Identifier, Token::OpenParen, 
-                                          Token::Identifier, Token::OpenParen, Token::CloseParen, Token::Dot,
-                                          Token::Identifier, Token::Dot,
-                                          Token::Identifier, Token::OpenParen, Token::CloseParen, Token::Dot,
-                                          Token::Identifier, Token::Dot,
-                                          Token::Identifier, Token::OpenParen, Token::CloseParen,               // 3
-                                          Token::CloseParen, Token::Punctuation/*(',')*/,                   // 1
-                           Token::Identifier, Token::OpenParen, Token::CloseParen,                              // 1
+            Token::Identifier("x".to_owned()), Token::OpenParen, 
+                           Token::Identifier("x".to_owned()), Token::OpenParen, 
+                                          Token::Identifier("x".to_owned()), Token::OpenParen, Token::CloseParen, Token::Dot,
+                                          Token::Identifier("x".to_owned()), Token::Dot,
+                                          Token::Identifier("x".to_owned()), Token::OpenParen, Token::CloseParen, Token::Dot,
+                                          Token::Identifier("x".to_owned()), Token::Dot,
+                                          Token::Identifier("x".to_owned()), Token::OpenParen, Token::CloseParen,               // 3
+                                          Token::CloseParen, Token::Punctuation(','),                   // 1
+                           Token::Identifier("x".to_owned()), Token::OpenParen, Token::CloseParen,                              // 1
                            Token::CloseParen, Token::Dot,
-            Token::Identifier, Token::Dot,
-            Token::Identifier, Token::OpenParen, 
-                           Token::Identifier, Token::OpenParen, Token::CloseParen, Token::Punctuation/*(',')*/, // 1
+            Token::Identifier("x".to_owned()), Token::Dot,
+            Token::Identifier("x".to_owned()), Token::OpenParen, 
+                           Token::Identifier("x".to_owned()), Token::OpenParen, Token::CloseParen, Token::Punctuation(','), // 1
                            Token::OpenBracket, 
-                                Token::Identifier, Token::OpenParen, Token::CloseParen, Token::Punctuation,     // 1
-                                Token::Identifier, Token::OpenParen, Token::CloseParen, Token::Punctuation,     // 1
+                                Token::Identifier("x".to_owned()), Token::OpenParen, Token::CloseParen, Token::Punctuation(','),     // 1
+                                Token::Identifier("x".to_owned()), Token::OpenParen, Token::CloseParen, Token::Punctuation(','),     // 1
                            Token::CloseBracket,
-                           Token::Identifier, Token::OpenParen, Token::CloseParen,                              // 1
-                           Token::CloseParen, Token::Punctuation/*(';')*/,                                  // 2
-            Token::Identifier, Token::OpenParen, Token::CloseParen                                              // 1
+                           Token::Identifier("x".to_owned()), Token::OpenParen, Token::CloseParen,                              // 1
+                           Token::CloseParen, Token::Punctuation(';'),                                  // 2
+            Token::Identifier("x".to_owned()), Token::OpenParen, Token::CloseParen                                              // 1
         ];
         let histogram: BTreeMap<usize, usize> = BTreeMap::from_iter(vec![
             (3,1), (2, 1), (1, 7)
@@ -497,9 +1755,9 @@ mod tests {
     #[test]
     fn test_chain8() {
         let tokens = vec![
-            Token::New, Token::Identifier, Token::OpenParen, Token::CloseParen, Token::Dot, // 0
-            Token::Identifier, Token::Dot,                                                  // 0
-            Token::Identifier, Token::OpenParen, Token::CloseParen                          // 1
+            Token::New, Token::Identifier("x".to_owned()), Token::OpenParen, Token::CloseParen, Token::Dot, // 0
+            Token::Identifier("x".to_owned()), Token::Dot,                                                  // 0
+            Token::Identifier("x".to_owned()), Token::OpenParen, Token::CloseParen                          // 1
         ];
         let histogram: BTreeMap<usize, usize> = BTreeMap::from_iter(vec![
             (1, 1)
@@ -538,4 +1796,907 @@ mod tests {
         let actual = program.method_chain_histogram(10).unwrap();
         assert_eq!(expected, actual);
     }
-}
\ No newline at end of file
+    #[test]
+    fn test_field_counting() {
+        let config = ChainConfig { count_fields: true, ..ChainConfig::default() };
+        let program = "System.out.println(x); a.b.c; d.e().f;";
+        assert_eq!(program.method_chain_counts(10).unwrap(), vec![1, 1]);
+        assert_eq!(program.method_chain_counts_with(10, &config).unwrap(), vec![2, 2, 2]);
+    }
+
+    #[test]
+    fn test_noarg_calls_as_fields() {
+        let fields = ChainConfig { count_fields: true, ..ChainConfig::default() };
+        let config = ChainConfig { treat_noarg_calls_as_fields: true, ..fields.clone() };
+        for (calls, accesses) in [("a.x().y();", "a.x.y;"), ("point.x().y().z(1);", "point.x.y.z(1);"),
+                                  ("x().y();", "x.y;"), ("this.x().y();", "this.x.y;"), ("f(x());", "f(x);")] {
+            let counts = accesses.method_chain_counts_with(10, &config).unwrap();
+            assert_eq!(calls.method_chain_counts_with(10, &config).unwrap(), counts, "{}", calls);
+            assert_eq!(accesses.method_chain_counts_with(10, &fields).unwrap(), counts, "{}", accesses);
+        }
+        assert_eq!("a.x().y();".method_chain_counts_with(10, &config).unwrap(), vec![2]);
+        // Calls with arguments are no accessors.
+        assert_eq!("x(1).y();".method_chain_counts_with(10, &config).unwrap(), vec![2]);
+        assert_eq!("x().y();".method_chain_counts_with(10, &fields).unwrap(), vec![2]);
+        // Only with fields counted.
+        let calls_only = ChainConfig { treat_noarg_calls_as_fields: true, ..ChainConfig::default() };
+        assert_eq!("x().y();".method_chain_counts_with(10, &calls_only).unwrap(), vec![2]);
+    }
+
+    #[test]
+    fn test_import_and_package_skipped() {
+        let config = ChainConfig { count_fields: true, ..ChainConfig::default() };
+        let program = r#"
+            package com.example.app;
+            import java.util.List;
+            import static java.util.stream.Collectors.toList;
+        "#;
+        assert_eq!(program.method_chain_counts(10).unwrap(), Vec::<usize>::new());
+        assert_eq!(program.method_chain_counts_with(10, &config).unwrap(), Vec::<usize>::new());
+
+        let program = "import java.util.List; class A { void f() { a.b().c(); } }";
+        assert_eq!(program.method_chain_counts_with(10, &config).unwrap(), vec![2]);
+    }
+    #[test]
+    fn test_longest_chain_methods() {
+        assert_eq!("a().bb().ccc()".longest_chain_methods(10).unwrap(), vec!["a", "bb", "ccc"]);
+        assert_eq!("x.f(y.g().h()).i()".longest_chain_methods(10).unwrap(), vec!["f", "i"]);
+        assert_eq!("x.f(y.g().h().j()).i()".longest_chain_methods(10).unwrap(), vec!["g", "h", "j"]);
+        assert_eq!("a.b().c(); x.f(y.g().h()).i()".longest_chain_methods(10).unwrap(), vec!["b", "c"]);
+        assert_eq!("x = 1;".longest_chain_methods(10).unwrap(), Vec::<String>::new());
+
+        let config = ChainConfig { count_fields: true, ..ChainConfig::default() };
+        assert_eq!("System.out.println(a.b().c());".longest_chain_methods(10).unwrap(), vec!["b", "c"]);
+        assert_eq!("System.out.println(a.b().c()).d;".longest_chain_methods_with(10, &config).unwrap(), 
+                   vec!["out", "println", "d"]);
+    }
+    #[test]
+    fn test_indexing() {
+        let config = ChainConfig { count_indexing: true, ..ChainConfig::default() };
+
+        let program = "list.get(0)[1].foo();";
+        assert_eq!(program.method_chain_counts(10).unwrap(), vec![2]);
+        assert_eq!(program.method_chain_counts_with(10, &config).unwrap(), vec![3]);
+        assert_eq!(program.longest_chain_methods_with(10, &config).unwrap(), vec!["get", "[]", "foo"]);
+
+        let program = "matrix[i][j].foo().bar(); rows()[0][1];";
+        assert_eq!(program.method_chain_counts(10).unwrap(), vec![2, 1]);
+        assert_eq!(program.method_chain_counts_with(10, &config).unwrap(), vec![4, 3]);
+
+        let program = "void main(String[] args) { x = args[f()]; }";
+        assert_eq!(program.method_chain_counts(10).unwrap(), vec![1]);
+        assert_eq!(program.method_chain_counts_with(10, &config).unwrap(), vec![1, 1]);
+    }
+
+    #[test]
+    fn test_try_with_resources() {
+        let links = |program: &str| program.method_chains_with(10, &ChainConfig::default()).unwrap().into_iter().map(|chain| chain.links).collect::<Vec<_>>();
+        assert_eq!(links("try (var r = open()) { r.a().b(); }"), vec![vec!["open"], vec!["a", "b"]]);
+        let program = "try (Reader r = Files.newReader(p).buffered(); Writer w = out.get()) { r.a().b(); } \
+                       catch (IOException e) { e.c(); } finally { x.y().z(); }";
+        assert_eq!(program.method_chain_counts(10).unwrap(), vec![2, 1, 2, 1, 2]);
+        assert_eq!("try (r; s.t()) { r.a(); }".method_chain_counts(10).unwrap(), vec![1, 1]);
+        assert_eq!("try { a().b(); } catch (E e) { }".method_chain_counts(10).unwrap(), vec![2]);
+        // Calls named like the restricted keywords of modules.
+        assert_eq!("Files.open().to(x).requires();".method_chain_counts(10).unwrap(), vec![3]);
+    }
+
+    #[test]
+    fn test_synchronized_blocks() {
+        assert_eq!("synchronized(lock) { a.b().c(); }".method_chain_counts(10).unwrap(), vec![2]);
+        assert_eq!("synchronized (this.lock()) { a.b().c(); }".method_chain_counts(10).unwrap(), vec![1, 2]);
+        assert_eq!("synchronized (locks.get(k).inner) { d(); synchronized (o) { e().f(); } }".method_chain_counts(10).unwrap(),
+                   vec![1, 1, 2]);
+        assert_eq!("public synchronized void f() { a.b(); }".method_chain_counts(10).unwrap(), vec![1]);
+    }
+
+    // The subscript of the root is no link, but the chain goes on after it.
+    #[test]
+    fn test_subscript_rooted_chain() {
+        let config = ChainConfig { count_indexing: true, ..ChainConfig::default() };
+        for program in ["arr[i].foo().bar();", "x = arr[i].foo().bar();", "a.b[i].foo().bar();"] {
+            assert_eq!(program.method_chain_counts(10).unwrap(), vec![2], "{}", program);
+            assert_eq!(program.method_chain_counts_with(10, &config).unwrap(), vec![3], "{}", program);
+        }
+        assert_eq!("arr[i].foo().bar();".longest_chain_methods(10).unwrap(), vec!["foo", "bar"]);
+        // A chain in the subscript is a chain of its own.
+        assert_eq!("arr[x.y()].foo().bar();".method_chain_counts(10).unwrap(), vec![1, 2]);
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_analyze_reader() {
+        let program = "a().b(); c.d().e().f();";
+        let reader: &[u8] = program.as_bytes();
+        let actual = analyze_reader(reader, 10).await.unwrap();
+        assert_eq!(actual, program.method_chain_counts(10).unwrap());
+        assert_eq!(actual, vec![2, 3]);
+    }
+    #[test]
+    fn test_strict_tokenizer() {
+        let config = TokenizerConfig { strict: true, ..TokenizerConfig::default() };
+
+        let tokens = tokenize_with("list2.get(10);", &config).unwrap();
+        assert_eq!(tokens, vec![
+            Token::Identifier("list2".to_owned()), Token::Dot, Token::Identifier("get".to_owned()), 
+            Token::OpenParen, Token::String("10".to_owned()), Token::CloseParen, Token::Punctuation(';'),
+        ]);
+
+        let error = tokenize_with("int 2fast#x = 1;", &config).unwrap_err();
+        assert_eq!(error, TokenizeError { token: "2fast#x".to_owned() });
+        assert!(tokenize_with("int 2fast#x = 1;", &TokenizerConfig::default()).is_ok());
+    }
+    #[test]
+    fn test_whitespace_tokens() {
+        let config = TokenizerConfig { whitespace: true, ..TokenizerConfig::default() };
+        let tokens = tokenize_with("  a .b(\t\n) ", &config).unwrap();
+        assert_eq!(tokens, vec![
+            Token::Whitespace(2), Token::Identifier("a".to_owned()), Token::Whitespace(1), Token::Dot,
+            Token::Identifier("b".to_owned()), Token::OpenParen, Token::Whitespace(2), Token::CloseParen,
+            Token::Whitespace(1),
+        ]);
+
+        let source = remove_comments("class A {\n    void f(String... args) {\n        a -> b;  // c\n        new B().c( x-->0 ).d();\n    }\n}\n");
+        let tokens = tokenize_with(&source, &config).unwrap();
+        let length: usize = tokens.iter()
+            .map(|token| match token {
+                Token::Punctuation(character) => character.len_utf8(),
+                Token::String(text) | Token::Keyword(text) | Token::Identifier(text) => text.len(),
+                Token::Ellipsis => 3,
+                Token::Arrow => 2,
+                Token::Whitespace(length) => *length,
+                // Followed by `Token::Keyword("new")`.
+                Token::New => 0,
+                _ => 1,
+            })
+            .sum();
+        assert_eq!(length, source.len());
+
+        let mut detector = detector::Detector::new(100);
+        detector.feed_all(tokens).unwrap();
+        assert_eq!(detector.finish(), source.method_chain_counts(100).unwrap());
+    }
+    #[test]
+    fn test_ellipsis() {
+        let tokens = tokenize("foo(String... a)");
+        assert_eq!(tokens, vec![
+            Token::Identifier("foo".to_owned()), Token::OpenParen, Token::Identifier("String".to_owned()), 
+            Token::Ellipsis, Token::Identifier("a".to_owned()), Token::CloseParen,
+        ]);
+        assert!(!tokens.contains(&Token::Dot));
+        assert_eq!("void foo(String... a) { a.b().c(); }".method_chain_counts(10).unwrap(), vec![2]);
+    }
+    #[test]
+    fn test_javadoc_code_snippets() {
+        let program = r#"
+            /**
+             * Use it like {@code builder.name("x").build()} or see {@link Foo#bar()}.
+             * <pre>{@code
+             * list.stream()
+             *     .map(x -> { return x; })
+             *     .count();
+             * }</pre>
+             * Not {@codex a().b()} though.
+             */
+            void f() { a.b(); } // {@code c().d()}
+        "#;
+        assert_eq!(javadoc_code_snippets(program), vec![
+            "builder.name(\"x\").build()", 
+            "Foo#bar()", 
+            "list.stream()\n     .map(x -> { return x; })\n     .count();",
+        ]);
+
+        let config = ChainConfig { analyze_javadoc_code: true, ..ChainConfig::default() };
+        assert_eq!(program.method_chain_counts(10).unwrap(), vec![1]);
+        assert_eq!(program.method_chain_counts_with(10, &config).unwrap(), vec![1, 2, 3]);
+    }
+    #[test]
+    fn test_for_each_chain() {
+        let program = r#"
+            list.stream().map(s -> s.replace("foo", "bar").replace("baz", "qux"))
+                .forEach(s -> System.out.println(s.split(",").length));
+            a()[f().g()].h();
+        "#;
+        let mut lengths = Vec::new();
+        for_each_chain(tokenize(&remove_comments(program)), 10, |chain_length| lengths.push(chain_length)).unwrap();
+        assert_eq!(lengths, program.method_chain_counts(10).unwrap());
+        assert_eq!(lengths, vec![2, 1, 1, 3, 2, 2]);
+
+        let mut total = 0;
+        assert!(for_each_chain(tokenize("a(b(c(d())))"), 2, |_| total += 1).is_err());
+    }
+
+    #[test]
+    fn test_chain_at_end_of_input() {
+        assert_eq!("a().b()".method_chain_counts(10).unwrap(), vec![2]);
+        assert_eq!("x = 1; a().b()".method_chain_counts(10).unwrap(), vec![2]);
+        assert_eq!("a().b().".method_chain_counts(10).unwrap(), vec![2]);
+        assert_eq!("a().b().c".method_chain_counts(10).unwrap(), vec![2]);
+
+        let config = ChainConfig { count_fields: true, ..ChainConfig::default() };
+        assert_eq!("a().b().c".method_chain_counts_with(10, &config).unwrap(), vec![3]);
+        assert_eq!(tokenize("a.b"), vec![
+            Token::Identifier("a".to_owned()), Token::Dot, Token::Identifier("b".to_owned()),
+        ]);
+    }
+
+    #[test]
+    fn test_long_single_line() {
+        // Minified sources put megabytes on one line. Nesting is tracked on
+        // the heap, so even half a million nested calls cannot overflow
+        // the stack.
+        let depth = 1 << 19;
+        let nested = format!("{}{};", "a(".repeat(depth), ")".repeat(depth));
+        assert!(nested.len() > 1 << 20);
+        let counts = nested.method_chain_counts(usize::MAX).unwrap();
+        assert_eq!(counts, vec![1; depth]);
+
+        let chained = format!("x{};", ".a()".repeat(depth));
+        assert_eq!(chained.method_chain_counts(10).unwrap(), vec![depth]);
+    }
+
+    #[test]
+    fn test_assignment_breaks_chains() {
+        assert_eq!("x = a.b();".method_chain_counts(10).unwrap(), vec![1]);
+        assert_eq!("x += a.b();".method_chain_counts(10).unwrap(), vec![1]);
+        assert_eq!("x().y() = a.b();".method_chain_counts(10).unwrap(), vec![2, 1]);
+        for operator in &["=", "+=", "-=", "*=", "/=", "%=", "&=", "|=", "^=", "<<=", ">>=", ">>>=", "==", "!=", "<=", ">="] {
+            let program = format!("x().y() {} a().b().c();", operator);
+            assert_eq!(program.method_chain_counts(10).unwrap(), vec![2, 3], "{}", program);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_analyze_path_mmap() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("A.java");
+        let config = ChainConfig::default();
+
+        std::fs::write(&path, "class A { void f() { a().b().c(); d.e(); /* f().g() */ \u{e9}().h(); } }").unwrap();
+        assert_eq!(analyze_path_mmap_with(&path, 10, &config).unwrap(), vec![3, 1, 2]);
+        assert_eq!(analyze_path_mmap_with(&path, 10, &config).unwrap(), analyze_path_with(&path, 10, &config).unwrap());
+
+        std::fs::write(&path, b"a().b(); \xff\xfe c().d().e();").unwrap();
+        assert_eq!(analyze_path_mmap_with(&path, 10, &config).unwrap(), analyze_path_with(&path, 10, &config).unwrap());
+
+        std::fs::write(&path, "").unwrap();
+        assert_eq!(analyze_path_mmap_with(&path, 10, &config).unwrap(), Vec::<usize>::new());
+
+        assert!(analyze_path_mmap_with(&dir.path().join("Missing.java"), 10, &config).is_err());
+    }
+
+    #[test]
+    fn test_array_initializer() {
+        assert_eq!("int[] xs = new int[]{ a.b(), c.d() };".method_chain_counts(10).unwrap(), vec![1, 1]);
+        assert_eq!("int[] xs = { a.b(), c.d() };".method_chain_counts(10).unwrap(), vec![1, 1]);
+        assert_eq!("f(new String[]{ a.b().c(), d.e() }).g();".method_chain_counts(10).unwrap(), vec![2, 1, 2]);
+    }
+
+    #[test]
+    fn test_method_call_count() {
+        let program = r#"
+            class A {
+                void f(int x) {                          // 1: the declaration
+                    if (x > 0) {                         // keywords are not calls
+                        list.stream().map(y).collect();  // 3
+                        g(h(), new B());                 // 3, the constructor included
+                        // i().j();                      comments are skipped
+                    }
+                    int[] xs = { k() };                  // 1
+                }
+            }
+        "#;
+        assert_eq!(program.method_call_count(), 8);
+        assert_eq!("".method_call_count(), 0);
+        assert_eq!(program.to_owned().method_call_count(), Cow::Borrowed(program).method_call_count());
+    }
+
+    #[test]
+    fn test_max_nesting_depth() {
+        assert_eq!("a(b(c(d())));".max_nesting_depth(), 4);
+        assert_eq!("class A { void f() { g(x[0]); } }".max_nesting_depth(), 4);
+        assert_eq!("a().b().c();".max_nesting_depth(), 1);
+        assert_eq!("x = 1;".max_nesting_depth(), 0);
+        // Unbalanced closers do not make it negative, nor comments deeper.
+        assert_eq!(")) // ((((\na(b());".max_nesting_depth(), 0);
+    }
+
+    #[test]
+    fn test_immediate_calls() {
+        assert_eq!("f()();".method_chain_counts(10).unwrap(), vec![1]);
+        assert_eq!("f()().g();".method_chain_counts(10).unwrap(), vec![2]);
+        assert_eq!("supplier.get()(x.y());".method_chain_counts(10).unwrap(), vec![1, 1]);
+        assert_eq!("f()(); g();".method_chain_counts(10).unwrap(), vec![1, 1]);
+
+        let config = ChainConfig { count_immediate_calls: true, ..ChainConfig::default() };
+        assert_eq!("f()();".method_chain_counts_with(10, &config).unwrap(), vec![2]);
+        assert_eq!("f()().g();".method_chain_counts_with(10, &config).unwrap(), vec![3]);
+        assert_eq!("supplier.get()(x.y());".method_chain_counts_with(10, &config).unwrap(), vec![1, 2]);
+        assert_eq!("f()(); g();".method_chain_counts_with(10, &config).unwrap(), vec![2, 1]);
+        assert_eq!("f()()()".longest_chain_methods_with(10, &config).unwrap(), vec!["f", "()", "()"]);
+    }
+
+    #[test]
+    fn test_package_name() {
+        assert_eq!(package_name("package a.b.c;\nclass A {}"), Some("a.b.c".to_owned()));
+        assert_eq!(package_name("/* package x; */ // package y;\n@Generated\npackage a . b ;"), Some("a.b".to_owned()));
+        assert_eq!(package_name("import a.b.C;\nclass D { void f() { g(); } }"), None);
+        assert_eq!(package_name("class A { String s = \"package\"; }"), None);
+        assert_eq!(package_name(""), None);
+    }
+
+    #[test]
+    fn test_comment_removal_edge_cases() {
+        for contents in &["/", "//", "/*", "*/", "/*/", "/**/", "a/", "/*\n*", "//\r\n/", "\u{e9}/\u{e9}"] {
+            let clean = remove_comments(contents);
+            assert!(clean.len() <= contents.len(), "{:?} became {:?}", contents, clean);
+            tokenize(&clean);
+        }
+        assert_eq!(remove_comments("a/b"), "a/b");
+        assert_eq!(remove_comments("a /* b */ c"), "a   c");
+        assert_eq!(remove_comments("a/**/b"), "a b");
+        assert_eq!(tokenize(&remove_comments("foo/**/bar")), vec![
+            Token::Identifier("foo".to_owned()), Token::Identifier("bar".to_owned()),
+        ]);
+    }
+
+    #[test]
+    fn test_strip_generics() {
+        assert_eq!(strip_generics(&tokenize("Map.<String, Integer>of().size()")), tokenize("Map.of().size()"));
+        assert_eq!(strip_generics(&tokenize("Map<String, List<int[]>> m; new ArrayList<>()")), tokenize("Map m; new ArrayList()"));
+        assert_eq!(strip_generics(&tokenize("<T extends A & B> T f(Class<? super T> c)")), tokenize("<T extends A & B> T f(Class c)"));
+        for kept in ["if (a < b && c > d)", "i < n; i++", "a < f(b) > c", "List<@NonNull String>", "a <"] {
+            assert_eq!(strip_generics(&tokenize(kept)), tokenize(kept), "{}", kept);
+        }
+
+        let config = ChainConfig { strip_generics: true, ..ChainConfig::default() };
+        for program in ["Map.<String, Integer>of().size();", "Collections.<List<String>>emptyList().stream().count();", "Map<K, V> m = a.b().c();"] {
+            assert_eq!(program.method_chain_counts_with(10, &config).unwrap(), program.method_chain_counts(10).unwrap(), "{}", program);
+        }
+        let spans = "List<String>\n  x = a\n  .b().c();".method_chain_spans_with(10, &config).unwrap();
+        assert_eq!(spans, vec![ChainSpan { start_line: 2, length: 2 }]);
+    }
+
+    #[test]
+    fn test_comment_syntax() {
+        let hash = CommentSyntax::hash();
+        assert_eq!(remove_comments_with("a.b() # c.d()\n# e()\nf() // g", &hash), "a.b() \n\nf() // g");
+        assert_eq!(remove_comments(r#"f("//", '"', "\"/*") // g"#), r#"f("//", '"', "\"/*") "#);
+        assert_eq!(remove_comments_with("fn f<'a>() {} // '", &CommentSyntax::rust()), "fn f<'a>() {} ");
+
+        // Delimiters of several characters, which may share a start.
+        let block = CommentSyntax { line_prefixes: vec!["--".to_owned()], block: Some(("{-".to_owned(), "-}".to_owned())), nested: false, quotes: Vec::new() };
+        assert_eq!(remove_comments_with("a {- b {- c -} d -} e -- f\ng - h", &block), "a   d -} e \ng - h");
+        assert_eq!(remove_comments_with("a{--}b{---}c-{-", &block), "a b c-");
+
+        let nested = CommentSyntax { nested: true, ..block };
+        assert_eq!(remove_comments_with("a {- b {- c -} d -} e", &nested), "a   e");
+        // Split anywhere, chunks give the same text.
+        let contents = "x {- {- -} -}-- y\nz";
+        let mut remover = CommentRemover::new(nested.clone());
+        let mut output = String::new();
+        contents.chars().for_each(|character| remover.push_str(&character.to_string(), &mut output));
+        assert_eq!(output, remove_comments_with(contents, &nested));
+        assert_eq!(output, "x  \nz");
+    }
+
+    #[test]
+    fn test_rust_comments() {
+        let rust = CommentSyntax::rust();
+        assert_eq!(remove_comments_with("a /* b /* c */ d */ e", &rust), "a   e");
+        assert_eq!(remove_comments_with("a /* b /* c */ d */ e", &CommentSyntax::java()), "a   d */ e");
+        assert_eq!(remove_comments_with("a /* b // c */ d", &rust), "a   d");
+        for contents in &["/*/", "/* /*/ */", "/* /", "/*/**/"] {
+            let clean = remove_comments_with(contents, &rust);
+            assert!(clean.len() <= contents.len(), "{:?} became {:?}", contents, clean);
+        }
+    }
+
+    #[test]
+    fn test_rust_literals() {
+        let tokens = |source: &str| tokenize_source(source, Language::Rust);
+        assert_eq!(tokens(r###"r#"a "b" c"# r##"d"#e"## r"\" br#"f"#"###), vec![
+            Token::String(r###"r#"a "b" c"#"###.to_owned()),
+            Token::String(r###"r##"d"#e"##"###.to_owned()),
+            Token::String(r#"r"\""#.to_owned()),
+            Token::String(r###"br#"f"#"###.to_owned()),
+        ]);
+        assert_eq!(tokens(r"<'a> '\\' 'b' b'c'"), vec![
+            Token::Punctuation('<'), Token::String("'a".to_owned()), Token::Punctuation('>'),
+            Token::String(r"'\\'".to_owned()), Token::String("'b'".to_owned()), Token::String("b'c'".to_owned()),
+        ]);
+        assert_eq!(tokens("Vec::new"), vec![
+            Token::Identifier("Vec".to_owned()), Token::DoubleColon, Token::Identifier("new".to_owned()),
+        ]);
+    }
+
+    #[test]
+    fn test_rust_chains() {
+        let config = ChainConfig { language: Language::Rust, ..ChainConfig::default() };
+        let chains = |source: &str| source.method_chain_counts_with(10, &config).unwrap();
+        assert_eq!(chains("let total: u32 = self.items.iter().filter(|item| item.is_ok()).map(Item::price).sum();"), vec![1, 4]);
+        assert_eq!(chains("let names = Vec::new().into_iter().collect::<Vec<String>>().join(\", \");"), vec![4]);
+        assert_eq!(chains("let text = std::fs::read_to_string(path)?.trim().to_owned();"), vec![3]);
+        assert_eq!(chains(r###"let query = r#"SELECT a.b() FROM "c.d()""#; db.run(query).unwrap();"###), vec![2]);
+        assert_eq!(chains("fn first<'a>(words: &'a [&'a str]) -> &'a str { words.iter().next().unwrap() }"), vec![3]);
+        assert_eq!(chains("/* a().b() /* c() */ d().e() */ f().g();"), vec![2]);
+
+        // The braces of a use tree are part of the declaration.
+        assert_eq!(chains("use std::collections::{HashMap, HashSet};\nfn main() { let x = a.b().c(); }"), vec![2]);
+        assert_eq!(chains("use a::{b::{c, d}, e};\nfn main() { a.b().c(); }"), vec![2]);
+        assert_eq!(chains("fn f() { x.a().b(); }\nmod m { use a::{b, c}; }\nfn g() { y.c().d(); }"), vec![2, 2]);
+
+        // The same source as Java, where `?.` is a safe call.
+        let java = ChainConfig::default();
+        assert_eq!("std::fs::read_to_string(path)?.trim();".method_chain_counts_with(10, &java).unwrap(), vec![2]);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_config_for_path() {
+        let config = ChainConfig { language: Language::Rust, ..ChainConfig::default() };
+        let java = ChainConfig::default();
+        assert_eq!(config.for_path(Path::new("A.java")).language, Language::Java);
+        assert_eq!(java.for_path(Path::new("src/main.RS")).language, Language::Rust);
+        assert_eq!(config.for_path(Path::new("A.kt")).language, Language::Rust);
+    }
+
+    #[test]
+    fn test_annotation_type() {
+        let program = r#"
+            public @interface Range {
+                int count() default 0;
+                String value() default "x";
+                Class<?> type() default Object.class;
+                String[] names() default {};
+                List<String> tags();
+            }
+        "#;
+        assert_eq!(program.method_chain_counts(10).unwrap(), Vec::<usize>::new());
+
+        // Only the declarations directly in the body are not calls.
+        let program = r#"
+            @Retention(RetentionPolicy.RUNTIME)
+            @interface Marker {
+                int size() default 1;
+                List<Integer> SIZES = Arrays.asList(1, 2).subList(0, 1);
+            }
+            interface Plain { List<String> names(); }
+            class A { void f() { g(); } }
+        "#;
+        assert_eq!(program.method_chain_counts(10).unwrap(), vec![1, 2, 1, 1]);
+    }
+
+    #[test]
+    fn test_non_getter_chains() {
+        let links = |chains: Vec<Chain>| chains.into_iter().map(|chain| chain.links).collect::<Vec<_>>();
+        assert_eq!(links("a.getA().getB();".non_getter_chains(10).unwrap()), Vec::<Vec<String>>::new());
+        assert_eq!(links("a.getA().transform();".non_getter_chains(10).unwrap()), vec![vec!["getA", "transform"]]);
+        assert_eq!(links("x.isEmpty(); y.get().island();".non_getter_chains(10).unwrap()), vec![vec!["get", "island"]]);
+
+        let getters = GetterPatterns { prefixes: vec!["to".to_owned()] };
+        let program = "a.getA().getB(); b.toList().toArray();";
+        assert_eq!(links(program.non_getter_chains_with(10, &ChainConfig::default(), &getters).unwrap()),
+                   vec![vec!["getA", "getB"]]);
+    }
+
+    #[test]
+    fn test_byte_sources() {
+        let program = "class A { void f() { a().b(c.d()); x.y(\"\u{e9}t\u{e9}\").z(); /* \u{2192} e().f() */ } }";
+        let bytes = program.as_bytes();
+        let config = ChainConfig { count_fields: true, ..ChainConfig::default() };
+        assert_eq!(bytes.method_chains_with(10, &config).unwrap(), program.method_chains_with(10, &config).unwrap());
+        assert_eq!(bytes.method_chain_spans(10).unwrap(), program.method_chain_spans(10).unwrap());
+        assert_eq!(bytes.max_nesting_depth(), program.max_nesting_depth());
+        assert_eq!(bytes.to_vec().method_chain_counts(10).unwrap(), vec![1, 2, 2]);
+        assert_eq!(method_chain_counts_strict_with(bytes, 10, &config).unwrap(), program.method_chain_counts_with(10, &config).unwrap());
+
+        let invalid = b"a().b(); \xff\xfe c().d().e();";
+        assert_eq!(invalid.as_slice().method_chain_counts(10).unwrap(), String::from_utf8_lossy(invalid).method_chain_counts(10).unwrap());
+        assert!(method_chain_counts_strict_with(invalid, 10, &ChainConfig::default()).is_err());
+    }
+
+    #[test]
+    fn test_method_chain_counts_bytes() {
+        let program = r#"
+            import java.util.List;
+            class A {
+                /* a().b().c(); */
+                void f(String... args) {
+                    list.stream().map(x -> x.y()).collect(toList()); // d().e();
+                    g(h().i(), new int[]{ j.k() });
+                }
+            }
+        "#;
+        assert_eq!(method_chain_counts_bytes(program.as_bytes(), 100).unwrap(), program.method_chain_counts(100).unwrap());
+
+        // Non-ASCII identifiers count the same, as do invalid bytes within them.
+        assert_eq!(method_chain_counts_bytes("\u{e9}t\u{e9}().\u{fc}ber();".as_bytes(), 10).unwrap(), vec![2]);
+        assert_eq!(method_chain_counts_bytes(b"a\xff().b();", 10).unwrap(), vec![2]);
+        assert!(method_chain_counts_bytes(b"a(b(c()))", 1).is_err());
+    }
+
+    #[test]
+    fn test_tokenize_with_lines() {
+        let source = "a /* x\n y */ .b(\n  c// d\n) ... e";
+        let (tokens, lines) = tokenize_with_lines(source);
+        assert_eq!(tokens, tokenize(&remove_comments(source)));
+        assert_eq!(lines, vec![1, 2, 2, 2, 3, 4, 4, 4]);
+    }
+
+    #[test]
+    fn test_method_chain_spans() {
+        let program = "class A {\n  void f() {\n    /* a().b();\n */ list\n      .stream()\n      .map(x -> x.y())\n      .count();\n    new B().c();\n  }\n}\n";
+        assert_eq!(program.method_chain_spans(10).unwrap(), vec![
+            ChainSpan { start_line: 6, length: 1 },
+            ChainSpan { start_line: 4, length: 3 },
+            ChainSpan { start_line: 8, length: 1 },
+        ]);
+        assert_eq!(program.method_chain_spans(10).unwrap().iter().map(|span| span.length).collect::<Vec<_>>(),
+                   program.method_chain_counts(10).unwrap());
+    }
+
+    #[test]
+    fn test_arrow() {
+        let identifier = |name: &str| Token::Identifier(name.to_owned());
+        assert_eq!(tokenize("x -> y"), vec![identifier("x"), Token::Arrow, identifier("y")]);
+        assert_eq!(tokenize("x->y"), vec![identifier("x"), Token::Arrow, identifier("y")]);
+        assert_eq!(tokenize("x - > y")[1..3], [Token::Punctuation('-'), Token::Punctuation('>')]);
+        assert_eq!(tokenize("x-->0")[1..4], [Token::Punctuation('-'), Token::Punctuation('-'), Token::Punctuation('>')]);
+        assert_eq!(tokenize("x--->0")[1..4], [Token::Punctuation('-'), Token::Punctuation('-'), Token::Arrow]);
+    }
+
+    #[test]
+    fn test_transparent_methods() {
+        let config = ChainConfig { transparent_methods: vec!["requireNonNull".to_owned(), "ofNullable".to_owned()], ..ChainConfig::default() };
+        let counts = |program: &str| program.method_chain_counts_with(10, &config).unwrap();
+        assert_eq!(counts("a.b().c();"), vec![2]);
+        assert_eq!(counts("Objects.requireNonNull(a.b()).c();"), vec![2]);
+        assert_eq!(counts("requireNonNull(requireNonNull(a.b())).c().d();"), vec![3]);
+        assert_eq!(counts("requireNonNull(a).c();"), vec![1]);
+        assert_eq!(counts("f(Optional.ofNullable(a.b()));"), vec![1, 1]);
+        assert_eq!(counts("requireNonNull(a.b(), c.d());"), vec![1, 1]);
+        assert_eq!(counts("requireNonNull(a.b()"), vec![1]);
+        assert_eq!("Objects.requireNonNull(a.b()).c();".method_chain_counts(10).unwrap(), vec![1, 2]);
+
+        let chains = "x = requireNonNull(a.b(y)).c();".method_chains_with(10, &config).unwrap();
+        assert_eq!(chains[0].links, vec!["b".to_owned(), "c".to_owned()]);
+        assert_eq!((chains[0].start, chains[0].calls_with_arguments), (4, 1));
+    }
+
+    #[test]
+    fn test_safe_calls() {
+        let identifier = |name: &str| Token::Identifier(name.to_owned());
+        assert_eq!(tokenize("a?.b"), vec![identifier("a"), Token::SafeDot, identifier("b")]);
+        assert_eq!(tokenize("c ? .5 : 1")[1..3], [Token::Punctuation('?'), Token::Dot]);
+        assert_eq!(tokenize_source("f()?.g()", Language::Rust)[3..5], [Token::Punctuation('?'), Token::Dot]);
+
+        // The receiver is no link, so these are all as long as `a.b().c().d()`.
+        let chains = |program: &str| program.method_chain_counts(10).unwrap();
+        assert_eq!(chains("a.b()?.c()?.d();"), vec![3]);
+        assert_eq!(chains("a?.b().c()?.d();"), vec![3]);
+        assert_eq!(chains("a.b()?.c().d();"), chains("a.b().c().d();"));
+        assert_eq!(chains("x?.y?.z();"), vec![1]);
+    }
+
+    #[test]
+    fn test_brace_scopes() {
+        let links = |program: &str| program.method_chains_with(10, &ChainConfig::default()).unwrap()
+            .into_iter()
+            .map(|chain| chain.links)
+            .collect::<Vec<_>>();
+        let strings = |links: &[&[&str]]| links.iter()
+            .map(|chain| chain.iter().map(|link| link.to_string()).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        // Static and instance initializer blocks, and a method body.
+        assert_eq!(links("class A { static { a().b(); } { c().d().e(); } void f() { g().h(); } }"), strings(&[&["a", "b"], &["c", "d", "e"], &["g", "h"]]));
+        // A closing brace ends a chain without a semicolon, and nothing
+        // carries over from one block to the next.
+        assert_eq!(links("class A { void f() { a().b() } void g() { c(); } }"), strings(&[&["a", "b"], &["c"]]));
+        assert_eq!(links("class A { void f() { if (x) { a().b(); } else { c(); } } }"), strings(&[&["a", "b"], &["c"]]));
+        assert_eq!(links("class A { { a() } .b(); }"), strings(&[&["a"], &["b"]]));
+    }
+
+    #[test]
+    fn test_lambda_block_body() {
+        let links = |program: &str| program.method_chains_with(10, &ChainConfig::default()).unwrap()
+            .into_iter()
+            .map(|chain| chain.links)
+            .collect::<Vec<_>>();
+        assert_eq!(links("list.forEach(x -> { x.a().b(); });"), vec![
+            vec!["a".to_owned(), "b".to_owned()],
+            vec!["forEach".to_owned()],
+        ]);
+        // The block ends the chains in it, and the chain around it goes on.
+        assert_eq!(links("f(x -> { return x.a().b(); }).g();"), vec![
+            vec!["a".to_owned(), "b".to_owned()],
+            vec!["f".to_owned(), "g".to_owned()],
+        ]);
+    }
+
+    #[test]
+    fn test_chain_counts_by_root_kind() {
+        let program = r#"
+            class A extends B {
+                void f() {
+                    this.a().b();
+                    c().d().e();
+                    super.f().g();
+                    new StringBuilder().append(x).append(y).toString();
+                    Stream.of(x).map(y);
+                    list.stream().count();
+                    this.list.size();
+                }
+            }
+        "#;
+        let tokens = tokenize(&remove_comments(program));
+        let counts = chain_counts_by_root_kind(&tokens, 100, &ChainConfig::default()).unwrap();
+        assert_eq!(counts, BTreeMap::from([
+            (RootKind::This, vec![2, 3, 1]),
+            (RootKind::Super, vec![2]),
+            (RootKind::New, vec![3]),
+            (RootKind::ClassName, vec![2]),
+            (RootKind::Variable, vec![2]),
+        ]));
+    }
+
+    #[test]
+    fn test_double_colon() {
+        let identifier = |name: &str| Token::Identifier(name.to_owned());
+        assert_eq!(tokenize("String::trim"), vec![identifier("String"), Token::DoubleColon, identifier("trim")]);
+        assert_eq!(tokenize("a ? b : c")[3], Token::Punctuation(':'));
+        assert_eq!(tokenize("case A: :: "), vec![
+            Token::Keyword("case".to_owned()), identifier("A"), Token::Punctuation(':'), Token::DoubleColon,
+        ]);
+
+        // Split between the colons.
+        let mut tokenizer = Tokenizer::new(&TokenizerConfig::default());
+        let mut tokens = Vec::new();
+        tokenizer.push_str("a:", &mut tokens).unwrap();
+        assert_eq!(tokenizer.unsettled(), 1);
+        tokenizer.push_str(":b", &mut tokens).unwrap();
+        tokenizer.finish(&mut tokens).unwrap();
+        assert_eq!(tokens, vec![identifier("a"), Token::DoubleColon, identifier("b")]);
+    }
+
+    #[test]
+    fn test_count_method_references() {
+        let program = "list.forEach(System.out::println);";
+        assert_eq!(program.method_chain_counts(10).unwrap(), vec![1]);
+
+        let config = ChainConfig { count_method_references: true, ..ChainConfig::default() };
+        assert_eq!(program.method_chain_counts_with(10, &config).unwrap(), vec![1, 1]);
+        let chains = program.method_chains_with(10, &config).unwrap();
+        assert_eq!(chains[0].links, vec!["println".to_owned()]);
+
+        let program = "Stream.of(a).map(String::trim).toArray(String[]::new);";
+        assert_eq!(program.method_chain_counts(10).unwrap(), vec![3]);
+        assert_eq!(program.method_chain_counts_with(10, &config).unwrap(), vec![1, 1, 3]);
+
+        // Either way, a reference is an argument and not a link of the chain
+        // around it, which stays 3 links long.
+        let links = |config: &ChainConfig| "a().map(Foo::bar).count();".method_chains_with(10, config).unwrap()
+            .into_iter()
+            .map(|chain| chain.links)
+            .collect::<Vec<_>>();
+        let outer = vec!["a".to_owned(), "map".to_owned(), "count".to_owned()];
+        assert_eq!(links(&ChainConfig::default()), vec![outer.clone()]);
+        assert_eq!(links(&config), vec![vec!["bar".to_owned()], outer]);
+    }
+
+    #[test]
+    fn test_count_method_refs_in_args() {
+        let program = "a().map(Foo::bar).count(); list.forEach(System.out::println); Stream.of(x).toArray(String[]::new);";
+        assert_eq!(program.method_chain_counts(10).unwrap(), vec![3, 1, 2]);
+
+        let config = ChainConfig { count_method_refs_in_args: true, ..ChainConfig::default() };
+        assert_eq!(program.method_chain_counts_with(10, &config).unwrap(), vec![4, 2, 3]);
+        assert_eq!(program.longest_chain_methods_with(10, &config).unwrap(), vec!["a", "map", "bar", "count"]);
+        // Not an argument of a call.
+        assert_eq!("Function<String, String> f = String::trim;".method_chain_counts_with(10, &config).unwrap(), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn test_switch_rules() {
+        let program = r#"
+            int size = switch (kind) {
+                case LIST -> list.stream().filter(x -> x.isValid()).count();
+                case MAP, SET -> map.values().size();
+                case Kind.OTHER -> other.size();
+                default -> {
+                    log.warn("unknown");
+                    yield 0;
+                }
+            };
+        "#;
+        assert_eq!(program.method_chain_counts(10).unwrap(), vec![1, 3, 2, 1, 1]);
+        let config = ChainConfig { count_fields: true, ..ChainConfig::default() };
+        assert_eq!(program.method_chain_counts_with(10, &config).unwrap(), vec![1, 3, 2, 1, 1, 1]);
+    }
+
+    // An array creation is not a link itself, like a constructor call: what
+    // follows it starts a new chain, `.clone().h()` of length 2, while
+    // `.length` is a field that only counts with `count_fields`. The chains
+    // in dimensions and initializers are counted on their own.
+    #[test]
+    fn test_array_creation() {
+        let program = r#"
+            int n = new String[]{a.b(), c.d().e()}.length;
+            Object x = new String[]{f.g()}.clone().h();
+            int[][] grid = new int[rows.size()][columns.get(0).size()];
+            int m = new int[3][4].clone().length;
+        "#;
+        assert_eq!(program.method_chain_histogram(10).unwrap(), BTreeMap::from([(1, 4), (2, 3)]));
+        let config = ChainConfig { count_fields: true, ..ChainConfig::default() };
+        assert_eq!(program.method_chain_counts_with(10, &config).unwrap(), vec![1, 2, 1, 1, 2, 1, 2, 2]);
+    }
+
+    // `this.` and `super.` only qualify the root, so they add no link.
+    #[test]
+    fn test_this_and_super_qualifiers() {
+        for program in ["this.a().b();", "super.a().b();", "a().b();"] {
+            assert_eq!(program.method_chain_counts(10).unwrap(), vec![2], "{}", program);
+        }
+        let config = ChainConfig { count_fields: true, ..ChainConfig::default() };
+        for program in ["this.list.stream().count();", "list.stream().count();"] {
+            assert_eq!(program.method_chain_counts_with(10, &config).unwrap(), vec![2], "{}", program);
+        }
+        assert_eq!("this.a.b.c();".method_chain_counts_with(10, &config).unwrap(), vec![2]);
+    }
+
+    #[test]
+    fn test_parenthesized_receiver() {
+        let chains = "(a + b).toString().trim();".method_chains_with(10, &ChainConfig::default()).unwrap();
+        assert_eq!(chains.len(), 1);
+        assert_eq!(chains[0].links, vec!["toString".to_owned(), "trim".to_owned()]);
+        assert_eq!(chains[0].start, 0);
+
+        // A name or constructor after a cast or a condition is a new root.
+        let program = "if (x) new A().b(); for (; it.hasNext();) it.next().remove(); y = (Foo) a.b.c();";
+        assert_eq!(program.method_chain_counts(10).unwrap(), vec![1, 1, 2, 1]);
+        let config = ChainConfig { count_fields: true, ..ChainConfig::default() };
+        let chains = program.method_chains_with(10, &config).unwrap();
+        assert_eq!(chains[3].links, vec!["b".to_owned(), "c".to_owned()]);
+    }
+
+    #[test]
+    fn test_cast_in_argument() {
+        let chains = "a().foo((Bar) b.c()).d();".method_chains_with(10, &ChainConfig::default()).unwrap();
+        assert_eq!(chains.iter().map(|chain| chain.links.clone()).collect::<Vec<_>>(), vec![
+            vec!["c".to_owned()],
+            vec!["a".to_owned(), "foo".to_owned(), "d".to_owned()],
+        ]);
+        assert_eq!("a().foo((Bar) b.c()).d();".method_chain_histogram(10).unwrap(), BTreeMap::from([(1, 1), (3, 1)]));
+    }
+
+    // Each branch of a conditional argument is a chain of its own, and the
+    // `?` and `:` do not break the chain around the call.
+    #[test]
+    fn test_conditional_argument() {
+        let program = "a(cond ? x.y() : z.w()).b();";
+        assert_eq!(program.method_chain_counts(10).unwrap(), vec![1, 1, 2]);
+        assert_eq!(program.method_chain_histogram(10).unwrap(), BTreeMap::from([(1, 2), (2, 1)]));
+        assert_eq!("a(c ? x.y().q() : z.w()).b().c();".method_chain_counts(10).unwrap(), vec![2, 1, 3]);
+    }
+
+    #[test]
+    fn test_repeated_call_chains() {
+        assert_eq!("b.add(x).add(y).build();".repeated_call_chains(10).unwrap(), vec![3]);
+        assert_eq!("b.a().b().c();".repeated_call_chains(10).unwrap(), Vec::<usize>::new());
+        assert_eq!("b.add(x).build().add(y); s.append(a).append(b).append(c);".repeated_call_chains(10).unwrap(), vec![3]);
+    }
+
+    #[test]
+    fn test_method_chain_analysis() {
+        let program = "a().b(); c(); d.e().f(g().h()); i();";
+        let analysis = program.method_chain_analysis(10).unwrap();
+        assert_eq!(analysis.counts, program.method_chain_counts(10).unwrap());
+        assert_eq!(analysis.histogram, histogram::histogram(analysis.counts.iter().copied()));
+        assert_eq!(analysis.histogram, BTreeMap::from([(1, 2), (2, 3)]));
+        assert_eq!("".method_chain_analysis(10).unwrap(), ChainAnalysis::default());
+    }
+
+    #[test]
+    fn test_method_chain_argument_calls() {
+        assert_eq!("a(x).b().c(y);".method_chain_argument_calls(10).unwrap(), vec![(3, 2)]);
+        assert_eq!("a().b(); c.d(e(f), g).h(i.j());".method_chain_argument_calls(10).unwrap(),
+                   vec![(2, 0), (1, 1), (1, 0), (2, 2)]);
+    }
+
+    #[test]
+    fn test_argument_chain_fanout() {
+        assert_eq!("f(a().b(), c(), d.e());".argument_chain_fanout(10).unwrap(), vec![3]);
+        // Inner calls first, and `h()` has no arguments.
+        assert_eq!("f(g(a().b(), x), h(), 1);".argument_chain_fanout(10).unwrap(), vec![1, 2]);
+        assert_eq!("x.f(y, z); new A(b.c()).d();".argument_chain_fanout(10).unwrap(), vec![0]);
+    }
+
+    // The identifier before a `:` is a label, not the root of a chain; even
+    // with fields counted, `outer` is not followed by a dot.
+    #[test]
+    fn test_labels() {
+        let program = r#"
+            outer: for (Item item : items) {
+                label: a().b();
+                switch (item.kind()) {
+                    case LIST: list.stream().count(); break;
+                    case Kind.OTHER: other.size(); continue outer;
+                    default: log.warn("unknown");
+                }
+            }
+        "#;
+        assert_eq!(program.method_chain_counts(10).unwrap(), vec![2, 1, 2, 1, 1]);
+        let config = ChainConfig { count_fields: true, ..ChainConfig::default() };
+        assert_eq!(program.method_chain_counts_with(10, &config).unwrap(), vec![2, 1, 2, 1, 1, 1]);
+        let chains = program.method_chains_with(10, &ChainConfig::default()).unwrap();
+        assert_eq!(chains[0].links, vec!["a".to_owned(), "b".to_owned()]);
+    }
+
+    #[test]
+    fn test_assertion_chains() {
+        let roots = AssertionRoots::default();
+        let chains = r#"
+            assertThat(result).isNotNull().isEqualTo(expected);
+            builder.withName(name).withAge(age).build();
+            Assertions.assertThat(list).hasSize(2);
+            verify(mock).close();
+            thenReturn(x).foo();
+        "#.method_chains_with(10, &ChainConfig::default()).unwrap();
+        assert_eq!(chains.iter().map(|chain| (chain.length(), chain.is_assertion(&roots))).collect::<Vec<_>>(),
+                   vec![(3, true), (3, false), (2, true), (2, true), (2, false)]);
+
+        let roots = AssertionRoots { methods: vec!["expect".to_owned()] };
+        assert!("expect(x).toBe(1);".method_chains_with(10, &ChainConfig::default()).unwrap()[0].is_assertion(&roots));
+    }
+
+    #[test]
+    fn test_is_balanced() {
+        let balanced = |source: &str| is_balanced(&tokenize(&remove_comments(source)));
+        assert!(balanced(""));
+        assert!(balanced("class A { void f(int[] a) { g(a[0], h(() -> { })); } }"));
+        assert!(balanced("f(a); // (\n/* [ */"));
+        assert!(balanced("class A { void f() { g(\"http://x\"); } }"));
+        assert!(balanced("char c = '\"'; f(\"/*\"); // )"));
+        assert!(!balanced("class A { void f() { g(); }"));
+        assert!(!balanced("f(a));"));
+        assert!(!balanced("f(a]"));
+        assert!(!balanced("a[f(])"));
+        assert!(!balanced("} {"));
+    }
+
+    #[test]
+    fn test_method_chain_counts_ignoring() {
+        let program = "query.select(a).as(Row.class).fetch(); b.cast(); c.d().e();";
+        assert_eq!(program.method_chain_counts(10).unwrap(), vec![3, 1, 2]);
+        assert_eq!(program.method_chain_counts_ignoring(10, &["as"]).unwrap(), vec![2, 1, 2]);
+        assert_eq!(program.method_chain_counts_ignoring(10, &["as", "cast"]).unwrap(), vec![2, 2]);
+    }
+
+    #[test]
+    fn test_decode_unicode_escapes() {
+        assert_eq!(decode_unicode_escapes(r"a\u002eb()"), "a.b()");
+        assert_eq!(decode_unicode_escapes(r"a\uuu002Eb()"), "a.b()");
+        assert_eq!(decode_unicode_escapes(r"s = \ud83d\ude00;"), "s = \u{1F600};");
+        assert_eq!(decode_unicode_escapes(r"\\u002e \\\u002e"), r"\\u002e \\.");
+        assert_eq!(decode_unicode_escapes(r"\u00 \u \x \ud83d;"), r"\u00 \u \x \ud83d;");
+        assert!(matches!(decode_unicode_escapes("a.b()"), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn test_chain_through_unicode_escape() {
+        let program = r"list\u002estream().count();";
+        assert_eq!(program.method_chain_counts(10).unwrap(), vec![1]);
+        let config = ChainConfig { decode_unicode_escapes: true, ..ChainConfig::default() };
+        assert_eq!(program.method_chain_counts_with(10, &config).unwrap(), vec![2]);
+        assert_eq!(program.method_chain_spans_with(10, &config).unwrap(), vec![ChainSpan { start_line: 1, length: 2 }]);
+    }
+
+    #[test]
+    fn test_comments_inside_chains() {
+        let program = "a() /* c */ .b(); d(). // e\n f(); g()// h\n.i(); j()/* k */.l()/**/.m();";
+        assert_eq!(program.method_chain_counts(10).unwrap(), vec![2, 2, 2, 3]);
+        assert_eq!(remove_comments("x// c\ny"), "x\ny");
+        assert_eq!(tokenize(&remove_comments("int/* */x")).len(), 2);
+    }
+}
@@ -1,120 +1,1000 @@
 use std::collections::BTreeMap;
-use std::path::PathBuf;
-use std::io::Write;
-use bstr::ByteSlice;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
 
 use clap::*;
 use itertools::Itertools;
 
-use method_chains::MethodChaining;
+use method_chains::{AssertionRoots, ChainConfig, RootKind};
+use method_chains::corpus::{Corpus, LongChain, Shard};
+use method_chains::file_list::{self, GroupBy};
+use method_chains::histogram;
+use method_chains::output;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    Csv,
+    /// One `<project>.dat` file per project in --out-dir, for plotting
+    Plot,
+    #[cfg(feature = "parquet")]
+    Parquet,
+}
+
+impl Format {
+    fn name(&self) -> &'static str {
+        match self {
+            Format::Csv => "CSV",
+            Format::Plot => "plot",
+            #[cfg(feature = "parquet")]
+            Format::Parquet => "Parquet",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Order {
+    /// By chain length, longest first
+    Sorted,
+    /// By where each chain length is first found, going through the files in path order
+    Appearance,
+}
 
 #[derive(Parser)]
 #[clap(version = crate_version!(), author = crate_authors!(), name = "method-chains")]
+#[clap(subcommand_negates_reqs = true, args_conflicts_with_subcommands = true)]
 pub struct Options {
-    #[clap(short = 'o', long = "output-path", parse(from_os_str))]
-    pub output_path: PathBuf,
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+
+    #[clap(short = 'o', long = "output-path", parse(from_os_str), required_unless_present_any = &["out-dir", "output-per-project"])]
+    pub output_path: Option<PathBuf>,
+
+    /// Directory of the files written by --format plot
+    #[clap(long = "out-dir", parse(from_os_str), conflicts_with_all = &["output-path", "with-size", "with-longest-chain"])]
+    pub out_dir: Option<PathBuf>,
+
+    /// Write each project's CSV histogram to its own DIR/<project>.csv instead of --output-path
+    #[clap(long = "output-per-project", value_name = "DIR", parse(from_os_str),
+           conflicts_with_all = &["output-path", "out-dir", "with-size", "with-longest-chain", "by-package", "group-by-extension", "by-root-kind", "report-over"])]
+    pub output_per_project: Option<PathBuf>,
+
+    #[clap(short = 'p', long = "project-dir", parse(from_os_str), required_unless_present_any = &["file-list", "projects-file", "zip"], conflicts_with_all = &["file-list", "projects-file", "zip"])]
+    pub project_dir: Option<PathBuf>,
+
+    /// Analyze the top-level directories of this zip archive as projects, without extracting it
+    #[clap(long = "zip", value_name = "PATH", parse(from_os_str), conflicts_with_all = &["file-list", "projects-file"])]
+    pub zip: Option<PathBuf>,
+
+    /// Analyze the project directories listed in this file (one path per line) instead of the subdirectories of --project-dir
+    #[clap(long = "projects-file", parse(from_os_str), conflicts_with = "file-list")]
+    pub projects_file: Option<PathBuf>,
+
+    /// Analyze the files listed in this file (one path per line) instead of scanning --project-dir
+    #[clap(long = "file-list", parse(from_os_str))]
+    pub file_list: Option<PathBuf>,
+
+    /// How files from --file-list are grouped into histograms
+    #[clap(long = "group-by", value_enum, default_value = "all")]
+    pub group_by: GroupBy,
+
+    /// Name the --file-list groups by their paths relative to the deepest directory containing all the listed files
+    #[clap(long = "relative-paths", requires = "file-list")]
+    pub relative_paths: bool,
+
+    #[clap(short = 'r', long = "max-recursion-depth", required = true)]
+    pub max_recursion_depth: Option<usize>,
+
+    #[clap(short = 'f', long = "format", value_enum, default_value = "csv")]
+    pub format: Format,
+
+    /// Comma-separated extensions of the files to analyze; .rs files are analyzed as Rust
+    #[clap(short = 'e', long = "extensions", use_value_delimiter = true, default_value = "java")]
+    pub extensions: Vec<String>,
+
+    /// Skip files matching this glob, relative to --project-dir (may be repeated)
+    #[clap(short = 'x', long = "exclude")]
+    pub exclude: Vec<String>,
+
+    /// Number of threads analyzing the files of each project
+    #[clap(short = 'j', long = "jobs", default_value = "1")]
+    pub jobs: usize,
+
+    /// Read and hold at most N files in memory at once, whatever the number of --jobs
+    #[clap(long = "max-in-flight", value_name = "N")]
+    pub max_in_flight: Option<usize>,
+
+    /// Also count chains in the code of {@code ...} and {@link ...} Javadoc tags
+    #[clap(long = "analyze-javadoc-code")]
+    pub analyze_javadoc_code: bool,
+
+    /// Only count the chains inside method and constructor bodies
+    #[clap(long = "bodies-only")]
+    pub bodies_only: bool,
+
+    /// Count every method reference like String::trim as a chain of length 1
+    #[clap(long = "count-method-references")]
+    pub count_method_references: bool,
+
+    /// Count a method reference passed to a call as a link of the calling chain, so a().map(Foo::bar).count() is 4 long
+    #[clap(long = "count-method-refs-in-args")]
+    pub count_method_refs_in_args: bool,
+
+    /// Comma-separated methods that only pass their argument on, like requireNonNull: requireNonNull(a.b()).c() then counts as a.b().c()
+    #[clap(long = "transparent-methods", value_name = "NAMES", use_value_delimiter = true)]
+    pub transparent_methods: Vec<String>,
+
+    /// Remove type arguments like the <String> of Collections.<String>emptyList() before looking for chains
+    #[clap(long = "strip-generics")]
+    pub strip_generics: bool,
+
+    /// Decode Unicode escapes like \u002e before looking for chains, as the Java compiler does
+    #[clap(long = "decode-unicode")]
+    pub decode_unicode: bool,
 
-    #[clap(short = 'p', long = "project-dir", parse(from_os_str))]
-    pub project_dir: PathBuf,
+    /// Also write each project's file and line count to a companion CSV next to --output-path
+    #[clap(long = "with-size", conflicts_with = "file-list")]
+    pub with_size: bool,
 
-    #[clap(short = 'r', long = "max-recursion-depth")]
-    pub max_recursion_depth: usize,
+    /// Also write the file with each project's longest chain to a companion CSV next to --output-path
+    #[clap(long = "with-longest-chain", conflicts_with = "file-list")]
+    pub with_longest_chain: bool,
+
+    /// Memory-map source files instead of reading them into memory
+    #[clap(long = "mmap")]
+    pub mmap: bool,
+
+    /// Split each project's histogram by the package declared in each file (CSV only)
+    #[clap(long = "by-package", conflicts_with = "file-list")]
+    pub by_package: bool,
+
+    /// Split each project's histogram by file extension (CSV only)
+    #[clap(long = "group-by-extension", conflicts_with_all = &["file-list", "by-package"])]
+    pub group_by_extension: bool,
+
+    /// Split each project's histogram by what the chains are called on: this, super, new, class, or variable (CSV only)
+    #[clap(long = "by-root-kind", conflicts_with_all = &["file-list", "by-package", "group-by-extension"])]
+    pub by_root_kind: bool,
+
+    /// Write each project's cumulative distribution instead of its histogram: the fraction of chains up to each length (CSV only)
+    #[clap(long = "cdf", conflicts_with_all = &["top-n", "by-package", "group-by-extension", "by-root-kind", "report-over", "count-anonymous-classes", "assertion-chains"])]
+    pub cdf: bool,
+
+    /// Write each project's number of chains per thousand lines instead of its histogram (CSV only)
+    #[clap(long = "density",
+           conflicts_with_all = &["file-list", "output-per-project", "cdf", "top-n", "order", "by-package", "group-by-extension", "by-root-kind", "report-over", "count-anonymous-classes", "assertion-chains"])]
+    pub density: bool,
+
+    /// Write the Shannon entropy of each project's chain lengths, in bits, instead of its histogram (CSV only)
+    #[clap(long = "entropy",
+           conflicts_with_all = &["density", "output-per-project", "cdf", "top-n", "order", "by-package", "group-by-extension", "by-root-kind", "report-over", "count-anonymous-classes", "assertion-chains"])]
+    pub entropy: bool,
+
+    /// Write the deepest nesting of parentheses, brackets, and braces in each project instead of its histogram (CSV only)
+    #[clap(long = "max-nesting",
+           conflicts_with_all = &["file-list", "density", "entropy", "output-per-project", "cdf", "top-n", "order", "by-package", "group-by-extension", "by-root-kind", "report-over", "count-anonymous-classes", "assertion-chains"])]
+    pub max_nesting: bool,
+
+    /// Warn about every file with a chain longer than N, which often means the tokenizer went wrong
+    #[clap(long = "sanity-max", value_name = "N", conflicts_with = "file-list")]
+    pub sanity_max: Option<usize>,
+
+    /// Only write the K most frequent chain lengths of each project
+    #[clap(long = "top-n", value_name = "K")]
+    pub top_n: Option<usize>,
+
+    /// The order of each project's histogram rows
+    #[clap(long = "order", value_enum, default_value = "sorted",
+           conflicts_with_all = &["file-list", "top-n", "cdf", "by-package", "group-by-extension", "by-root-kind", "report-over", "count-anonymous-classes", "assertion-chains"])]
+    pub order: Order,
+
+    /// Count chains of length N or more in a single bucket N: the row of length N then means "N or more"
+    #[clap(long = "chain-length-cap", value_name = "N", value_parser = parse_cap, conflicts_with_all = &["file-list", "report-over"])]
+    pub chain_length_cap: Option<usize>,
+
+    /// Instead of histograms, write every chain longer than N with its file and line (CSV only)
+    #[clap(long = "report-over", value_name = "N", conflicts_with_all = &["file-list", "by-package", "group-by-extension", "by-root-kind", "top-n"])]
+    pub report_over: Option<usize>,
+
+    /// After the analysis, print every chain longer than N, and exit with code 1 if there is one
+    #[clap(long = "max-allowed", value_name = "N", conflicts_with_all = &["file-list", "chain-length-cap"])]
+    pub max_allowed: Option<usize>,
+
+    /// Analyze each file only with this probability, for a quick approximate picture of a large corpus
+    #[clap(long = "sample", value_name = "RATE", value_parser = parse_rate, conflicts_with = "file-list")]
+    pub sample: Option<f64>,
+
+    /// Analyze only the first N files of each project, in path order, for a quick partial run
+    #[clap(long = "limit-files", value_name = "N", conflicts_with = "file-list")]
+    pub limit_files: Option<usize>,
+
+    /// Leave out the projects with fewer than N files to analyze
+    #[clap(long = "min-files", value_name = "N", conflicts_with = "file-list")]
+    pub min_files: Option<usize>,
+
+    /// Seed choosing the files of --sample (random if omitted)
+    #[clap(long = "seed", requires = "sample")]
+    pub seed: Option<u64>,
+
+    /// Analyze the projects in an order shuffled by this seed instead of by name
+    #[clap(long = "shuffle-seed", value_name = "SEED", conflicts_with = "file-list")]
+    pub shuffle_seed: Option<u64>,
+
+    /// Only analyze the projects of shard INDEX (from 0) of TOTAL, as in 2/8, picked by a hash of their names
+    #[clap(long = "shard", value_name = "INDEX/TOTAL", value_parser = parse_shard, conflicts_with = "file-list")]
+    pub shard: Option<Shard>,
+
+    /// Write the chains in the bodies of anonymous classes as a separate "PROJECT (anonymous)" histogram
+    #[clap(long = "count-anonymous-classes", conflicts_with_all = &["file-list", "by-package"])]
+    pub count_anonymous_classes: bool,
+
+    /// Skip files whose parentheses, brackets, and braces do not nest correctly
+    #[clap(long = "require-balanced", conflicts_with = "file-list")]
+    pub require_balanced: bool,
+
+    /// Write fluent assertion chains like assertThat(x).isEqualTo(y) as a separate "PROJECT (assertions)" histogram
+    #[clap(long = "assertion-chains", conflicts_with_all = &["file-list", "by-package"])]
+    pub assertion_chains: bool,
+
+    /// Comma-separated methods starting an assertion chain for --assertion-chains
+    #[clap(long = "assertion-roots", use_value_delimiter = true, default_value = "assertThat,assertj,verify,then", requires = "assertion-chains")]
+    pub assertion_roots: Vec<String>,
+}
+
+fn parse_rate(rate: &str) -> Result<f64, String> {
+    let rate = rate.parse::<f64>().map_err(|e| e.to_string())?;
+    if (0.0..=1.0).contains(&rate) { Ok(rate) } else { Err(format!("{} is not between 0.0 and 1.0", rate)) }
+}
+
+fn parse_cap(cap: &str) -> Result<usize, String> {
+    let cap = cap.parse::<usize>().map_err(|e| e.to_string())?;
+    if cap > 0 { Ok(cap) } else { Err("a cap of 0 would leave no chain length".to_owned()) }
+}
+
+fn parse_shard(shard: &str) -> Result<Shard, String> {
+    let (index, total) = shard.split_once('/').ok_or_else(|| format!("{} is not INDEX/TOTAL", shard))?;
+    let index = index.trim().parse::<usize>().map_err(|e| e.to_string())?;
+    let total = total.trim().parse::<usize>().map_err(|e| e.to_string())?;
+    if index < total { Ok(Shard { index, total }) } else { Err(format!("shard {} is not below the total of {}", index, total)) }
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Compare two histogram CSV files, emitting the per-project, per-length change in frequency
+    Diff {
+        #[clap(parse(from_os_str))]
+        old: PathBuf,
+
+        #[clap(parse(from_os_str))]
+        new: PathBuf,
+
+        /// Where to write the delta CSV (stdout if omitted)
+        #[clap(short = 'o', long = "output-path", parse(from_os_str))]
+        output_path: Option<PathBuf>,
+    },
+    /// Print how alike the histograms of two projects of a CSV file are: the KL divergence of the first from the second, and their cosine similarity
+    Compare {
+        #[clap(parse(from_os_str))]
+        csv: PathBuf,
+
+        first: String,
+
+        second: String,
+    },
+    /// Print the total number of projects, files, and chains, without writing any report
+    Count {
+        #[clap(short = 'p', long = "project-dir", parse(from_os_str))]
+        project_dir: PathBuf,
+
+        #[clap(short = 'r', long = "max-recursion-depth", default_value = "1000")]
+        max_recursion_depth: usize,
+
+        /// Comma-separated extensions of the files to analyze
+        #[clap(short = 'e', long = "extensions", use_value_delimiter = true, default_value = "java")]
+        extensions: Vec<String>,
+
+        /// Skip files matching this glob, relative to --project-dir (may be repeated)
+        #[clap(short = 'x', long = "exclude")]
+        exclude: Vec<String>,
+    },
 }
 
 impl Options {
+    pub fn output_path(&self) -> &PathBuf {
+        self.output_path.as_ref().unwrap()
+    }
     pub fn output_path_as_str(&self) -> &str {
-        self.output_path.as_os_str().to_str().unwrap()
+        self.output_path().as_os_str().to_str().unwrap()
     }
-    pub fn project_dir_as_str(&self) -> &str {
-        self.project_dir.as_os_str().to_str().unwrap()
+    /// The output file, or the output directory of --format plot or
+    /// --output-per-project.
+    pub fn output_name(&self) -> String {
+        match self.out_dir.as_ref().or(self.output_per_project.as_ref()) {
+            Some(dir) => dir.display().to_string(),
+            None => self.output_path_as_str().to_owned(),
+        }
+    }
+    /// The companion file of --with-size.
+    pub fn size_output_path(&self) -> Option<PathBuf> {
+        self.with_size.then(|| self.output_path().with_extension("sizes.csv"))
+    }
+    /// The length above which chains are reported by --report-over or are
+    /// not allowed by --max-allowed.
+    pub fn long_chain_threshold(&self) -> Option<usize> {
+        match (self.report_over, self.max_allowed) {
+            (Some(report_over), Some(max_allowed)) => Some(report_over.min(max_allowed)),
+            (report_over, max_allowed) => report_over.or(max_allowed),
+        }
+    }
+    /// The companion file of --with-longest-chain.
+    pub fn longest_chain_output_path(&self) -> Option<PathBuf> {
+        self.with_longest_chain.then(|| self.output_path().with_extension("longest.csv"))
+    }
+    pub fn max_recursion_depth(&self) -> usize {
+        self.max_recursion_depth.unwrap()
+    }
+    pub fn chain_config(&self) -> ChainConfig {
+        ChainConfig {
+            analyze_javadoc_code: self.analyze_javadoc_code,
+            decode_unicode_escapes: self.decode_unicode,
+            bodies_only: self.bodies_only,
+            count_method_references: self.count_method_references,
+            count_method_refs_in_args: self.count_method_refs_in_args,
+            strip_generics: self.strip_generics,
+            transparent_methods: self.transparent_methods.clone(),
+            ..ChainConfig::default()
+        }
+    }
+    /// The rows written for `histogram`: by chain length, longest first, or
+    /// only the most frequent ones with --top-n.
+    pub fn rows(&self, histogram: BTreeMap<usize, usize>) -> Vec<(usize, usize)> {
+        match self.top_n {
+            Some(n) => histogram::top_n(&histogram, n),
+            None => histogram.into_iter().sorted().rev().collect(),
+        }
+    }
+    pub fn corpus(&self) -> Corpus {
+        let corpus = Corpus::new(self.project_dir.clone().unwrap_or_default())
+            .extensions(self.extensions.iter().cloned())
+            .exclude(self.exclude.iter().cloned())
+            .jobs(self.jobs)
+            .max_in_flight(self.max_in_flight)
+            .shuffle(self.shuffle_seed)
+            .shard(self.shard)
+            .max_depth(self.max_recursion_depth())
+            .chain_config(self.chain_config())
+            .mmap(self.mmap)
+            .by_package(self.by_package)
+            .by_extension(self.group_by_extension)
+            .by_root_kind(self.by_root_kind)
+            .nesting_depth(self.max_nesting)
+            .report_over(self.long_chain_threshold())
+            .chain_length_cap(self.chain_length_cap)
+            .require_balanced(self.require_balanced)
+            .limit_files(self.limit_files)
+            .min_files(self.min_files)
+            .archive(self.zip.clone())
+            .sanity_max(self.sanity_max)
+            .count_anonymous_classes(self.count_anonymous_classes)
+            .assertion_chains(self.assertion_chains.then(|| AssertionRoots { methods: self.assertion_roots.clone() }));
+        match self.sample {
+            Some(rate) => {
+                let seed = self.seed.unwrap_or_else(rand::random);
+                tracing::info!(rate, seed, "sampling files");
+                corpus.sample(rate, seed)
+            }
+            None => corpus,
+        }
+    }
+}
+
+// Logs at `info` and above to stderr unless `RUST_LOG` says otherwise.
+#[cfg(feature = "cli")]
+fn init_logging() {
+    use tracing_subscriber::EnvFilter;
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr)
+        .init();
+}
+
+/// Exit code of a run stopped by Ctrl-C, as if killed by SIGINT.
+const INTERRUPTED_EXIT_CODE: i32 = 130;
+
+/// Records a Ctrl-C in `interrupted`, returning whether to quit right away:
+/// the first one only asks the run to stop after the current project, so that
+/// what is complete gets written, and the second one gives up on that.
+#[cfg(any(feature = "cli", test))]
+fn interrupt(interrupted: &AtomicBool) -> bool {
+    interrupted.swap(true, Ordering::SeqCst)
+}
+
+#[cfg(feature = "cli")]
+fn handle_interrupts(interrupted: Arc<AtomicBool>) {
+    let handled = ctrlc::set_handler(move || {
+        if interrupt(&interrupted) {
+            std::process::exit(INTERRUPTED_EXIT_CODE);
+        }
+        eprintln!("Interrupted, stopping after the current project (press Ctrl-C again to quit now)");
+    });
+    if let Err(error) = handled {
+        tracing::warn!(error = %error, "cannot handle Ctrl-C");
     }
 }
 
 pub fn main() {
     let config = Options::parse();
-    
-    let project_dirs = std::fs::read_dir(&config.project_dir)
-        .expect(&format!("Cannot read directory {}", config.project_dir_as_str()))
-        .map(|entry | entry.unwrap())
-        .filter(|entry| {
-            entry.file_type().map_or(false, |file_type| file_type.is_dir())
-        })
-        .map(|entry| entry.file_name().to_str().unwrap().to_owned())
-        .map(|file_name| {
-            let mut path = config.project_dir.clone();
-            path.push(file_name);
-            path
-        })
-        .collect::<Vec<PathBuf>>();
-
-    let total_projects = project_dirs.len();
-
-    eprintln!("Found {} project directories in {}.", total_projects, config.project_dir_as_str());
-
-    eprintln!("Creating CSV file at {} (if file exists, it will be overwritten)", config.output_path_as_str());
-
-    let mut file = std::fs::File::create(config.output_path_as_str())
-        .expect(&format!("Cannot create file {}", config.output_path_as_str()));
-    writeln!(file, "project, chain length, frequency")
-        .expect(&format!("Cannot write to file {}", config.output_path_as_str()));
-
-    for (i, project_dir) in project_dirs.into_iter().enumerate() {
-
-        let project_name = project_dir.file_name().unwrap().to_str().unwrap().to_owned();
-        eprintln!("[{}/{}] processing project {}", i + 1, total_projects, project_name);
-
-        let histogram = process_project_dir(i, total_projects, &project_name, &project_dir, config.max_recursion_depth)
-            .into_iter()
-            .sorted()
-            .rev()
-            .map(|(chain, frequency)| {
-                (project_name.clone(), chain, frequency)
-            }).collect::<Vec<(String, usize, usize)>>();
-
-        eprintln!("[{}/{}] appending {} items for project {} to CSV {}", i + 1, total_projects, 
-                    histogram.len(), project_name, config.output_path_as_str());
-        
-        for (project, chain_length, frequency) in histogram {
-            writeln!(file, "{}, {}, {}", project, chain_length, frequency)
-                .expect(&format!("Cannot write to file {}", config.output_path_as_str()));
-        }
-    }
-
-    eprintln!("Done.");
-}
-
-pub fn process_project_dir(i: usize, total_projects: usize, project_name: &str, project_dir: &PathBuf, max_recursion_depth: usize) -> BTreeMap<usize, usize> {
-    let java_paths = method_chains::read_dir_all(project_dir)
-        .into_iter()
-        .filter(|path| {
-            path.extension().map_or(false, |str| {
-                str.to_str().unwrap() == "java"
-            })
-        })
-        .collect::<Vec<PathBuf>>();
-
-    eprintln!("[{}/{}] processing {} Java files for project {}", i + 1, total_projects, 
-               java_paths.len(), project_name);
-
-    
-    java_paths.into_iter()
-        .flat_map(|path| {
-            let result = std::fs::read(&path)                
-                .expect(&format!("Cannot read file {:?}", &path))
-                .to_str_lossy()
-                .method_chain_counts(max_recursion_depth);
-            match result {
-                Err(error) => { 
-                    eprintln!("Failed to process file {:?}: {}", path, error);
-                    Vec::new()
-                }
-                Ok(method_chain_counts) => {
-                    method_chain_counts
-                }
+
+    #[cfg(feature = "cli")]
+    init_logging();
+
+    if let Some(Command::Diff { old, new, output_path }) = &config.command {
+        let result = match output_path {
+            Some(path) => std::fs::File::create(path)
+                .map_err(anyhow::Error::from)
+                .and_then(|file| {
+                    let mut file = output::ReportWriter::new(file);
+                    method_chains::diff::diff_files(old, new, &mut file)?;
+                    file.finish()?;
+                    Ok(())
+                }),
+            None => method_chains::diff::diff_files(old, new, &mut std::io::stdout().lock()),
+        };
+        if let Err(error) = result {
+            eprintln!("{:#}", error);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if let Some(Command::Compare { csv, first, second }) = &config.command {
+        if let Err(error) = compare_projects(csv, first, second) {
+            eprintln!("{:#}", error);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if let Some(Command::Count { project_dir, max_recursion_depth, extensions, exclude }) = &config.command {
+        let corpus = Corpus::new(project_dir)
+            .extensions(extensions.iter().cloned())
+            .exclude(exclude.iter().cloned())
+            .max_depth(*max_recursion_depth);
+        match corpus.run() {
+            Ok(report) => {
+                let files: usize = report.projects.iter().map(|project| project.files).sum();
+                let chains: usize = report.projects.iter().flat_map(|project| project.histogram.values()).sum();
+                println!("projects: {}", report.projects.len());
+                println!("files: {}", files);
+                println!("chains: {}", chains);
+            }
+            Err(error) => {
+                eprintln!("{:#}", error);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if (config.format == Format::Plot) != config.out_dir.is_some() {
+        eprintln!("--format plot and --out-dir go together");
+        std::process::exit(1);
+    }
+
+    // The options that only change or add CSV columns and files.
+    let csv_only = [
+        ("--output-per-project", config.output_per_project.is_some()),
+        ("--cdf", config.cdf),
+        ("--density", config.density),
+        ("--max-nesting", config.max_nesting),
+        ("--entropy", config.entropy),
+        ("--by-package", config.by_package),
+        ("--group-by-extension", config.group_by_extension),
+        ("--by-root-kind", config.by_root_kind),
+        ("--report-over", config.report_over.is_some()),
+    ];
+    if config.format != Format::Csv {
+        if let Some((flag, _)) = csv_only.iter().find(|(_, set)| *set) {
+            eprintln!("{} is only supported with --format csv", flag);
+            std::process::exit(1);
+        }
+    }
+
+    // Only runs over projects have complete parts to write when stopped.
+    let interrupted = Arc::new(AtomicBool::new(false));
+    #[cfg(feature = "cli")]
+    if config.file_list.is_none() {
+        handle_interrupts(interrupted.clone());
+    }
+
+    let start = Instant::now();
+    let mut too_long = Vec::new();
+    let (files, bytes) = match &config.file_list {
+        Some(file_list) => process_file_list(&config, file_list),
+        None => process_project_dirs(&config, &interrupted, &mut too_long),
+    };
+
+    let interrupted = interrupted.load(Ordering::SeqCst);
+    if interrupted {
+        tracing::warn!("interrupted, only the projects analyzed so far were written");
+    } else {
+        tracing::info!("done");
+    }
+    eprintln!("{}", output::Summary { elapsed: start.elapsed(), files, bytes });
+    if let Some(max_allowed) = config.max_allowed {
+        for (path, chain) in &too_long {
+            println!("{}:{}: chain of length {}, more than {}", path.display(), chain.start_line, chain.length, max_allowed);
+        }
+    }
+    if interrupted {
+        std::process::exit(INTERRUPTED_EXIT_CODE);
+    }
+    if !too_long.is_empty() {
+        std::process::exit(1);
+    }
+}
+
+pub fn compare_projects(csv: &Path, first: &str, second: &str) -> anyhow::Result<()> {
+    let contents = std::fs::read_to_string(csv)
+        .map_err(|error| anyhow::anyhow!("Cannot read file {:?}: {}", csv, error))?;
+    let rows = output::parse_csv(&contents)?;
+    let histogram = |name: &str| -> anyhow::Result<BTreeMap<usize, usize>> {
+        // A length may be repeated, in files appended to by several runs.
+        let mut histogram = BTreeMap::new();
+        for (_, chain_length, frequency) in rows.iter().filter(|(project, _, _)| project == name) {
+            histogram::add_to_histogram(&mut histogram, *chain_length, *frequency);
+        }
+        if histogram.is_empty() {
+            anyhow::bail!("No project {} in {:?}", name, csv);
+        }
+        Ok(histogram)
+    };
+    let (first, second) = (histogram(first)?, histogram(second)?);
+    println!("kl divergence: {}", histogram::histogram_kl_divergence(&first, &second));
+    println!("cosine similarity: {}", histogram::histogram_cosine_similarity(&first, &second));
+    Ok(())
+}
+
+/// Returns the number of files analyzed and their total size.
+pub fn process_file_list(config: &Options, file_list: &Path) -> (usize, u64) {
+    let paths = file_list::read_file_list(file_list)
+        .unwrap_or_else(|e| panic!("{}", e));
+
+    tracing::info!(files = paths.len(), file_list = ?file_list, "found files");
+
+    let mut sink = Sink::create(config);
+
+    let root = (config.relative_paths && config.group_by != GroupBy::All).then(|| file_list::common_root(&paths));
+    let report = file_list::analyze_file_list(&paths, config.group_by, config.max_recursion_depth(), &config.chain_config(), config.mmap);
+    for (group, histogram) in report.groups {
+        let group = match &root {
+            Some(root) => file_list::relative_group(&group, root),
+            None => group,
+        };
+        let written = if config.entropy {
+            sink.write_entropy(&group, histogram::histogram_entropy(&histogram))
+        } else if config.cdf {
+            sink.write_cdf(&group, &histogram::histogram_cdf(&histogram))
+        } else {
+            sink.write_project(&group, &config.rows(histogram))
+        };
+        written.unwrap_or_else(|e| panic!("Cannot write to {}: {}", config.output_name(), e));
+    }
+
+    sink.close()
+        .unwrap_or_else(|e| panic!("Cannot write to {}: {}", config.output_name(), e));
+
+    (report.files, report.bytes)
+}
+
+/// Returns the number of files analyzed and their total size.
+/// Stops before the next project once `interrupted` is set, marking the
+/// output as incomplete.
+/// Adds the chains longer than --max-allowed, with the paths of their files,
+/// to `too_long`.
+pub fn process_project_dirs(config: &Options, interrupted: &Arc<AtomicBool>, too_long: &mut Vec<(PathBuf, LongChain)>) -> (usize, u64) {
+    let mut corpus = config.corpus().stop_when(interrupted.clone());
+    if let Some(projects_file) = &config.projects_file {
+        let project_dirs = file_list::read_file_list(projects_file)
+            .unwrap_or_else(|e| panic!("{}", e));
+        tracing::info!(projects = project_dirs.len(), projects_file = ?projects_file, "found project list");
+        corpus = corpus.project_dirs(project_dirs);
+    }
+
+    let mut sink = Sink::create(config);
+
+    let mut size_file = config.size_output_path()
+        .map(|path| Companion::create(path, "project size", output::write_size_csv_header));
+    let mut longest_chain_file = config.longest_chain_output_path()
+        .map(|path| Companion::create(path, "longest chain", output::write_longest_chain_csv_header));
+
+    let mut files = 0;
+    let mut bytes = 0;
+    corpus.for_each_project(|project| {
+        files += project.files;
+        bytes += project.bytes;
+
+        if let Some(size_file) = size_file.as_mut() {
+            size_file.write(|file| output::write_size_csv_row(file, &project.name, project.files, project.lines));
+        }
+        if let (Some(longest_chain_file), Some(file), Some(max_chain_length)) =
+            (longest_chain_file.as_mut(), project.longest_chain_file.as_ref(), project.histogram.keys().last()) {
+            longest_chain_file.write(|csv| output::write_longest_chain_csv_row(csv, &project.name, *max_chain_length, file));
+        }
+
+        if let Some(max_allowed) = config.max_allowed {
+            too_long.extend(project.long_chains.iter()
+                .filter(|chain| chain.length > max_allowed)
+                .map(|chain| (project.path.join(&chain.file), chain.clone())));
+        }
+
+        if let Some(report_over) = config.report_over {
+            // Longer than --max-allowed, if that is lower.
+            let long_chains = project.long_chains.iter().filter(|chain| chain.length > report_over).cloned().collect::<Vec<_>>();
+            tracing::info!(items = long_chains.len(), project = %project.name, "appending long chains");
+            sink.write_long_chains(&project.name, &long_chains)
+                .unwrap_or_else(|e| panic!("Cannot write to {}: {}", config.output_name(), e));
+            return;
+        }
+
+        if config.density {
+            tracing::info!(project = %project.name, "appending project density");
+            sink.write_density(&project.name, project.chains_per_kloc())
+                .unwrap_or_else(|e| panic!("Cannot write to {}: {}", config.output_name(), e));
+            return;
+        }
+
+        if config.max_nesting {
+            tracing::info!(project = %project.name, "appending project nesting depth");
+            sink.write_nesting(&project.name, project.max_nesting_depth)
+                .unwrap_or_else(|e| panic!("Cannot write to {}: {}", config.output_name(), e));
+            return;
+        }
+
+        if config.entropy {
+            tracing::info!(project = %project.name, "appending project entropy");
+            sink.write_entropy(&project.name, histogram::histogram_entropy(&project.histogram))
+                .unwrap_or_else(|e| panic!("Cannot write to {}: {}", config.output_name(), e));
+            return;
+        }
+
+        if config.cdf {
+            let cdf = histogram::histogram_cdf(&project.histogram);
+            tracing::info!(items = cdf.len(), project = %project.name, "appending project distribution");
+            sink.write_cdf(&project.name, &cdf)
+                .unwrap_or_else(|e| panic!("Cannot write to {}: {}", config.output_name(), e));
+            return;
+        }
+
+        let histogram = match config.order {
+            Order::Sorted => config.rows(project.histogram),
+            Order::Appearance => histogram::in_order(&project.histogram, &project.first_appearances),
+        };
+
+        if config.by_package {
+            for (package, histogram) in project.packages {
+                let histogram = config.rows(histogram);
+                tracing::info!(items = histogram.len(), package = %package, project = %project.name, "appending package histogram");
+                sink.write_package(&project.name, &package, &histogram)
+                    .unwrap_or_else(|e| panic!("Cannot write to {}: {}", config.output_name(), e));
             }
-        })
-        .fold(BTreeMap::new(), |mut accumulator, chain_length| {
-            *accumulator.entry(chain_length).or_insert(0) += 1;
-            accumulator
-        })
+            return;
+        }
+
+        if config.group_by_extension {
+            for (extension, histogram) in project.extensions {
+                let histogram = config.rows(histogram);
+                tracing::info!(items = histogram.len(), extension = %extension, project = %project.name, "appending extension histogram");
+                sink.write_extension(&project.name, &extension, &histogram)
+                    .unwrap_or_else(|e| panic!("Cannot write to {}: {}", config.output_name(), e));
+            }
+            return;
+        }
+
+        if config.by_root_kind {
+            for (root_kind, histogram) in project.root_kinds {
+                let histogram = config.rows(histogram);
+                tracing::info!(items = histogram.len(), root_kind = root_kind.name(), project = %project.name, "appending root kind histogram");
+                sink.write_root_kind(&project.name, root_kind, &histogram)
+                    .unwrap_or_else(|e| panic!("Cannot write to {}: {}", config.output_name(), e));
+            }
+            return;
+        }
+
+        tracing::info!(items = histogram.len(), project = %project.name, "appending project histogram");
+
+        sink.write_project(&project.name, &histogram)
+            .unwrap_or_else(|e| panic!("Cannot write to {}: {}", config.output_name(), e));
+
+        if config.count_anonymous_classes {
+            let name = format!("{} (anonymous)", project.name);
+            let histogram = config.rows(project.anonymous_histogram);
+            tracing::info!(items = histogram.len(), project = %project.name, "appending anonymous class histogram");
+            sink.write_project(&name, &histogram)
+                .unwrap_or_else(|e| panic!("Cannot write to {}: {}", config.output_name(), e));
+        }
+
+        if config.assertion_chains {
+            let name = format!("{} (assertions)", project.name);
+            let histogram = config.rows(project.assertion_histogram);
+            tracing::info!(items = histogram.len(), project = %project.name, "appending assertion chain histogram");
+            sink.write_project(&name, &histogram)
+                .unwrap_or_else(|e| panic!("Cannot write to {}: {}", config.output_name(), e));
+        }
+    }).unwrap_or_else(|e| panic!("{}", e));
+
+    if interrupted.load(Ordering::SeqCst) {
+        sink.mark_interrupted()
+            .unwrap_or_else(|e| panic!("Cannot write to {}: {}", config.output_name(), e));
+        for companion in size_file.iter_mut().chain(longest_chain_file.iter_mut()) {
+            companion.write(output::write_interrupted_marker);
+        }
+    }
+
+    sink.close()
+        .unwrap_or_else(|e| panic!("Cannot write to {}: {}", config.output_name(), e));
+    for companion in size_file.into_iter().chain(longest_chain_file) {
+        companion.finish();
+    }
+    (files, bytes)
+}
+
+/// A CSV file written next to --output-path along with the report, like the
+/// one of --with-size.
+struct Companion {
+    path: PathBuf,
+    file: output::ReportWriter<std::fs::File>,
+}
+
+impl Companion {
+    fn create(path: PathBuf, name: &str, write_header: fn(&mut output::ReportWriter<std::fs::File>) -> std::io::Result<()>) -> Self {
+        tracing::info!(path = ?path, "creating {} file (if file exists, it will be overwritten)", name);
+        let file = std::fs::File::create(&path)
+            .unwrap_or_else(|_| panic!("Cannot create file {:?}", path));
+        let mut companion = Companion { path, file: output::ReportWriter::new(file) };
+        companion.write(write_header);
+        companion
+    }
+
+    fn write<F: FnOnce(&mut output::ReportWriter<std::fs::File>) -> std::io::Result<()>>(&mut self, write: F) {
+        write(&mut self.file)
+            .unwrap_or_else(|e| panic!("Cannot write to file {:?}: {}", self.path, e));
+    }
+
+    fn finish(self) {
+        let Companion { path, file } = self;
+        file.finish()
+            .unwrap_or_else(|e| panic!("Cannot write to file {:?}: {}", path, e));
+    }
+}
+
+enum Sink {
+    Csv(output::ReportWriter<std::fs::File>),
+    /// The output directory.
+    Plot(PathBuf),
+    /// The output directory of --output-per-project.
+    CsvPerProject(PathBuf),
+    #[cfg(feature = "parquet")]
+    Parquet(Box<output::ParquetWriter>),
+}
+
+impl Sink {
+    #[allow(clippy::expect_fun_call)]
+    fn create(config: &Options) -> Self {
+        match config.format {
+            Format::Csv if config.output_per_project.is_some() => {
+                let dir = config.output_per_project.clone().unwrap();
+                tracing::info!(format = config.format.name(), path = ?dir, "creating output directory (existing files will be overwritten)");
+                std::fs::create_dir_all(&dir)
+                    .unwrap_or_else(|e| panic!("Cannot create directory {:?}: {}", dir, e));
+                Sink::CsvPerProject(dir)
+            }
+            Format::Csv => {
+                tracing::info!(format = config.format.name(), path = config.output_path_as_str(), "creating output file (if file exists, it will be overwritten)");
+                let file = std::fs::File::create(config.output_path_as_str())
+                    .expect(&format!("Cannot create file {}", config.output_path_as_str()));
+                let mut file = output::ReportWriter::new(file);
+                let header = if config.report_over.is_some() {
+                    output::write_long_chain_csv_header(&mut file)
+                } else if config.by_package {
+                    output::write_package_csv_header(&mut file)
+                } else if config.group_by_extension {
+                    output::write_extension_csv_header(&mut file)
+                } else if config.by_root_kind {
+                    output::write_root_kind_csv_header(&mut file)
+                } else if config.cdf {
+                    output::write_cdf_csv_header(&mut file)
+                } else if config.density {
+                    output::write_density_csv_header(&mut file)
+                } else if config.entropy {
+                    output::write_entropy_csv_header(&mut file)
+                } else if config.max_nesting {
+                    output::write_nesting_csv_header(&mut file)
+                } else {
+                    output::write_csv_header(&mut file)
+                };
+                header.expect(&format!("Cannot write to file {}", config.output_path_as_str()));
+                Sink::Csv(file)
+            }
+            Format::Plot => {
+                let dir = config.out_dir.clone().unwrap();
+                tracing::info!(format = config.format.name(), path = ?dir, "creating output directory (existing files will be overwritten)");
+                std::fs::create_dir_all(&dir)
+                    .unwrap_or_else(|e| panic!("Cannot create directory {:?}: {}", dir, e));
+                Sink::Plot(dir)
+            }
+            #[cfg(feature = "parquet")]
+            Format::Parquet => {
+                tracing::info!(format = config.format.name(), path = config.output_path_as_str(), "creating output file (if file exists, it will be overwritten)");
+                let writer = output::ParquetWriter::create(config.output_path())
+                    .unwrap_or_else(|e| panic!("Cannot create file {}: {}", config.output_path_as_str(), e));
+                Sink::Parquet(Box::new(writer))
+            }
+        }
+    }
+
+    fn write_project(&mut self, project: &str, histogram: &[(usize, usize)]) -> anyhow::Result<()> {
+        match self {
+            Sink::Csv(file) => output::write_csv_rows(file, project, histogram)?,
+            Sink::Plot(dir) => {
+                let file = std::fs::File::create(dir.join(output::plot_file_name(project)))?;
+                let mut file = output::ReportWriter::new(file);
+                output::write_plot_rows(&mut file, histogram)?;
+                file.finish()?;
+            }
+            Sink::CsvPerProject(dir) => {
+                let file = std::fs::File::create(dir.join(output::project_file_name(project, "csv")))?;
+                let mut file = output::ReportWriter::new(file);
+                output::write_csv_header(&mut file)?;
+                output::write_csv_rows(&mut file, project, histogram)?;
+                file.finish()?;
+            }
+            #[cfg(feature = "parquet")]
+            Sink::Parquet(writer) => writer.write_project(project, histogram)?,
+        }
+        Ok(())
+    }
+
+    fn write_cdf(&mut self, project: &str, cdf: &BTreeMap<usize, f64>) -> anyhow::Result<()> {
+        match self {
+            Sink::Csv(file) => output::write_cdf_csv_rows(file, project, cdf)?,
+            Sink::Plot(_) => anyhow::bail!("distributions cannot be written as plot files"),
+            Sink::CsvPerProject(dir) => {
+                let file = std::fs::File::create(dir.join(output::project_file_name(project, "csv")))?;
+                let mut file = output::ReportWriter::new(file);
+                output::write_cdf_csv_header(&mut file)?;
+                output::write_cdf_csv_rows(&mut file, project, cdf)?;
+                file.finish()?;
+            }
+            #[cfg(feature = "parquet")]
+            Sink::Parquet(_) => anyhow::bail!("distributions cannot be written as Parquet"),
+        }
+        Ok(())
+    }
+
+    fn write_density(&mut self, project: &str, chains_per_kloc: f64) -> anyhow::Result<()> {
+        match self {
+            Sink::Csv(file) => output::write_density_csv_row(file, project, chains_per_kloc)?,
+            Sink::Plot(_) => anyhow::bail!("densities cannot be written as plot files"),
+            Sink::CsvPerProject(_) => anyhow::bail!("densities cannot be written with --output-per-project"),
+            #[cfg(feature = "parquet")]
+            Sink::Parquet(_) => anyhow::bail!("densities cannot be written as Parquet"),
+        }
+        Ok(())
+    }
+
+    fn write_nesting(&mut self, project: &str, max_nesting_depth: usize) -> anyhow::Result<()> {
+        match self {
+            Sink::Csv(file) => output::write_nesting_csv_row(file, project, max_nesting_depth)?,
+            Sink::Plot(_) => anyhow::bail!("nesting depths cannot be written as plot files"),
+            Sink::CsvPerProject(_) => anyhow::bail!("nesting depths cannot be written with --output-per-project"),
+            #[cfg(feature = "parquet")]
+            Sink::Parquet(_) => anyhow::bail!("nesting depths cannot be written as Parquet"),
+        }
+        Ok(())
+    }
+
+    fn write_entropy(&mut self, project: &str, entropy: f64) -> anyhow::Result<()> {
+        match self {
+            Sink::Csv(file) => output::write_entropy_csv_row(file, project, entropy)?,
+            Sink::Plot(_) => anyhow::bail!("entropies cannot be written as plot files"),
+            Sink::CsvPerProject(_) => anyhow::bail!("entropies cannot be written with --output-per-project"),
+            #[cfg(feature = "parquet")]
+            Sink::Parquet(_) => anyhow::bail!("entropies cannot be written as Parquet"),
+        }
+        Ok(())
+    }
+
+    fn write_package(&mut self, project: &str, package: &str, histogram: &[(usize, usize)]) -> anyhow::Result<()> {
+        match self {
+            Sink::Csv(file) => output::write_package_csv_rows(file, project, package, histogram)?,
+            Sink::Plot(_) => anyhow::bail!("per-package histograms cannot be written as plot files"),
+            Sink::CsvPerProject(_) => anyhow::bail!("per-package histograms cannot be written with --output-per-project"),
+            #[cfg(feature = "parquet")]
+            Sink::Parquet(_) => anyhow::bail!("per-package histograms cannot be written as Parquet"),
+        }
+        Ok(())
+    }
+
+    fn write_extension(&mut self, project: &str, extension: &str, histogram: &[(usize, usize)]) -> anyhow::Result<()> {
+        match self {
+            Sink::Csv(file) => output::write_extension_csv_rows(file, project, extension, histogram)?,
+            Sink::Plot(_) => anyhow::bail!("per-extension histograms cannot be written as plot files"),
+            Sink::CsvPerProject(_) => anyhow::bail!("per-extension histograms cannot be written with --output-per-project"),
+            #[cfg(feature = "parquet")]
+            Sink::Parquet(_) => anyhow::bail!("per-extension histograms cannot be written as Parquet"),
+        }
+        Ok(())
+    }
+
+    fn write_root_kind(&mut self, project: &str, root_kind: RootKind, histogram: &[(usize, usize)]) -> anyhow::Result<()> {
+        match self {
+            Sink::Csv(file) => output::write_root_kind_csv_rows(file, project, root_kind, histogram)?,
+            Sink::Plot(_) => anyhow::bail!("per-root-kind histograms cannot be written as plot files"),
+            Sink::CsvPerProject(_) => anyhow::bail!("per-root-kind histograms cannot be written with --output-per-project"),
+            #[cfg(feature = "parquet")]
+            Sink::Parquet(_) => anyhow::bail!("per-root-kind histograms cannot be written as Parquet"),
+        }
+        Ok(())
+    }
+
+    fn write_long_chains(&mut self, project: &str, chains: &[LongChain]) -> anyhow::Result<()> {
+        match self {
+            Sink::Csv(file) => output::write_long_chain_csv_rows(file, project, chains)?,
+            Sink::Plot(_) => anyhow::bail!("long chains cannot be written as plot files"),
+            Sink::CsvPerProject(_) => anyhow::bail!("long chains cannot be written with --output-per-project"),
+            #[cfg(feature = "parquet")]
+            Sink::Parquet(_) => anyhow::bail!("long chains cannot be written as Parquet"),
+        }
+        Ok(())
+    }
+
+    // The files of the other sinks only ever hold complete projects.
+    fn mark_interrupted(&mut self) -> anyhow::Result<()> {
+        if let Sink::Csv(file) = self {
+            output::write_interrupted_marker(file)?;
+        }
+        Ok(())
+    }
+
+    fn close(self) -> anyhow::Result<()> {
+        match self {
+            Sink::Csv(file) => { file.finish()?; }
+            Sink::Plot(_) | Sink::CsvPerProject(_) => {}
+            #[cfg(feature = "parquet")]
+            Sink::Parquet(writer) => writer.close()?,
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_cap() {
+        assert_eq!(parse_cap("5"), Ok(5));
+        assert!(parse_cap("0").is_err());
+        assert!(parse_cap("-1").is_err());
+    }
+
+    #[test]
+    fn test_parse_shard() {
+        assert_eq!(parse_shard("2/8"), Ok(Shard { index: 2, total: 8 }));
+        assert!(parse_shard("8/8").is_err());
+        assert!(parse_shard("0/0").is_err());
+        assert!(parse_shard("2").is_err());
+        assert!(parse_shard("a/8").is_err());
+    }
+
+    #[test]
+    fn test_interrupt() {
+        let interrupted = AtomicBool::new(false);
+        assert!(!interrupt(&interrupted));
+        assert!(interrupted.load(Ordering::SeqCst));
+        assert!(interrupt(&interrupted));
+    }
 }
@@ -0,0 +1,578 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::RootKind;
+use crate::corpus::LongChain;
+
+/// Buffers the rows of a text report, flushing every [`ReportWriter::FLUSH_LINES`]
+/// lines so that a long run shows its progress in the file without writing
+/// each row on its own. The `write_*` functions of this module all take one.
+///
+/// Dropping the writer flushes it too, but ignores errors; call
+/// [`ReportWriter::finish`] to know that the report is complete.
+pub struct ReportWriter<W: Write> {
+    writer: BufWriter<W>,
+    /// Lines written since the last flush.
+    lines: usize,
+}
+
+impl<W: Write> ReportWriter<W> {
+    pub const FLUSH_LINES: usize = 10_000;
+
+    pub fn new(writer: W) -> Self {
+        ReportWriter { writer: BufWriter::new(writer), lines: 0 }
+    }
+
+    /// Flushes the last rows and returns the underlying writer.
+    pub fn finish(self) -> std::io::Result<W> {
+        self.writer.into_inner().map_err(|error| error.into_error())
+    }
+}
+
+impl<W: Write> Write for ReportWriter<W> {
+    fn write(&mut self, buffer: &[u8]) -> std::io::Result<usize> {
+        let written = self.writer.write(buffer)?;
+        self.lines += buffer[..written].iter().filter(|byte| **byte == b'\n').count();
+        if self.lines >= Self::FLUSH_LINES {
+            self.writer.flush()?;
+            self.lines = 0;
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.lines = 0;
+        self.writer.flush()
+    }
+}
+
+/// Quotes a field as RFC 4180 does if it contains a comma, a double quote, or
+/// a line break, doubling its quotes, and also if it starts or ends with
+/// whitespace that readers would trim. The `write_*_csv_*` functions pass
+/// every name through it, so that a project directory named `foo, bar` is
+/// written as `"foo, bar"`. They separate the fields with `", "` rather than
+/// `","`, though, so a quoted field after the first follows a space, which
+/// strict RFC 4180 readers take as part of the field.
+pub fn csv_field(field: &str) -> Cow<'_, str> {
+    let needs_quotes = field.contains([',', '"', '\n', '\r'])
+        || field.starts_with(char::is_whitespace)
+        || field.ends_with(char::is_whitespace);
+    if needs_quotes {
+        Cow::Owned(format!("\"{}\"", field.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(field)
+    }
+}
+
+/// The last line of a report cut short by an interrupt, after the rows of the
+/// projects that were complete. [`parse_csv`] skips it.
+pub const INTERRUPTED_MARKER: &str = "# interrupted";
+
+pub fn write_interrupted_marker<W: Write>(writer: &mut W) -> std::io::Result<()> {
+    writeln!(writer, "{}", INTERRUPTED_MARKER)
+}
+
+pub const CSV_HEADER: &str = "project, chain length, frequency";
+
+pub fn write_csv_header<W: Write>(writer: &mut W) -> std::io::Result<()> {
+    writeln!(writer, "{}", CSV_HEADER)
+}
+
+pub fn write_csv_rows<W: Write>(writer: &mut W, project: &str, histogram: &[(usize, usize)]) -> std::io::Result<()> {
+    for (chain_length, frequency) in histogram {
+        writeln!(writer, "{}, {}, {}", csv_field(project), chain_length, frequency)?;
+    }
+    Ok(())
+}
+
+pub const CDF_CSV_HEADER: &str = "project, chain length, cumulative fraction";
+
+pub fn write_cdf_csv_header<W: Write>(writer: &mut W) -> std::io::Result<()> {
+    writeln!(writer, "{}", CDF_CSV_HEADER)
+}
+
+/// Writes the cumulative distribution (see [`crate::histogram::histogram_cdf`])
+/// by chain length, shortest first.
+pub fn write_cdf_csv_rows<W: Write>(writer: &mut W, project: &str, cdf: &BTreeMap<usize, f64>) -> std::io::Result<()> {
+    for (chain_length, fraction) in cdf {
+        writeln!(writer, "{}, {}, {}", csv_field(project), chain_length, fraction)?;
+    }
+    Ok(())
+}
+
+pub const DENSITY_CSV_HEADER: &str = "project, chains per kloc";
+
+pub fn write_density_csv_header<W: Write>(writer: &mut W) -> std::io::Result<()> {
+    writeln!(writer, "{}", DENSITY_CSV_HEADER)
+}
+
+/// Writes the chains per thousand lines of a project, see
+/// [`crate::corpus::ProjectReport::chains_per_kloc`].
+pub fn write_density_csv_row<W: Write>(writer: &mut W, project: &str, chains_per_kloc: f64) -> std::io::Result<()> {
+    writeln!(writer, "{}, {}", csv_field(project), chains_per_kloc)
+}
+
+pub const ENTROPY_CSV_HEADER: &str = "project, entropy";
+
+pub fn write_entropy_csv_header<W: Write>(writer: &mut W) -> std::io::Result<()> {
+    writeln!(writer, "{}", ENTROPY_CSV_HEADER)
+}
+
+/// Writes the entropy of a project's histogram, see
+/// [`crate::histogram::histogram_entropy`].
+pub fn write_entropy_csv_row<W: Write>(writer: &mut W, project: &str, entropy: f64) -> std::io::Result<()> {
+    writeln!(writer, "{}, {}", csv_field(project), entropy)
+}
+
+pub const NESTING_CSV_HEADER: &str = "project, max nesting depth";
+
+pub fn write_nesting_csv_header<W: Write>(writer: &mut W) -> std::io::Result<()> {
+    writeln!(writer, "{}", NESTING_CSV_HEADER)
+}
+
+pub fn write_nesting_csv_row<W: Write>(writer: &mut W, project: &str, max_nesting_depth: usize) -> std::io::Result<()> {
+    writeln!(writer, "{}, {}", csv_field(project), max_nesting_depth)
+}
+
+pub const PACKAGE_CSV_HEADER: &str = "project, package, chain length, frequency";
+
+pub fn write_package_csv_header<W: Write>(writer: &mut W) -> std::io::Result<()> {
+    writeln!(writer, "{}", PACKAGE_CSV_HEADER)
+}
+
+pub fn write_package_csv_rows<W: Write>(writer: &mut W, project: &str, package: &str, histogram: &[(usize, usize)]) -> std::io::Result<()> {
+    for (chain_length, frequency) in histogram {
+        writeln!(writer, "{}, {}, {}, {}", csv_field(project), csv_field(package), chain_length, frequency)?;
+    }
+    Ok(())
+}
+
+pub const EXTENSION_CSV_HEADER: &str = "project, extension, chain length, frequency";
+
+pub fn write_extension_csv_header<W: Write>(writer: &mut W) -> std::io::Result<()> {
+    writeln!(writer, "{}", EXTENSION_CSV_HEADER)
+}
+
+pub fn write_extension_csv_rows<W: Write>(writer: &mut W, project: &str, extension: &str, histogram: &[(usize, usize)]) -> std::io::Result<()> {
+    for (chain_length, frequency) in histogram {
+        writeln!(writer, "{}, {}, {}, {}", csv_field(project), csv_field(extension), chain_length, frequency)?;
+    }
+    Ok(())
+}
+
+pub const ROOT_KIND_CSV_HEADER: &str = "project, root kind, chain length, frequency";
+
+pub fn write_root_kind_csv_header<W: Write>(writer: &mut W) -> std::io::Result<()> {
+    writeln!(writer, "{}", ROOT_KIND_CSV_HEADER)
+}
+
+pub fn write_root_kind_csv_rows<W: Write>(writer: &mut W, project: &str, root_kind: RootKind, histogram: &[(usize, usize)]) -> std::io::Result<()> {
+    for (chain_length, frequency) in histogram {
+        writeln!(writer, "{}, {}, {}, {}", csv_field(project), root_kind.name(), chain_length, frequency)?;
+    }
+    Ok(())
+}
+
+pub const SIZE_CSV_HEADER: &str = "project, files, lines";
+
+pub fn write_size_csv_header<W: Write>(writer: &mut W) -> std::io::Result<()> {
+    writeln!(writer, "{}", SIZE_CSV_HEADER)
+}
+
+pub fn write_size_csv_row<W: Write>(writer: &mut W, project: &str, files: usize, lines: usize) -> std::io::Result<()> {
+    writeln!(writer, "{}, {}, {}", csv_field(project), files, lines)
+}
+
+pub const LONGEST_CHAIN_CSV_HEADER: &str = "project, max chain length, file";
+
+pub fn write_longest_chain_csv_header<W: Write>(writer: &mut W) -> std::io::Result<()> {
+    writeln!(writer, "{}", LONGEST_CHAIN_CSV_HEADER)
+}
+
+pub fn write_longest_chain_csv_row<W: Write>(writer: &mut W, project: &str, max_chain_length: usize, file: &Path) -> std::io::Result<()> {
+    writeln!(writer, "{}, {}, {}", csv_field(project), max_chain_length, csv_field(&file.to_string_lossy()))
+}
+
+pub const LONG_CHAIN_CSV_HEADER: &str = "project, file, start line, length";
+
+pub fn write_long_chain_csv_header<W: Write>(writer: &mut W) -> std::io::Result<()> {
+    writeln!(writer, "{}", LONG_CHAIN_CSV_HEADER)
+}
+
+pub fn write_long_chain_csv_rows<W: Write>(writer: &mut W, project: &str, chains: &[LongChain]) -> std::io::Result<()> {
+    for chain in chains {
+        writeln!(writer, "{}, {}, {}, {}", csv_field(project), csv_field(&chain.file.to_string_lossy()), chain.start_line, chain.length)?;
+    }
+    Ok(())
+}
+
+/// Writes a histogram for plotting with gnuplot or matplotlib: one
+/// `chain_length frequency` line per row, without a header, by chain length.
+pub fn write_plot_rows<W: Write>(writer: &mut W, histogram: &[(usize, usize)]) -> std::io::Result<()> {
+    let mut rows = histogram.to_vec();
+    rows.sort_unstable();
+    for (chain_length, frequency) in rows {
+        writeln!(writer, "{} {}", chain_length, frequency)?;
+    }
+    Ok(())
+}
+
+/// The name of the plot file of `project`, see [`project_file_name`].
+pub fn plot_file_name(project: &str) -> String {
+    project_file_name(project, "dat")
+}
+
+/// The name of the file of `project` in an output directory:
+/// `<project>.<extension>`, with every character but ASCII letters, digits,
+/// `-`, `_`, and `.` replaced by `_`, so that the name cannot leave the
+/// directory. A name changed that way ends in a hash of `project`, so that
+/// `a b` and `a_b` do not share a file.
+pub fn project_file_name(project: &str, extension: &str) -> String {
+    let name = project.chars()
+        .map(|ch| if ch.is_ascii_alphanumeric() || ch == '-' || ch == '_' || ch == '.' { ch } else { '_' })
+        .collect::<String>();
+    let name = if name.is_empty() || name.chars().all(|ch| ch == '.') { name.replace('.', "_") + "_" } else { name };
+    if name == project {
+        format!("{}.{}", name, extension)
+    } else {
+        format!("{}-{:08x}.{}", name, crate::corpus::stable_hash(project) as u32, extension)
+    }
+}
+
+/// Totals of a whole run, to tell whether it is I/O- or CPU-bound.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Summary {
+    pub elapsed: std::time::Duration,
+    pub files: usize,
+    pub bytes: u64,
+}
+
+impl Summary {
+    /// Megabytes (10^6 bytes) analyzed per second.
+    pub fn throughput(&self) -> f64 {
+        let seconds = self.elapsed.as_secs_f64();
+        if seconds == 0.0 { 0.0 } else { self.bytes as f64 / 1e6 / seconds }
+    }
+}
+
+impl std::fmt::Display for Summary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Analyzed {} files ({} bytes) in {:.2}s: {:.2} MB/s",
+               self.files, self.bytes, self.elapsed.as_secs_f64(), self.throughput())
+    }
+}
+
+/// Parses histogram rows written by [`write_csv_rows`]. The header line,
+/// blank lines, and the [`INTERRUPTED_MARKER`] are skipped, but not other
+/// lines starting with `#`, the rows of projects like `#foo`. Project names
+/// may be quoted (see [`csv_field`]), and may also contain commas unquoted,
+/// as in files written before names were quoted.
+pub fn parse_csv(contents: &str) -> anyhow::Result<Vec<(String, usize, usize)>> {
+    contents.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && *line != INTERRUPTED_MARKER)
+        .filter(|(i, line)| !(*i == 0 && line.trim() == CSV_HEADER))
+        .map(|(i, line)| {
+            let mut columns = line.rsplitn(3, ',');
+            let (frequency, chain_length, project) = match (columns.next(), columns.next(), columns.next()) {
+                (Some(frequency), Some(chain_length), Some(project)) => (frequency, chain_length, project),
+                _ => anyhow::bail!("line {}: expected 3 columns: {}", i + 1, line),
+            };
+            let chain_length = chain_length.trim().parse::<usize>()
+                .map_err(|e| anyhow::anyhow!("line {}: invalid chain length: {}", i + 1, e))?;
+            let frequency = frequency.trim().parse::<usize>()
+                .map_err(|e| anyhow::anyhow!("line {}: invalid frequency: {}", i + 1, e))?;
+            Ok((unquote(project.trim()), chain_length, frequency))
+        })
+        .collect()
+}
+
+// The inverse of `csv_field`, leaving fields that are not quoted as they are.
+fn unquote(field: &str) -> String {
+    match field.strip_prefix('"').and_then(|field| field.strip_suffix('"')) {
+        Some(quoted) => quoted.replace("\"\"", "\""),
+        None => field.to_owned(),
+    }
+}
+
+/// Writes histograms as a Parquet file with columns `project: Utf8`,
+/// `chain_length: UInt32`, and `frequency: UInt32`. Each project becomes its
+/// own row group.
+#[cfg(feature = "parquet")]
+pub struct ParquetWriter {
+    writer: parquet::arrow::ArrowWriter<std::fs::File>,
+    schema: std::sync::Arc<arrow_schema::Schema>,
+}
+
+#[cfg(feature = "parquet")]
+impl ParquetWriter {
+    pub fn create(path: &std::path::Path) -> anyhow::Result<Self> {
+        use arrow_schema::{DataType, Field, Schema};
+        let schema = std::sync::Arc::new(Schema::new(vec![
+            Field::new("project", DataType::Utf8, false),
+            Field::new("chain_length", DataType::UInt32, false),
+            Field::new("frequency", DataType::UInt32, false),
+        ]));
+        let file = std::fs::File::create(path)?;
+        let writer = parquet::arrow::ArrowWriter::try_new(file, schema.clone(), None)?;
+        Ok(ParquetWriter { writer, schema })
+    }
+
+    pub fn write_project(&mut self, project: &str, histogram: &[(usize, usize)]) -> anyhow::Result<()> {
+        use arrow_array::{ArrayRef, RecordBatch, StringArray, UInt32Array};
+        use std::convert::TryFrom;
+
+        if histogram.is_empty() {
+            return Ok(());
+        }
+
+        let projects = StringArray::from(vec![project; histogram.len()]);
+        let chain_lengths = histogram.iter()
+            .map(|(chain_length, _)| u32::try_from(*chain_length))
+            .collect::<Result<UInt32Array, _>>()?;
+        let frequencies = histogram.iter()
+            .map(|(_, frequency)| u32::try_from(*frequency))
+            .collect::<Result<UInt32Array, _>>()?;
+
+        let batch = RecordBatch::try_new(self.schema.clone(), vec![
+            std::sync::Arc::new(projects) as ArrayRef,
+            std::sync::Arc::new(chain_lengths) as ArrayRef,
+            std::sync::Arc::new(frequencies) as ArrayRef,
+        ])?;
+        self.writer.write(&batch)?;
+        // One row group per project.
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    pub fn close(self) -> anyhow::Result<()> {
+        self.writer.close()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "parquet")]
+    #[test]
+    fn test_parquet_matches_csv() {
+        use arrow_array::{StringArray, UInt32Array};
+        use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+        let projects = vec![
+            ("lan-chat-app", vec![(7, 1), (6, 2), (1, 205)]),
+            ("Calculator", vec![(2, 8), (1, 416)]),
+        ];
+
+        let mut csv = Vec::new();
+        write_csv_header(&mut csv).unwrap();
+        let path = std::env::temp_dir().join(format!("method-chains-{}.parquet", std::process::id()));
+        let mut parquet = ParquetWriter::create(&path).unwrap();
+        for (project, histogram) in &projects {
+            write_csv_rows(&mut csv, project, histogram).unwrap();
+            parquet.write_project(project, histogram).unwrap();
+        }
+        parquet.close().unwrap();
+
+        let reader = ParquetRecordBatchReaderBuilder::try_new(std::fs::File::open(&path).unwrap()).unwrap();
+        assert_eq!(reader.metadata().num_row_groups(), 2);
+
+        let mut rows = vec![CSV_HEADER.to_owned()];
+        for batch in reader.build().unwrap() {
+            let batch = batch.unwrap();
+            let project = batch.column(0).as_any().downcast_ref::<StringArray>().unwrap();
+            let chain_length = batch.column(1).as_any().downcast_ref::<UInt32Array>().unwrap();
+            let frequency = batch.column(2).as_any().downcast_ref::<UInt32Array>().unwrap();
+            for row in 0..batch.num_rows() {
+                rows.push(format!("{}, {}, {}", project.value(row), chain_length.value(row), frequency.value(row)));
+            }
+        }
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(String::from_utf8(csv).unwrap().lines().collect::<Vec<_>>(), rows);
+    }
+
+    #[test]
+    fn test_csv_field() {
+        assert_eq!(csv_field("Calculator"), "Calculator");
+        assert_eq!(csv_field("lan chat app"), "lan chat app");
+        assert_eq!(csv_field("foo, bar"), "\"foo, bar\"");
+        assert_eq!(csv_field(r#"say "hi""#), r#""say ""hi""""#);
+        assert_eq!(csv_field(" padded"), "\" padded\"");
+
+        let mut csv = Vec::new();
+        write_csv_header(&mut csv).unwrap();
+        write_csv_rows(&mut csv, "foo, bar", &[(2, 1)]).unwrap();
+        assert_eq!(String::from_utf8(csv).unwrap(), "project, chain length, frequency\n\"foo, bar\", 2, 1\n");
+    }
+
+    #[test]
+    fn test_parse_csv() {
+        let contents = "project, chain length, frequency\nCalculator, 2, 8\nfoo, bar, 1, 416\n";
+        assert_eq!(parse_csv(contents).unwrap(), vec![
+            ("Calculator".to_owned(), 2, 8), ("foo, bar".to_owned(), 1, 416),
+        ]);
+        assert_eq!(parse_csv("\"foo, \"\"bar\"\"\", 1, 416\n").unwrap(), vec![("foo, \"bar\"".to_owned(), 1, 416)]);
+        assert!(parse_csv("project, chain length, frequency\nCalculator, two, 8\n").is_err());
+
+        let mut interrupted = contents.as_bytes().to_vec();
+        write_interrupted_marker(&mut interrupted).unwrap();
+        assert_eq!(parse_csv(std::str::from_utf8(&interrupted).unwrap()).unwrap(), parse_csv(contents).unwrap());
+
+        let mut csv = Vec::new();
+        write_csv_header(&mut csv).unwrap();
+        write_csv_rows(&mut csv, "#foo", &[(2, 1)]).unwrap();
+        write_interrupted_marker(&mut csv).unwrap();
+        assert_eq!(parse_csv(std::str::from_utf8(&csv).unwrap()).unwrap(), vec![("#foo".to_owned(), 2, 1)]);
+    }
+
+    #[test]
+    fn test_report_writer() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.csv");
+        let mut writer = ReportWriter::new(std::fs::File::create(&path).unwrap());
+        write_csv_header(&mut writer).unwrap();
+        let histogram = (1..=1000).rev().map(|chain_length| (chain_length, 1)).collect::<Vec<_>>();
+        for project in 0..100 {
+            write_csv_rows(&mut writer, &format!("project-{}", project), &histogram).unwrap();
+        }
+        // Some rows reached the file before the end.
+        assert!(std::fs::metadata(&path).unwrap().len() > 0);
+        writer.finish().unwrap();
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(contents.lines().count(), 1 + 100 * 1000);
+        assert!(contents.ends_with("project-99, 1, 1\n"));
+    }
+
+    #[test]
+    fn test_csv_rows() {
+        let mut csv = Vec::new();
+        write_csv_header(&mut csv).unwrap();
+        write_csv_rows(&mut csv, "Calculator", &[(2, 8), (1, 416)]).unwrap();
+        assert_eq!(String::from_utf8(csv).unwrap(),
+                   "project, chain length, frequency\nCalculator, 2, 8\nCalculator, 1, 416\n");
+    }
+
+    #[test]
+    fn test_package_csv_rows() {
+        let mut csv = Vec::new();
+        write_package_csv_header(&mut csv).unwrap();
+        write_package_csv_rows(&mut csv, "Calculator", "com.houarizegai.calculator", &[(2, 8), (1, 416)]).unwrap();
+        assert_eq!(String::from_utf8(csv).unwrap(),
+                   "project, package, chain length, frequency\n\
+                    Calculator, com.houarizegai.calculator, 2, 8\n\
+                    Calculator, com.houarizegai.calculator, 1, 416\n");
+    }
+
+    #[test]
+    fn test_cdf_csv_rows() {
+        let mut csv = Vec::new();
+        write_cdf_csv_header(&mut csv).unwrap();
+        write_cdf_csv_rows(&mut csv, "Calculator", &BTreeMap::from([(1, 0.75), (3, 1.0)])).unwrap();
+        assert_eq!(String::from_utf8(csv).unwrap(), "project, chain length, cumulative fraction\nCalculator, 1, 0.75\nCalculator, 3, 1\n");
+    }
+
+    #[test]
+    fn test_nesting_csv_rows() {
+        let mut csv = Vec::new();
+        write_nesting_csv_header(&mut csv).unwrap();
+        write_nesting_csv_row(&mut csv, "Calculator", 7).unwrap();
+        assert_eq!(String::from_utf8(csv).unwrap(), "project, max nesting depth\nCalculator, 7\n");
+    }
+
+    #[test]
+    fn test_entropy_csv_rows() {
+        let mut csv = Vec::new();
+        write_entropy_csv_header(&mut csv).unwrap();
+        write_entropy_csv_row(&mut csv, "Calculator", 1.5).unwrap();
+        assert_eq!(String::from_utf8(csv).unwrap(), "project, entropy\nCalculator, 1.5\n");
+    }
+
+    #[test]
+    fn test_density_csv_rows() {
+        let mut csv = Vec::new();
+        write_density_csv_header(&mut csv).unwrap();
+        write_density_csv_row(&mut csv, "Calculator", 12.5).unwrap();
+        write_density_csv_row(&mut csv, "Empty", 0.0).unwrap();
+        assert_eq!(String::from_utf8(csv).unwrap(), "project, chains per kloc\nCalculator, 12.5\nEmpty, 0\n");
+    }
+
+    #[test]
+    fn test_extension_csv_rows() {
+        let mut csv = Vec::new();
+        write_extension_csv_header(&mut csv).unwrap();
+        write_extension_csv_rows(&mut csv, "Calculator", "kt", &[(2, 8)]).unwrap();
+        assert_eq!(String::from_utf8(csv).unwrap(), "project, extension, chain length, frequency\nCalculator, kt, 2, 8\n");
+    }
+
+    #[test]
+    fn test_root_kind_csv_rows() {
+        let mut csv = Vec::new();
+        write_root_kind_csv_header(&mut csv).unwrap();
+        write_root_kind_csv_rows(&mut csv, "Calculator", RootKind::ClassName, &[(3, 2)]).unwrap();
+        assert_eq!(String::from_utf8(csv).unwrap(), "project, root kind, chain length, frequency\nCalculator, class, 3, 2\n");
+    }
+
+    #[test]
+    fn test_summary() {
+        let summary = Summary { elapsed: std::time::Duration::from_millis(500), files: 12, bytes: 3_000_000 };
+        assert_eq!(summary.to_string(), "Analyzed 12 files (3000000 bytes) in 0.50s: 6.00 MB/s");
+        assert_eq!(Summary { elapsed: std::time::Duration::ZERO, ..summary }.throughput(), 0.0);
+    }
+
+    #[test]
+    fn test_size_csv_row() {
+        let mut csv = Vec::new();
+        write_size_csv_header(&mut csv).unwrap();
+        write_size_csv_row(&mut csv, "Calculator", 12, 1034).unwrap();
+        assert_eq!(String::from_utf8(csv).unwrap(), "project, files, lines\nCalculator, 12, 1034\n");
+    }
+
+    #[test]
+    fn test_longest_chain_csv_row() {
+        let mut csv = Vec::new();
+        write_longest_chain_csv_header(&mut csv).unwrap();
+        write_longest_chain_csv_row(&mut csv, "Calculator", 7, Path::new("src/Parser, old.java")).unwrap();
+        assert_eq!(String::from_utf8(csv).unwrap(), "project, max chain length, file\nCalculator, 7, \"src/Parser, old.java\"\n");
+    }
+
+    #[test]
+    fn test_long_chain_csv_rows() {
+        let dir = tempfile::tempdir().unwrap();
+        let project = dir.path().join("Calculator");
+        std::fs::create_dir_all(project.join("src")).unwrap();
+        std::fs::write(project.join("src/A.java"),
+                       "class A {\n  void f() {\n    a().b();\n    builder.c()\n      .d().e();\n  }\n}\n").unwrap();
+
+        let report = crate::corpus::Corpus::new(dir.path()).report_over(Some(2)).run().unwrap();
+        let mut csv = Vec::new();
+        write_long_chain_csv_header(&mut csv).unwrap();
+        for project in &report.projects {
+            write_long_chain_csv_rows(&mut csv, &project.name, &project.long_chains).unwrap();
+        }
+        let row = format!("Calculator, {}, 4, 3", std::path::Path::new("src").join("A.java").display());
+        assert_eq!(String::from_utf8(csv).unwrap(), format!("project, file, start line, length\n{}\n", row));
+    }
+
+    #[test]
+    fn test_plot_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(plot_file_name("lan-chat app/v2"));
+        let mut file = std::fs::File::create(&path).unwrap();
+        write_plot_rows(&mut file, &[(7, 1), (6, 2), (1, 205)]).unwrap();
+        assert_eq!(path.file_name().unwrap(), "lan-chat_app_v2-c3827c07.dat");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "1 205\n6 2\n7 1\n");
+
+        assert_eq!(plot_file_name(".."), "___-b4a03f2d.dat");
+        assert_eq!(plot_file_name(""), "_-84222325.dat");
+        assert_eq!(project_file_name("lan-chat app", "csv"), "lan-chat_app-6485f9fe.csv");
+        assert_eq!(project_file_name("lan-chat_app", "csv"), "lan-chat_app.csv");
+        assert_ne!(project_file_name("a b", "csv"), project_file_name("a_b", "csv"));
+        assert_ne!(project_file_name("a b", "csv"), project_file_name("a.b", "csv"));
+    }
+}
@@ -0,0 +1,154 @@
+//! Chains added by a unified diff, for attributing new chains to the commits
+//! that introduced them.
+
+use std::path::PathBuf;
+
+use anyhow::{bail, Context};
+
+use crate::{ChainConfig, MethodChaining};
+
+/// The chains added to one file of a patch.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PatchFile {
+    /// The path after the change, without the `b/` prefix of git. For a
+    /// deleted file, the path before the change.
+    pub path: PathBuf,
+    pub chain_counts: Vec<usize>,
+}
+
+/// Counts the chains in the lines a unified diff adds, file by file in the
+/// order of the patch. See [`process_diff_with`].
+pub fn process_diff(patch: &str, max_depth: usize) -> anyhow::Result<Vec<PatchFile>> {
+    process_diff_with(patch, max_depth, &ChainConfig::default())
+}
+
+/// Counts the chains in the lines a unified diff adds, file by file in the
+/// order of the patch.
+///
+/// The count is approximate: only the added lines of each hunk are analyzed,
+/// without the context around them. A chain that an added line merely extends
+/// is counted with the added links only, and a hunk that closes a brace opened
+/// in the context has no chains counted after that brace.
+pub fn process_diff_with(patch: &str, max_depth: usize, config: &ChainConfig) -> anyhow::Result<Vec<PatchFile>> {
+    let mut files = Vec::new();
+    let mut old_path = None;
+    let mut added = String::new();
+    // Lines of the current hunk still to come, before and after the change.
+    let mut remaining = (0usize, 0usize);
+
+    let end_hunk = |added: &mut String, files: &mut Vec<PatchFile>| -> anyhow::Result<()> {
+        if let Some(file) = files.last_mut() {
+            file.chain_counts.extend(added.as_str().method_chain_counts_with(max_depth, config)?);
+        }
+        added.clear();
+        Ok(())
+    };
+
+    for (number, line) in patch.lines().enumerate() {
+        if remaining != (0, 0) {
+            let (old, new) = &mut remaining;
+            match line.chars().next() {
+                Some('+') => {
+                    added.push_str(&line[1..]);
+                    added.push('\n');
+                    *new = new.saturating_sub(1);
+                }
+                Some('-') => *old = old.saturating_sub(1),
+                Some('\\') => { /* no newline at end of file */ }
+                _ => {
+                    *old = old.saturating_sub(1);
+                    *new = new.saturating_sub(1);
+                }
+            }
+            if remaining == (0, 0) {
+                end_hunk(&mut added, &mut files)?;
+            }
+        } else if let Some(path) = line.strip_prefix("--- ") {
+            old_path = Some(header_path(path, "a/"));
+        } else if let Some(path) = line.strip_prefix("+++ ") {
+            let path = match header_path(path, "b/") {
+                path if path == "/dev/null" => old_path.take().unwrap_or(path),
+                path => path,
+            };
+            files.push(PatchFile { path: PathBuf::from(path), chain_counts: Vec::new() });
+        } else if line.starts_with("@@") {
+            remaining = hunk_lengths(line)
+                .with_context(|| format!("Invalid hunk header on line {}: {:?}", number + 1, line))?;
+        }
+    }
+    if !added.is_empty() {
+        end_hunk(&mut added, &mut files)?;
+    }
+    Ok(files)
+}
+
+// The path of a `---` or `+++` header, without a trailing timestamp.
+fn header_path(header: &str, prefix: &str) -> String {
+    let path = header.split('\t').next().unwrap_or(header).trim_end();
+    path.strip_prefix(prefix).unwrap_or(path).to_owned()
+}
+
+// The number of lines before and after the change in `@@ -1,5 +1,7 @@`.
+fn hunk_lengths(header: &str) -> anyhow::Result<(usize, usize)> {
+    let mut ranges = header.trim_start_matches('@').split_whitespace();
+    let mut length = |sign: char| -> anyhow::Result<usize> {
+        let range = match ranges.next().and_then(|range| range.strip_prefix(sign)) {
+            Some(range) => range,
+            None => bail!("missing {} range", sign),
+        };
+        Ok(match range.split_once(',') {
+            Some((_, length)) => length.parse()?,
+            None => 1,
+        })
+    };
+    Ok((length('-')?, length('+')?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PATCH: &str = "\
+diff --git a/src/A.java b/src/A.java
+index 1234567..89abcde 100644
+--- a/src/A.java
++++ b/src/A.java
+@@ -1,4 +1,6 @@
+ class A {
+     void f() {
+-        a();
++        list.stream()
++            .map(x -> x.y())
++            .collect(toList());
+     }
+ }
+diff --git a/src/B.java b/src/B.java
+new file mode 100644
+--- /dev/null
++++ b/src/B.java
+@@ -0,0 +1,2 @@
++class B { void g() { b().c(); } }
++++counter;
+diff --git a/src/C.java b/src/C.java
+deleted file mode 100644
+--- a/src/C.java
++++ /dev/null
+@@ -1 +0,0 @@
+-class C { void h() { d().e(); } }
+";
+
+    #[test]
+    fn test_process_diff() {
+        assert_eq!(process_diff(PATCH, 100).unwrap(), vec![
+            PatchFile { path: PathBuf::from("src/A.java"), chain_counts: vec![1, 1, 3] },
+            PatchFile { path: PathBuf::from("src/B.java"), chain_counts: vec![2] },
+            PatchFile { path: PathBuf::from("src/C.java"), chain_counts: vec![] },
+        ]);
+    }
+
+    #[test]
+    fn test_process_diff_invalid_hunk() {
+        let error = process_diff("--- a/A.java\n+++ b/A.java\n@@ nonsense @@\n", 100).unwrap_err();
+        assert!(error.to_string().contains("line 3"), "{}", error);
+    }
+}
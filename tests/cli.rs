@@ -0,0 +1,371 @@
+//! Runs the binary end to end.
+
+use std::path::Path;
+use std::process::Command;
+
+fn write(root: &Path, path: &str, contents: &str) {
+    let path = root.join(path);
+    std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+    std::fs::write(path, contents).unwrap();
+}
+
+#[test]
+fn test_summary() {
+    let dir = tempfile::tempdir().unwrap();
+    write(dir.path(), "projects/alpha/A.java", "class A { void f() { a().b(); } }");
+    write(dir.path(), "projects/alpha/B.java", "class B { void g() { c(); } }");
+    write(dir.path(), "projects/beta/C.java", "class C { void h() { d.e().f(); } }");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_method-chains"))
+        .arg("--project-dir").arg(dir.path().join("projects"))
+        .arg("--output-path").arg(dir.path().join("out.csv"))
+        .args(["--max-recursion-depth", "10"])
+        .env("RUST_LOG", "off")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let summary = stderr.lines().last().unwrap();
+    assert!(summary.starts_with("Analyzed 3 files (97 bytes) in "), "{}", summary);
+    assert!(summary.ends_with(" MB/s"), "{}", summary);
+}
+
+#[test]
+fn test_jobs_do_not_change_output() {
+    let dir = tempfile::tempdir().unwrap();
+    for project in 0..3 {
+        for file in 0..40 {
+            let calls = ".a()".repeat(file % 7 + project);
+            let source = format!("class C{} {{ void f() {{ x{}.y(); z.w(){}; }} }}", file, calls, calls);
+            write(dir.path(), &format!("projects/p{}/C{}.java", project, file), &source);
+        }
+    }
+
+    let run = |jobs: &str, extra: &[&str]| {
+        let out = dir.path().join(format!("out-{}.csv", jobs));
+        let output = Command::new(env!("CARGO_BIN_EXE_method-chains"))
+            .arg("--project-dir").arg(dir.path().join("projects"))
+            .arg("--output-path").arg(&out)
+            .args(["--max-recursion-depth", "10", "--jobs", jobs, "--with-size"])
+            .args(extra)
+            .env("RUST_LOG", "off")
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        let sizes = std::fs::read(out.with_extension("sizes.csv")).unwrap();
+        (std::fs::read(out).unwrap(), sizes)
+    };
+
+    assert_eq!(run("1", &[]), run("8", &[]));
+    assert_eq!(run("1", &["--report-over", "3"]), run("8", &["--report-over", "3"]));
+}
+
+#[test]
+fn test_with_longest_chain() {
+    let dir = tempfile::tempdir().unwrap();
+    write(dir.path(), "projects/alpha/A.java", "class A { void f() { a().b(); } }");
+    write(dir.path(), "projects/alpha/B.java", "class B { void g() { c().d().e(); } }");
+    write(dir.path(), "projects/beta/C.java", "class C { int x; }");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_method-chains"))
+        .arg("--project-dir").arg(dir.path().join("projects"))
+        .arg("--output-path").arg(dir.path().join("out.csv"))
+        .args(["--max-recursion-depth", "10", "--with-longest-chain"])
+        .env("RUST_LOG", "off")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let csv = std::fs::read_to_string(dir.path().join("out.longest.csv")).unwrap();
+    assert_eq!(csv, "project, max chain length, file\nalpha, 3, B.java\n");
+}
+
+#[test]
+fn test_max_allowed() {
+    let dir = tempfile::tempdir().unwrap();
+    write(dir.path(), "projects/alpha/A.java", "class A { void f() { a().b(); } }");
+    write(dir.path(), "projects/alpha/B.java", "class B {\n  void g() {\n    c().d().e().f();\n  }\n}");
+    let run = |max_allowed: &str, extra: &[&str]| Command::new(env!("CARGO_BIN_EXE_method-chains"))
+        .arg("--project-dir").arg(dir.path().join("projects"))
+        .arg("--output-path").arg(dir.path().join("out.csv"))
+        .args(["--max-recursion-depth", "10", "--max-allowed", max_allowed])
+        .args(extra)
+        .env("RUST_LOG", "off")
+        .output()
+        .unwrap();
+
+    let output = run("4", &[]);
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "");
+    let csv = std::fs::read_to_string(dir.path().join("out.csv")).unwrap();
+    assert_eq!(csv, "project, chain length, frequency\nalpha, 4, 1\nalpha, 2, 1\n");
+
+    let output = run("3", &[]);
+    assert_eq!(output.status.code(), Some(1));
+    let path = dir.path().join("projects").join("alpha").join("B.java");
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), format!("{}:3: chain of length 4, more than 3\n", path.display()));
+    assert_eq!(std::fs::read_to_string(dir.path().join("out.csv")).unwrap(), csv);
+
+    // Reported from 1, failing over 3.
+    let output = run("3", &["--report-over", "1"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(String::from_utf8(output.stdout).unwrap().lines().count(), 1);
+    let report = std::fs::read_to_string(dir.path().join("out.csv")).unwrap();
+    assert_eq!(report.lines().count(), 3);
+    let output = run("1", &["--report-over", "3"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(String::from_utf8(output.stdout).unwrap().lines().count(), 2);
+    let report = std::fs::read_to_string(dir.path().join("out.csv")).unwrap();
+    assert_eq!(report.lines().collect::<Vec<_>>(), vec!["project, file, start line, length", "alpha, B.java, 3, 4"]);
+}
+
+#[test]
+fn test_limit_files() {
+    let dir = tempfile::tempdir().unwrap();
+    write(dir.path(), "projects/alpha/A.java", "class A { void f() { a().b(); } }");
+    write(dir.path(), "projects/alpha/B.java", "class B { void g() { c().d().e(); } }");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_method-chains"))
+        .arg("--project-dir").arg(dir.path().join("projects"))
+        .arg("--output-path").arg(dir.path().join("out.csv"))
+        .args(["--max-recursion-depth", "10", "--limit-files", "1"])
+        .env("RUST_LOG", "off")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let csv = std::fs::read_to_string(dir.path().join("out.csv")).unwrap();
+    assert_eq!(csv, "project, chain length, frequency\nalpha, 2, 1\n");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.starts_with("Analyzed 1 files "), "{}", stderr);
+}
+
+#[test]
+fn test_density() {
+    let dir = tempfile::tempdir().unwrap();
+    write(dir.path(), "projects/alpha/A.java", "class A {\n  void f() {\n    a().b();\n    c();\n  }\n}\n");
+    write(dir.path(), "projects/alpha/B.java", "class B {\n  int g = d.e().f();\n}\n");
+    write(dir.path(), "projects/empty/C.java", "");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_method-chains"))
+        .arg("--project-dir").arg(dir.path().join("projects"))
+        .arg("--output-path").arg(dir.path().join("out.csv"))
+        .args(["--max-recursion-depth", "10", "--density"])
+        .env("RUST_LOG", "off")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    // 3 chains in 9 lines.
+    let csv = std::fs::read_to_string(dir.path().join("out.csv")).unwrap();
+    assert_eq!(csv, format!("project, chains per kloc\nalpha, {}\nempty, 0\n", 3000.0 / 9.0));
+}
+
+#[test]
+fn test_min_files() {
+    let dir = tempfile::tempdir().unwrap();
+    write(dir.path(), "projects/alpha/A.java", "class A { void f() { a().b(); } }");
+    write(dir.path(), "projects/alpha/B.java", "class B { void g() { c(); } }");
+    write(dir.path(), "projects/beta/C.java", "class C { void h() { d().e().f(); } }");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_method-chains"))
+        .arg("--project-dir").arg(dir.path().join("projects"))
+        .arg("--output-path").arg(dir.path().join("out.csv"))
+        .args(["--max-recursion-depth", "10", "--min-files", "2"])
+        .env("RUST_LOG", "off")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let csv = std::fs::read_to_string(dir.path().join("out.csv")).unwrap();
+    assert_eq!(csv, "project, chain length, frequency\nalpha, 2, 1\nalpha, 1, 1\n");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.starts_with("Analyzed 2 files "), "{}", stderr);
+}
+
+#[test]
+fn test_quoted_project_name() {
+    let dir = tempfile::tempdir().unwrap();
+    write(dir.path(), "projects/foo, bar/A.java", "class A { void f() { a().b(); } }");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_method-chains"))
+        .arg("--project-dir").arg(dir.path().join("projects"))
+        .arg("--output-path").arg(dir.path().join("out.csv"))
+        .args(["--max-recursion-depth", "10"])
+        .env("RUST_LOG", "off")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let csv = std::fs::read_to_string(dir.path().join("out.csv")).unwrap();
+    assert_eq!(csv, "project, chain length, frequency\n\"foo, bar\", 2, 1\n");
+}
+
+#[test]
+fn test_relative_paths() {
+    let dir = tempfile::tempdir().unwrap();
+    write(dir.path(), "repo/src/A.java", "class A { void f() { a().b(); } }");
+    write(dir.path(), "repo/test/B.java", "class B { void g() { c(); } }");
+    let list = format!("{}\n{}\n", dir.path().join("repo/src/A.java").display(), dir.path().join("repo/test/B.java").display());
+    write(dir.path(), "files.txt", &list);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_method-chains"))
+        .arg("--file-list").arg(dir.path().join("files.txt"))
+        .arg("--output-path").arg(dir.path().join("out.csv"))
+        .args(["--max-recursion-depth", "10", "--group-by", "file", "--relative-paths"])
+        .env("RUST_LOG", "off")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let csv = std::fs::read_to_string(dir.path().join("out.csv")).unwrap();
+    assert_eq!(csv, "project, chain length, frequency\nsrc/A.java, 2, 1\ntest/B.java, 1, 1\n");
+}
+
+#[test]
+fn test_zip() {
+    let dir = tempfile::tempdir().unwrap();
+    let archive = dir.path().join("projects.zip");
+    let mut zip = zip::ZipWriter::new(std::fs::File::create(&archive).unwrap());
+    let options = zip::write::SimpleFileOptions::default();
+    zip.add_directory("alpha/", options).unwrap();
+    for (path, contents) in [
+        ("alpha/A.java", "class A { void f() { a().b(); } }"),
+        ("alpha/notes.txt", "a().b().c().d();"),
+        ("beta/src/B.java", "class B { void g() { c(); d.e().f(); } }"),
+    ] {
+        zip.start_file(path, options).unwrap();
+        std::io::Write::write_all(&mut zip, contents.as_bytes()).unwrap();
+    }
+    zip.finish().unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_method-chains"))
+        .arg("--zip").arg(&archive)
+        .arg("--output-path").arg(dir.path().join("out.csv"))
+        .args(["--max-recursion-depth", "10"])
+        .env("RUST_LOG", "off")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let csv = std::fs::read_to_string(dir.path().join("out.csv")).unwrap();
+    assert_eq!(csv, "project, chain length, frequency\nalpha, 2, 1\nbeta, 2, 1\nbeta, 1, 1\n");
+}
+
+#[test]
+fn test_order_appearance() {
+    let dir = tempfile::tempdir().unwrap();
+    write(dir.path(), "projects/alpha/A.java", "class A { void f() { a().b(); c().d().e(); } }");
+    write(dir.path(), "projects/alpha/B.java", "class B { void g() { f(); g().h(); i().j().k(); } }");
+
+    for (order, expected) in [("sorted", "3, 2\nalpha, 2, 2\nalpha, 1, 1\n"), ("appearance", "2, 2\nalpha, 3, 2\nalpha, 1, 1\n")] {
+        let output = Command::new(env!("CARGO_BIN_EXE_method-chains"))
+            .arg("--project-dir").arg(dir.path().join("projects"))
+            .arg("--output-path").arg(dir.path().join("out.csv"))
+            .args(["--max-recursion-depth", "10", "--order", order])
+            .env("RUST_LOG", "off")
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+
+        let csv = std::fs::read_to_string(dir.path().join("out.csv")).unwrap();
+        assert_eq!(csv, format!("project, chain length, frequency\nalpha, {}", expected));
+    }
+}
+
+#[test]
+fn test_output_per_project() {
+    let dir = tempfile::tempdir().unwrap();
+    write(dir.path(), "projects/alpha/A.java", "class A { void f() { a().b(); } }");
+    write(dir.path(), "projects/beta gamma/B.java", "class B { void g() { c(); d.e(); } }");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_method-chains"))
+        .arg("--project-dir").arg(dir.path().join("projects"))
+        .arg("--output-per-project").arg(dir.path().join("out"))
+        .args(["--max-recursion-depth", "10"])
+        .env("RUST_LOG", "off")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let mut files = std::fs::read_dir(dir.path().join("out")).unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect::<Vec<String>>();
+    files.sort();
+    assert_eq!(files, vec!["alpha.csv", "beta_gamma-d8fa53aa.csv"]);
+    let csv = std::fs::read_to_string(dir.path().join("out/beta_gamma-d8fa53aa.csv")).unwrap();
+    assert_eq!(csv, "project, chain length, frequency\nbeta gamma, 1, 2\n");
+}
+
+#[test]
+fn test_output_per_project_similar_names() {
+    let dir = tempfile::tempdir().unwrap();
+    write(dir.path(), "projects/a b/A.java", "class A { void f() { a().b(); } }");
+    write(dir.path(), "projects/a_b/B.java", "class B { void g() { c(); } }");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_method-chains"))
+        .arg("--project-dir").arg(dir.path().join("projects"))
+        .arg("--output-per-project").arg(dir.path().join("out"))
+        .args(["--max-recursion-depth", "10"])
+        .env("RUST_LOG", "off")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let mut files = std::fs::read_dir(dir.path().join("out")).unwrap()
+        .map(|entry| std::fs::read_to_string(entry.unwrap().path()).unwrap())
+        .collect::<Vec<String>>();
+    files.sort();
+    assert_eq!(files, vec!["project, chain length, frequency\na b, 2, 1\n", "project, chain length, frequency\na_b, 1, 1\n"]);
+}
+
+#[test]
+fn test_compare() {
+    let dir = tempfile::tempdir().unwrap();
+    write(dir.path(), "histograms.csv", "project, chain length, frequency\n\
+                                         alpha, 1, 4\nalpha, 2, 2\nbeta, 1, 2\nbeta, 2, 1\ngamma, 3, 5\n\
+                                         epsilon, 1, 1\nepsilon, 2, 2\nepsilon, 1, 3\n");
+    let compare = |first: &str, second: &str| Command::new(env!("CARGO_BIN_EXE_method-chains"))
+        .arg("compare").arg(dir.path().join("histograms.csv")).args([first, second])
+        .env("RUST_LOG", "off")
+        .output()
+        .unwrap();
+
+    let output = compare("alpha", "alpha");
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "kl divergence: 0\ncosine similarity: 1\n");
+
+    // The rows of a length add up.
+    let output = compare("alpha", "epsilon");
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "kl divergence: 0\ncosine similarity: 1\n");
+
+    let output = compare("alpha", "gamma");
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.ends_with("\ncosine similarity: 0\n"), "{}", stdout);
+    assert!(!stdout.starts_with("kl divergence: 0\n"), "{}", stdout);
+
+    let output = compare("alpha", "delta");
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr).unwrap().contains("No project delta"));
+}
+
+#[test]
+fn test_count() {
+    let dir = tempfile::tempdir().unwrap();
+    write(dir.path(), "projects/alpha/A.java", "class A { void f() { a().b(); } }");
+    write(dir.path(), "projects/alpha/test/ATest.java", "class ATest { void t() { x.y(); } }");
+    write(dir.path(), "projects/beta/C.java", "class C { void h() { d.e().f(); g(); } }");
+    write(dir.path(), "projects/beta/D.kt", "fun k() { h.i() }");
+
+    let output = Command::new(env!("CARGO_BIN_EXE_method-chains"))
+        .arg("count")
+        .arg("--project-dir").arg(dir.path().join("projects"))
+        .args(["--exclude", "*/test/**", "--extensions", "java,kt"])
+        .env("RUST_LOG", "off")
+        .current_dir(dir.path())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "projects: 2\nfiles: 3\nchains: 4\n");
+    assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+}
@@ -0,0 +1,13 @@
+// expected: 4, 1, 2
+class Builder {
+    Person build(String name) {
+        return Person.builder()
+            .name(name)
+            .age(42)
+            .build();
+    }
+
+    void log(Person person) {
+        System.out.println(person.getName().trim());
+    }
+}
@@ -0,0 +1,7 @@
+// expected: 2, 1
+class Indexing {
+    String cell(List<String[]> rows) {
+        String value = rows.get(0)[1].trim();
+        return value.strip();
+    }
+}
@@ -0,0 +1,14 @@
+// expected: 5, 1, 1, 1, 1, 2
+class Streams {
+    List<String> names(List<Person> people) {
+        return people.stream()
+            .filter(person -> person.isAdult())
+            .map(Person::getName)
+            .sorted(Comparator.reverseOrder())
+            .collect(Collectors.toList());
+    }
+
+    String first(String text) {
+        return "a.b().c()".length() > 0 ? text.trim().split(",")[0] : text;
+    }
+}
@@ -0,0 +1,19 @@
+//! Builds the `no_std` crate in `tests/no_std`, which uses this one without
+//! its default features.
+
+use std::path::Path;
+use std::process::Command;
+
+#[test]
+fn test_no_std_build() {
+    let manifest = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/no_std/Cargo.toml");
+    let output = Command::new(env!("CARGO"))
+        .arg("build")
+        .arg("--manifest-path").arg(&manifest)
+        .arg("--target-dir").arg(Path::new(env!("CARGO_TARGET_TMPDIR")).join("no_std"))
+        // The dependencies are among those of this crate, already fetched.
+        .arg("--offline")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+}
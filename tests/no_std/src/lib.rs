@@ -0,0 +1,32 @@
+//! Uses the tokenizer, comment remover, and detector of `method-chains`
+//! without `std`.
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+use method_chains::incremental::IncrementalAnalysis;
+use method_chains::{remove_comments, tokenize, MethodChaining};
+
+pub fn token_count(source: &str) -> usize {
+    tokenize(&remove_comments(source)).len()
+}
+
+pub fn chain_counts(source: &str) -> anyhow::Result<Vec<usize>> {
+    source.method_chain_counts(10)
+}
+
+pub fn chain_histogram(source: &str) -> anyhow::Result<BTreeMap<usize, usize>> {
+    source.method_chain_histogram(10)
+}
+
+pub fn chunked_chain_counts<'a>(chunks: impl IntoIterator<Item = &'a str>) -> anyhow::Result<Vec<usize>> {
+    let mut analysis = IncrementalAnalysis::new(10);
+    for chunk in chunks {
+        analysis.push_str(chunk)?;
+    }
+    analysis.finish()
+}